@@ -0,0 +1,81 @@
+//! Offline target annotations: an inventory file mapping CIDR ranges to labels (business unit,
+//! cloud provider, owner, ...). Lookups are longest-prefix match; nothing touches the network.
+//!
+//! Format, one mapping per line (`#` starts a comment):
+//!
+//! ```text
+//! 10.0.0.0/8        corp
+//! 10.20.0.0/16      corp-payments
+//! 2001:db8::/32     lab v6
+//! ```
+
+use anyhow::{anyhow, Context, Result};
+use ipnet::IpNet;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Default)]
+pub struct Annotations {
+    // Network address (as an integer) -> label, per prefix length; longest lengths are tried first
+    v4: BTreeMap<u8, HashMap<u128, String>>,
+    v6: BTreeMap<u8, HashMap<u128, String>>,
+}
+
+impl Annotations {
+    pub fn load(path: &Path) -> Result<Self> {
+        let src = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read annotation file {}", path.display()))?;
+        Self::parse(&src).with_context(|| format!("invalid annotation file {}", path.display()))
+    }
+
+    pub fn parse(src: &str) -> Result<Self> {
+        let mut out = Self::default();
+        for (idx, raw) in src.lines().enumerate() {
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let lineno = idx + 1;
+            let (cidr, label) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| anyhow!("line {lineno}: expected `<cidr> <label>`"))?;
+            // A bare address is a host route
+            let net = IpNet::from_str(cidr)
+                .or_else(|_| IpAddr::from_str(cidr).map(IpNet::from))
+                .map_err(|_| anyhow!("line {lineno}: invalid CIDR `{cidr}`"))?;
+            let table = match net {
+                IpNet::V4(_) => &mut out.v4,
+                IpNet::V6(_) => &mut out.v6,
+            };
+            // Later lines win for an identical prefix
+            table
+                .entry(net.prefix_len())
+                .or_default()
+                .insert(addr_bits(net.network()), label.trim().to_string());
+        }
+        Ok(out)
+    }
+
+    /// Label of the most specific range containing `ip`.
+    pub fn lookup(&self, ip: IpAddr) -> Option<&str> {
+        let (table, width) = match ip {
+            IpAddr::V4(_) => (&self.v4, 32u32),
+            IpAddr::V6(_) => (&self.v6, 128u32),
+        };
+        let bits = addr_bits(ip);
+        table.iter().rev().find_map(|(&len, nets)| {
+            let host_bits = width - u32::from(len);
+            let network = if host_bits >= 128 { 0 } else { bits >> host_bits << host_bits };
+            nets.get(&network).map(String::as_str)
+        })
+    }
+}
+
+fn addr_bits(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(v4) => u128::from(u32::from(v4)),
+        IpAddr::V6(v6) => u128::from(v6),
+    }
+}
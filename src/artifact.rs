@@ -0,0 +1,191 @@
+//! The save file: results are staged while the scan runs, then written as one JSON document,
+//! appended as NDJSON (`--append`), or kept as the `--stream-save` stream.
+
+use crate::cli::Cli;
+use crate::output::pretty_json;
+use crate::sink::ResultSink;
+use anyhow::Result;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// Rebuild the `{"results":[...]}` artifact from NDJSON lines and make it durable at `path`.
+fn write_artifact<R: BufRead>(reader: R, path: &Path, config: &str, pretty: bool) -> Result<()> {
+    let file = File::create(path)?;
+    let mut out = BufWriter::new(file);
+    // Pretty output needs the whole document; compact output streams straight to the file
+    let mut doc = String::new();
+    let mut emit = |fragment: &str| -> io::Result<()> {
+        if pretty {
+            doc.push_str(fragment);
+            Ok(())
+        } else {
+            out.write_all(fragment.as_bytes())
+        }
+    };
+    emit(&format!("{{\"config\":{},\"results\":[", config))?;
+    let mut first = true;
+    for line_res in reader.lines() {
+        let line = line_res?;
+        if line.is_empty() { continue; }
+        if !first { emit(",")?; }
+        first = false;
+        // Each line is already a serialized ScanResult JSON object
+        emit(&line)?;
+    }
+    emit("]}")?;
+    if pretty {
+        writeln!(&mut out, "{}", pretty_json(&doc))?;
+    }
+    out.flush()?;
+    out.get_ref().sync_all()?;
+    Ok(())
+}
+
+// Where results are staged until the save file is written: a temp file, or memory (--no-temp-file).
+pub enum Staging {
+    File(BufWriter<File>),
+    Memory(Vec<u8>),
+}
+
+impl Staging {
+    // Flush and rewind for reading; the file handle is kept rather than reopened by path.
+    pub fn into_reader(self) -> Result<Box<dyn BufRead>> {
+        match self {
+            Staging::File(w) => {
+                let mut file = w.into_inner().map_err(|e| e.into_error())?;
+                file.seek(SeekFrom::Start(0))?;
+                Ok(Box::new(BufReader::new(file)))
+            }
+            Staging::Memory(buf) => Ok(Box::new(io::Cursor::new(buf))),
+        }
+    }
+}
+
+impl Write for Staging {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Staging::File(w) => w.write(buf),
+            Staging::Memory(v) => v.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Staging::File(w) => w.flush(),
+            Staging::Memory(_) => Ok(()),
+        }
+    }
+}
+
+// Randomized staging file in `dir`, created with O_EXCL semantics to avoid clobber/symlink issues.
+pub fn create_staging_file(dir: &Path) -> io::Result<(PathBuf, File)> {
+    let mut last_err = io::Error::other("no attempt made");
+    // Attempt a few times to avoid extremely unlikely name collisions
+    for attempt in 0..3u8 {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        let pid = std::process::id();
+        let path = dir.join(format!("ospine.{pid}.{nanos}.{attempt}.ndjson.tmp"));
+        match OpenOptions::new().read(true).write(true).create_new(true).open(&path) {
+            Ok(f) => return Ok((path, f)),
+            Err(e) => last_err = e,
+        }
+    }
+    Err(last_err)
+}
+
+// One NDJSON result for the live outputs: appended to the --stream-save file and flushed, so a
+// watcher tailing it sees the line now, and queued for the --sink (sent on its next flush)
+pub fn live_write(
+    stream_writer: &mut Option<BufWriter<File>>,
+    sink: &mut Option<ResultSink>,
+    line: String,
+    fsync: bool,
+) -> Result<()> {
+    if let Some(w) = stream_writer.as_mut() {
+        writeln!(w, "{}", line)?;
+        w.flush()?;
+        if fsync {
+            w.get_ref().sync_data()?;
+        }
+    }
+    if let Some(s) = sink.as_mut() {
+        s.push(line);
+    }
+    Ok(())
+}
+
+// Flush and durably sync a --stream-save writer once the scan is done.
+fn finish_stream(mut w: BufWriter<File>) -> Result<()> {
+    w.flush()?;
+    w.get_ref().sync_all()?;
+    Ok(())
+}
+
+// Append NDJSON lines to `path` (created if absent) so several runs can share one log;
+// each record carries its own scan id and timestamp.
+fn append_artifact<R: BufRead>(reader: R, path: &Path) -> Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut out = BufWriter::new(file);
+    for line_res in reader.lines() {
+        let line = line_res?;
+        if line.is_empty() { continue; }
+        writeln!(&mut out, "{}", line)?;
+    }
+    out.flush()?;
+    out.get_ref().sync_all()?;
+    Ok(())
+}
+
+// Turn the staged NDJSON into the save file (single document, append, or the finished stream),
+// then drop the staging file.
+pub fn finalize_artifact(
+    staging: Staging,
+    tmp_path: Option<&Path>,
+    stream_writer: Option<BufWriter<File>>,
+    cli: &Cli,
+    config_json: &str,
+) -> Result<()> {
+    let save_file = cli.save_file.as_str();
+    let reader = staging.into_reader()?;
+
+    // Stage the artifact next to its destination and rename it into place only after a
+    // successful flush, so a crash mid-write never clobbers the previous good artifact.
+    let staged_path = PathBuf::from(format!("{}.tmp", save_file));
+    let finalized = if let Some(w) = stream_writer {
+        // The streamed NDJSON already holds every result; rewriting it as one document is opt-in
+        finish_stream(w).and_then(|_| {
+            if !cli.finalize_array {
+                return Ok(());
+            }
+            write_artifact(reader, &staged_path, config_json, cli.json_pretty)
+                .and_then(|_| std::fs::rename(&staged_path, save_file).map_err(Into::into))
+        })
+    } else if cli.append {
+        append_artifact(reader, Path::new(save_file))
+    } else {
+        write_artifact(reader, &staged_path, config_json, cli.json_pretty)
+            .and_then(|_| std::fs::rename(&staged_path, save_file).map_err(Into::into))
+    };
+    if let Err(e) = finalized {
+        if !cli.append {
+            let _ = std::fs::remove_file(&staged_path);
+        }
+        // Keep the NDJSON source so the results of this run are not lost
+        if let Some(path) = tmp_path {
+            eprintln!("error: failed to write {}: {}; raw results kept in {}", save_file, e, path.display());
+        }
+        return Err(e);
+    }
+
+    // Remove the temporary file
+    if let Some(path) = tmp_path {
+        if let Err(e) = std::fs::remove_file(path) {
+            eprintln!("warning: failed to remove temp file {}: {}", path.display(), e);
+        }
+    }
+    Ok(())
+}
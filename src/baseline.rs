@@ -0,0 +1,114 @@
+//! Earlier scans read back: `--baseline` compares each result with the same (target, port) in one,
+//! and `--rescan-open` scans the ports it found open.
+
+use anyhow::Result;
+use ospine::ScanResult;
+use serde::Deserialize;
+use std::path::Path;
+
+/// Difference of a result from the same (target, port) in a baseline scan.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// Open now; closed or absent in the baseline
+    NewOpen,
+    /// Open in the baseline, not any more
+    NowClosed,
+    /// Open in both, with a different banner
+    ChangedBanner,
+    Unchanged,
+}
+
+impl Change {
+    /// Compare a result with its baseline state (`None` when the baseline lacks the port).
+    pub fn between(baseline: Option<(bool, Option<&str>)>, now: &ScanResult) -> Self {
+        match (baseline, now.open) {
+            (Some((true, banner)), true) if !same_banner(banner, now.banner.as_deref()) => Change::ChangedBanner,
+            (Some((true, _)), true) => Change::Unchanged,
+            (_, true) => Change::NewOpen,
+            (Some((true, _)), false) => Change::NowClosed,
+            (_, false) => Change::Unchanged,
+        }
+    }
+}
+
+// Banner equality ignoring `Date:` header lines, which change on every HTTP response.
+fn same_banner(a: Option<&str>, b: Option<&str>) -> bool {
+    let stable = |s: &str| -> Vec<String> {
+        s.split('\n')
+            .filter(|line| !line.to_ascii_lowercase().starts_with("date:"))
+            .map(str::to_string)
+            .collect()
+    };
+    match (a, b) {
+        (Some(a), Some(b)) => stable(a) == stable(b),
+        (a, b) => a == b,
+    }
+}
+
+impl Change {
+    /// The form stored in `ScanResult.change`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Change::NewOpen => "new-open",
+            Change::NowClosed => "now-closed",
+            Change::ChangedBanner => "changed-banner",
+            Change::Unchanged => "unchanged",
+        }
+    }
+}
+
+// The fields of a prior result that --rescan-open and --baseline need; anything else in the line is ignored.
+#[derive(Deserialize)]
+pub struct PriorResult {
+    pub target: String,
+    pub port: u16,
+    pub open: bool,
+    #[serde(default)]
+    pub banner: Option<String>,
+    #[serde(default)]
+    pub resolved_addr: Option<String>,
+}
+
+// Results of a prior artifact. NDJSON (--append, --stream-save) is read line by line; a single
+// JSON document is read from its "results". Malformed records are skipped with a warning.
+pub fn load_prior_results(path: &Path) -> Result<Vec<PriorResult>> {
+    let src = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+    #[derive(Deserialize)]
+    struct Artifact {
+        results: Vec<serde_json::Value>,
+    }
+    let records: Vec<serde_json::Value> = match serde_json::from_str::<Artifact>(&src) {
+        Ok(doc) => doc.results,
+        Err(_) => src
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| serde_json::from_str(l).unwrap_or(serde_json::Value::Null))
+            .collect(),
+    };
+    let mut malformed = 0usize;
+    let mut results = Vec::new();
+    for record in records {
+        match serde_json::from_value::<PriorResult>(record) {
+            Ok(r) => results.push(r),
+            Err(_) => malformed += 1,
+        }
+    }
+    if malformed > 0 {
+        eprintln!("warning: skipped {} malformed line(s) in {}", malformed, path.display());
+    }
+    Ok(results)
+}
+
+// --rescan-open: (target, port) of every open result in a prior artifact.
+pub fn load_open_endpoints(path: &Path) -> Result<Vec<(String, Vec<u16>)>> {
+    let pairs: Vec<(String, Vec<u16>)> = load_prior_results(path)?
+        .into_iter()
+        .filter(|r| r.open)
+        .map(|r| (r.target, vec![r.port]))
+        .collect();
+    if pairs.is_empty() {
+        anyhow::bail!("no open ports found in {}", path.display());
+    }
+    Ok(pairs)
+}
@@ -0,0 +1,513 @@
+//! Fluent construction of a `ScanConfig` for library users, with the binary's defaults. The shared
+//! connection cap, rate limiter and pacing are created at `build()` unless supplied, so several
+//! configs can share them by passing the same `Arc`s.
+
+use crate::proxy::HttpProxy;
+use crate::service_probes::ProbeSet;
+use crate::types::{new_scan_id, ByteBudget, HttpMethod, PortSpec, RateLimiter, ReadStrategy, ScanConfig, ScanDelay, SourcePorts};
+use anyhow::{bail, Result};
+use encoding_rs::Encoding;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+/// Builder for `ScanConfig` (see `ScanConfig::builder`).
+#[derive(Clone, Debug)]
+pub struct ScanConfigBuilder {
+    scan_id: Option<String>,
+    target: String,
+    host: Option<String>,
+    scope_id: Option<u32>,
+    no_dns: bool,
+    label: Option<String>,
+    resolved_addr: Option<String>,
+    ports: Vec<u16>,
+    concurrency: usize,
+    per_host_connections: Option<usize>,
+    timeout: Duration,
+    total_timeout: Option<Duration>,
+    max_host_timeouts: Option<usize>,
+    stop_after_open: Option<usize>,
+    rtt_multiplier: Option<f64>,
+    banner_read_len: usize,
+    read_strategy: ReadStrategy,
+    banner_max_chars: Option<usize>,
+    banner_hex: bool,
+    banner_encoding: Option<&'static Encoding>,
+    passive: bool,
+    connect_only: bool,
+    no_banner_ports: Vec<u16>,
+    reprobe_unknown: u32,
+    two_phase: bool,
+    verify_open: Option<Duration>,
+    max_probes: Option<usize>,
+    os_hint: bool,
+    starttls: bool,
+    verbose: bool,
+    rate: u64,
+    max_connections: usize,
+    global_limit: Option<Arc<Semaphore>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    scan_delay: Option<Arc<ScanDelay>>,
+    probes: Option<Arc<ProbeSet>>,
+    http_method: HttpMethod,
+    http_path: String,
+    http_user_agent: String,
+    http_host: Option<String>,
+    ws_probe: bool,
+    follow_redirect: bool,
+    capture_dir: Option<PathBuf>,
+    proxy: Option<Arc<HttpProxy>>,
+    source_ports: Option<Arc<SourcePorts>>,
+    byte_budget: Option<Arc<ByteBudget>>,
+}
+
+impl Default for ScanConfigBuilder {
+    fn default() -> Self {
+        Self {
+            scan_id: None,
+            target: String::new(),
+            host: None,
+            scope_id: None,
+            no_dns: false,
+            label: None,
+            resolved_addr: None,
+            ports: (1..=1024).collect(),
+            concurrency: 100,
+            per_host_connections: None,
+            timeout: Duration::from_millis(1000),
+            total_timeout: None,
+            max_host_timeouts: None,
+            stop_after_open: None,
+            rtt_multiplier: None,
+            banner_read_len: 512,
+            read_strategy: ReadStrategy::Single,
+            banner_max_chars: None,
+            banner_hex: false,
+            banner_encoding: None,
+            passive: false,
+            connect_only: false,
+            no_banner_ports: Vec::new(),
+            reprobe_unknown: 0,
+            two_phase: false,
+            verify_open: None,
+            max_probes: None,
+            os_hint: false,
+            starttls: false,
+            verbose: false,
+            rate: 5_000,
+            max_connections: 10_000,
+            global_limit: None,
+            rate_limiter: None,
+            scan_delay: None,
+            probes: None,
+            http_method: HttpMethod::Head,
+            http_path: "/".to_string(),
+            http_user_agent: "ospine".to_string(),
+            http_host: None,
+            ws_probe: false,
+            follow_redirect: false,
+            capture_dir: None,
+            proxy: None,
+            source_ports: None,
+            byte_budget: None,
+        }
+    }
+}
+
+impl ScanConfig {
+    /// Start a builder with the same defaults as the command line (ports 1-1024, concurrency 100,
+    /// 1s timeout, 5000 connects/s, 10000 connections).
+    pub fn builder() -> ScanConfigBuilder {
+        ScanConfigBuilder::default()
+    }
+
+    /// Whether `port` is reported from the connect alone, without banner grab or probes.
+    pub fn skips_banner(&self, port: u16) -> bool {
+        self.connect_only || self.no_banner_ports.binary_search(&port).is_ok()
+    }
+}
+
+impl ScanConfigBuilder {
+    /// Host name or address to scan, as reported in results; also the connect address unless `host` is set.
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = target.into();
+        self
+    }
+
+    /// Address to connect to when it differs from `target` (e.g. punycode of an IDN).
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// IPv6 scope id for link-local targets.
+    pub fn scope_id(mut self, scope_id: u32) -> Self {
+        self.scope_id = Some(scope_id);
+        self
+    }
+
+    /// Refuse hostnames: `host` (or `target`) must be an IP address and is never resolved.
+    pub fn no_dns(mut self, on: bool) -> Self {
+        self.no_dns = on;
+        self
+    }
+
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Which of the target's addresses `host` is, reported in results.
+    pub fn resolved_addr(mut self, addr: impl Into<String>) -> Self {
+        self.resolved_addr = Some(addr.into());
+        self
+    }
+
+    /// Identifier copied into every result; a fresh one is generated otherwise.
+    pub fn scan_id(mut self, scan_id: impl Into<String>) -> Self {
+        self.scan_id = Some(scan_id.into());
+        self
+    }
+
+    /// Ports to scan (see `parse_ports` for `--ports` style specs).
+    pub fn ports(mut self, ports: impl Into<Vec<u16>>) -> Self {
+        self.ports = ports.into();
+        self
+    }
+
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    pub fn per_host_connections(mut self, limit: usize) -> Self {
+        self.per_host_connections = Some(limit);
+        self
+    }
+
+    /// Connect timeout, and banner/probe timeout unless `rtt_multiplier` raises it.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Stop trying a target's ports once this many of its connects have timed out; the rest are
+    /// reported filtered.
+    pub fn max_host_timeouts(mut self, max: usize) -> Self {
+        self.max_host_timeouts = Some(max);
+        self
+    }
+
+    /// Stop scanning a target once this many of its ports were found open; the ports not yet
+    /// reported are dropped without a result.
+    pub fn stop_after_open(mut self, open: usize) -> Self {
+        self.stop_after_open = Some(open);
+        self
+    }
+
+    /// Upper bound on one port's connect plus all of its probes.
+    pub fn total_timeout(mut self, budget: Duration) -> Self {
+        self.total_timeout = Some(budget);
+        self
+    }
+
+    pub fn rtt_multiplier(mut self, multiplier: f64) -> Self {
+        self.rtt_multiplier = Some(multiplier);
+        self
+    }
+
+    /// Bytes read for a banner or probe reply (1 to 16384).
+    pub fn banner_read_len(mut self, len: usize) -> Self {
+        self.banner_read_len = len;
+        self
+    }
+
+    pub fn read_strategy(mut self, strategy: ReadStrategy) -> Self {
+        self.read_strategy = strategy;
+        self
+    }
+
+    pub fn banner_max_chars(mut self, max_chars: usize) -> Self {
+        self.banner_max_chars = Some(max_chars);
+        self
+    }
+
+    pub fn banner_hex(mut self, on: bool) -> Self {
+        self.banner_hex = on;
+        self
+    }
+
+    /// Decode banners with `encoding` instead of lossy UTF-8 (e.g. `encoding_rs::WINDOWS_1252`).
+    pub fn banner_encoding(mut self, encoding: &'static Encoding) -> Self {
+        self.banner_encoding = Some(encoding);
+        self
+    }
+
+    /// Reconnect up to `times` times to an open port that stayed silent and unidentified, reading
+    /// until idle and waiting longer on each attempt.
+    pub fn reprobe_unknown(mut self, times: u32) -> Self {
+        self.reprobe_unknown = times;
+        self
+    }
+
+    pub fn passive(mut self, on: bool) -> Self {
+        self.passive = on;
+        self
+    }
+
+    pub fn connect_only(mut self, on: bool) -> Self {
+        self.connect_only = on;
+        self
+    }
+
+    /// Ports to treat as with `connect_only` while the rest are probed (fragile devices, e.g. printers on 9100).
+    pub fn no_banner_ports(mut self, ports: impl IntoIterator<Item = u16>) -> Self {
+        self.no_banner_ports = ports.into_iter().collect();
+        self
+    }
+
+    pub fn two_phase(mut self, on: bool) -> Self {
+        self.two_phase = on;
+        self
+    }
+
+    /// Hold new connections for `grace` and report the ones the peer closes silently as hollow.
+    pub fn verify_open(mut self, grace: Duration) -> Self {
+        self.verify_open = Some(grace);
+        self
+    }
+
+    /// Active probes tried per open port, likeliest first.
+    pub fn max_probes(mut self, max: usize) -> Self {
+        self.max_probes = Some(max);
+        self
+    }
+
+    /// Guess each open port's OS family from the handshake's TCP options (Linux only).
+    pub fn os_hint(mut self, on: bool) -> Self {
+        self.os_hint = on;
+        self
+    }
+
+    pub fn starttls(mut self, on: bool) -> Self {
+        self.starttls = on;
+        self
+    }
+
+    pub fn verbose(mut self, on: bool) -> Self {
+        self.verbose = on;
+        self
+    }
+
+    /// Connection attempts per second; ignored when a `rate_limiter` is supplied.
+    pub fn rate(mut self, per_sec: u64) -> Self {
+        self.rate = per_sec;
+        self
+    }
+
+    /// Cap on in-flight connections; ignored when a `global_limit` is supplied.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = max;
+        self
+    }
+
+    /// Connection cap shared with other configs.
+    pub fn global_limit(mut self, limit: Arc<Semaphore>) -> Self {
+        self.global_limit = Some(limit);
+        self
+    }
+
+    /// Rate limiter shared with other configs.
+    pub fn rate_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Local ports to connect from, in rotation; may be shared with other configs. Not usable with a proxy.
+    pub fn source_ports(mut self, pool: Arc<SourcePorts>) -> Self {
+        self.source_ports = Some(pool);
+        self
+    }
+
+    /// Cap on banner bytes read in total; may be shared with other configs so it spans them all.
+    pub fn byte_budget(mut self, budget: Arc<ByteBudget>) -> Self {
+        self.byte_budget = Some(budget);
+        self
+    }
+
+    /// Per-attempt delay and jitter; none by default.
+    pub fn scan_delay(mut self, delay: Arc<ScanDelay>) -> Self {
+        self.scan_delay = Some(delay);
+        self
+    }
+
+    /// Data-driven probes; the built-in set by default.
+    pub fn probes(mut self, probes: Arc<ProbeSet>) -> Self {
+        self.probes = Some(probes);
+        self
+    }
+
+    pub fn http_method(mut self, method: HttpMethod) -> Self {
+        self.http_method = method;
+        self
+    }
+
+    pub fn http_path(mut self, path: impl Into<String>) -> Self {
+        self.http_path = path.into();
+        self
+    }
+
+    pub fn http_user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.http_user_agent = user_agent.into();
+        self
+    }
+
+    pub fn http_host(mut self, host: impl Into<String>) -> Self {
+        self.http_host = Some(host.into());
+        self
+    }
+
+    pub fn ws_probe(mut self, on: bool) -> Self {
+        self.ws_probe = on;
+        self
+    }
+
+    /// Follow one HTTP redirect (3xx with a Location on the same host), over TLS for https:// URLs.
+    pub fn follow_redirect(mut self, on: bool) -> Self {
+        self.follow_redirect = on;
+        self
+    }
+
+    pub fn capture_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.capture_dir = Some(dir.into());
+        self
+    }
+
+    /// Reach every port through this HTTP proxy (CONNECT).
+    pub fn proxy(mut self, proxy: impl Into<Arc<HttpProxy>>) -> Self {
+        self.proxy = Some(proxy.into());
+        self
+    }
+
+    /// Check the settings and create the shared limits that were not supplied.
+    pub fn build(self) -> Result<ScanConfig> {
+        if self.target.trim().is_empty() {
+            bail!("target is required");
+        }
+        if self.ports.is_empty() {
+            bail!("at least one port is required");
+        }
+        let host = self.host.as_deref().unwrap_or(&self.target);
+        if self.no_dns && host.parse::<IpAddr>().is_err() {
+            bail!("'{}' is not an IP address; hostnames cannot be used without DNS", host);
+        }
+        if self.no_dns && self.proxy.as_ref().is_some_and(|p| p.host().parse::<IpAddr>().is_err()) {
+            bail!("the proxy must be given as an IP address when DNS is disabled");
+        }
+        if let Some(pool) = &self.source_ports {
+            if pool.is_empty() || self.proxy.is_some() {
+                bail!("source ports need at least one port and cannot be combined with a proxy");
+            }
+        }
+        if self.concurrency == 0 || self.per_host_connections == Some(0) {
+            bail!("concurrency and per-host connections must be at least 1");
+        }
+        if self.timeout.is_zero() || self.total_timeout.is_some_and(|t| t.is_zero()) {
+            bail!("timeout must be non-zero");
+        }
+        if self.verify_open.is_some_and(|grace| grace.is_zero()) {
+            bail!("verify-open grace period must be non-zero");
+        }
+        if self.rate == 0 || self.max_connections == 0 {
+            bail!("rate and max connections must be at least 1");
+        }
+        if !(1..=16384).contains(&self.banner_read_len) {
+            bail!("banner read length must be between 1 and 16384 bytes");
+        }
+        if self.max_host_timeouts == Some(0) {
+            bail!("max host timeouts must be at least 1");
+        }
+        if self.stop_after_open == Some(0) {
+            bail!("stop after open must be at least 1");
+        }
+        if self.max_probes == Some(0) {
+            bail!("max probes must be at least 1");
+        }
+        if self.banner_max_chars == Some(0) {
+            bail!("banner max chars must be at least 1");
+        }
+        if matches!(self.rtt_multiplier, Some(f) if !(1.0..=1000.0).contains(&f)) {
+            bail!("rtt multiplier must be between 1 and 1000");
+        }
+        if matches!(self.read_strategy, ReadStrategy::UntilIdle(idle) if idle.is_zero()) {
+            bail!("idle timeout must be non-zero");
+        }
+        if !self.http_path.starts_with('/') || self.http_path.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            bail!("http path must start with '/' and contain no whitespace or control characters");
+        }
+        if self.passive && (self.connect_only || self.starttls || self.ws_probe || self.follow_redirect) {
+            bail!("passive mode cannot be combined with connect-only, STARTTLS, WebSocket probing or redirect following");
+        }
+
+        let mut ports = self.ports;
+        ports.sort_unstable();
+        ports.dedup();
+        Ok(ScanConfig {
+            scan_id: self.scan_id.unwrap_or_else(new_scan_id),
+            host: self.host.unwrap_or_else(|| self.target.clone()),
+            target: self.target,
+            scope_id: self.scope_id,
+            no_dns: self.no_dns,
+            host_addrs: None,
+            label: self.label,
+            resolved_addr: self.resolved_addr,
+            port_spec: PortSpec::List(ports),
+            concurrency: self.concurrency,
+            per_host_connections: self.per_host_connections,
+            timeout: self.timeout,
+            total_timeout: self.total_timeout,
+            max_host_timeouts: self.max_host_timeouts,
+            stop_after_open: self.stop_after_open,
+            rtt_multiplier: self.rtt_multiplier,
+            banner_read_len: self.banner_read_len,
+            read_strategy: self.read_strategy,
+            banner_max_chars: self.banner_max_chars,
+            banner_hex: self.banner_hex,
+            banner_encoding: self.banner_encoding,
+            passive: self.passive,
+            connect_only: self.connect_only,
+            reprobe_unknown: self.reprobe_unknown,
+            no_banner_ports: {
+                let mut ports = self.no_banner_ports;
+                ports.sort_unstable();
+                ports.dedup();
+                ports.into()
+            },
+            two_phase: self.two_phase,
+            verify_open: self.verify_open,
+            max_probes: self.max_probes,
+            os_hint: self.os_hint,
+            starttls: self.starttls,
+            verbose: self.verbose,
+            global_limit: self.global_limit.unwrap_or_else(|| Arc::new(Semaphore::new(self.max_connections))),
+            rate_limiter: self.rate_limiter.unwrap_or_else(|| Arc::new(RateLimiter::new(self.rate))),
+            scan_delay: self
+                .scan_delay
+                .unwrap_or_else(|| Arc::new(ScanDelay::new(Duration::ZERO, Duration::ZERO, None))),
+            probes: self.probes.unwrap_or_else(|| Arc::new(ProbeSet::builtin())),
+            http_method: self.http_method,
+            http_path: self.http_path,
+            http_user_agent: self.http_user_agent,
+            http_host: self.http_host,
+            ws_probe: self.ws_probe,
+            follow_redirect: self.follow_redirect,
+            capture_dir: self.capture_dir,
+            proxy: self.proxy,
+            source_ports: self.source_ports,
+            byte_budget: self.byte_budget,
+        })
+    }
+}
@@ -0,0 +1,125 @@
+//! Raw traffic capture for `--capture-dir`: the connection handed to protocol detection records
+//! every byte it sends and receives, saved per (target, port) as `<target>_<port>.send` / `.recv`
+//! so detection logic can be replayed and improved offline.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// What a `ProbeStream` wraps: a TCP connection, or an in-memory stream in tests.
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// A connected stream used for banner grabbing and probes; records traffic when capturing.
+pub struct ProbeStream {
+    inner: Box<dyn Connection>,
+    capture: Option<Capture>,
+    // The first bytes received, kept so a probe sequence cut short still has a banner
+    head: Vec<u8>,
+    head_len: usize,
+}
+
+struct Capture {
+    base: PathBuf,
+    sent: Vec<u8>,
+    received: Vec<u8>,
+}
+
+impl ProbeStream {
+    /// Wrap `inner`; with `capture_dir` set, traffic is recorded for `target:port`. The first
+    /// `head_len` bytes received are always kept (see `received_head`).
+    pub fn new(
+        inner: impl Connection + 'static,
+        capture_dir: Option<&Path>,
+        target: &str,
+        port: u16,
+        head_len: usize,
+    ) -> Self {
+        let capture = capture_dir.map(|dir| Capture {
+            base: dir.join(format!("{}_{}", sanitize(target), port)),
+            sent: Vec::new(),
+            received: Vec::new(),
+        });
+        Self { inner: Box::new(inner), capture, head: Vec::new(), head_len }
+    }
+
+    /// Capture to `<target>_<port>.reprobe<n>.send` / `.recv` instead, so a fresh connection to the
+    /// same port (`--reprobe-unknown`) does not overwrite the first one's files.
+    pub fn reprobe(mut self, n: u32) -> Self {
+        if let Some(c) = self.capture.as_mut() {
+            let mut base = c.base.clone().into_os_string();
+            base.push(format!(".reprobe{}", n));
+            c.base = base.into();
+        }
+        self
+    }
+
+    /// The first bytes the peer sent, up to `head_len`, whichever probe drew them.
+    pub fn received_head(&self) -> &[u8] {
+        &self.head
+    }
+
+    /// Write the recorded bytes (if capturing) to `<base>.send` and `<base>.recv`.
+    pub fn save_capture(&self) -> io::Result<()> {
+        let Some(c) = &self.capture else {
+            return Ok(());
+        };
+        std::fs::write(c.base.with_extension(extension(&c.base, "send")), &c.sent)?;
+        std::fs::write(c.base.with_extension(extension(&c.base, "recv")), &c.received)
+    }
+}
+
+impl AsyncRead for ProbeStream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let this = &mut *self;
+        let res = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = &res {
+            let received = &buf.filled()[before..];
+            let room = this.head_len.saturating_sub(this.head.len());
+            this.head.extend_from_slice(&received[..received.len().min(room)]);
+            if let Some(c) = this.capture.as_mut() {
+                c.received.extend_from_slice(received);
+            }
+        }
+        res
+    }
+}
+
+impl AsyncWrite for ProbeStream {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let res = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let (Poll::Ready(Ok(n)), Some(c)) = (&res, this.capture.as_mut()) {
+            c.sent.extend_from_slice(&buf[..*n]);
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// File-system safe form of a target name: anything but [A-Za-z0-9.-] becomes '_'.
+fn sanitize(target: &str) -> String {
+    target
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect()
+}
+
+// `with_extension` would replace the last dotted segment of an IP ("10.0.0.1_443"); append instead.
+fn extension(base: &Path, ext: &str) -> String {
+    match base.extension() {
+        Some(existing) => format!("{}.{}", existing.to_string_lossy(), ext),
+        None => ext.to_string(),
+    }
+}
@@ -0,0 +1,751 @@
+//! Command-line options, and what they resolve to: the port set, the "config" object of JSON
+//! output and artifacts, and a command line that reproduces the run.
+
+use anyhow::Result;
+use clap::{ArgAction, CommandFactory, Parser};
+use encoding_rs::Encoding;
+use ospine::{parse_ports, HttpMethod, HttpProxy};
+use serde::Serialize;
+use std::path::PathBuf;
+
+// Build-time version: Major.Minor.Patch.Build
+pub const APP_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), ".", env!("APP_BUILD"));
+
+// Popular TCP ports list used by --popular flag
+const POPULAR_PORTS: &[u16] = &[
+    20,21,22,23,25,53,67,68,69,80,110,111,123,135,137,138,139,143,161,162,443,445,500,514,520,631,993,995,1434,1723,1900,3306,3389,4500,5900,8080,49152,
+];
+
+// Port set scanned when none of --ports, --popular, --profile or --port-group is given
+const DEFAULT_PORTS: &str = "1-1024";
+// Named port profiles used by --profile
+const PORT_PROFILES: &[(&str, &[u16])] = &[
+    ("web", &[80,81,443,591,3000,5000,8000,8008,8080,8081,8443,8888,9000,9443]),
+    ("db", &[1433,1521,3306,5432,5984,6379,7000,7199,8086,9042,9200,9300,11211,27017,27018,28015]),
+    ("windows", &[88,135,137,138,139,389,445,464,593,636,3268,3269,3389,5985,5986,9389,47001]),
+    ("mail", &[25,110,143,465,587,993,995]),
+    ("remote", &[22,23,3389,5900,5985,5986]),
+];
+
+// Serialized (with the effective port set) as the "config" object of JSON output and artifacts
+#[derive(Parser, Debug, Serialize)]
+#[command(name = "ospine", version = APP_VERSION, about = "Open Source Port Interrogation & Network Enumeration")] 
+pub struct Cli {
+    /// Target (IP, hostname, or CIDR range; IPv6 may carry a %zone, e.g. fe80::1%eth0)
+    #[arg(required_unless_present_any = ["classify_file", "endpoints_file", "rescan_open", "show_config"])]
+    pub target: Option<String>,
+
+    /// Scan exactly the `host port` / `host:port` pairs listed in FILE instead of <target> × ports
+    #[arg(long = "endpoints-file", value_name = "FILE", conflicts_with_all = ["target", "ports", "popular", "profile"])]
+    pub endpoints_file: Option<PathBuf>,
+
+    /// Re-scan only the ports reported open in a prior NDJSON (or JSON) artifact, to verify findings
+    #[arg(
+        long = "rescan-open",
+        value_name = "FILE",
+        conflicts_with_all = ["target", "ports", "popular", "profile", "endpoints_file"]
+    )]
+    pub rescan_open: Option<PathBuf>,
+
+    /// Ports to scan (e.g. 80,443,8000-8100,ssh). Comma-separated list of ports, ranges and
+    /// service names (looked up in /etc/services, case-insensitively).
+    /// Defaults to 1-1024 unless --popular, --profile or --port-group is given
+    #[arg(short, long)]
+    pub ports: Option<String>,
+
+    /// Named port profile(s) to scan: web, db, windows, mail, remote (comma-separated or repeated).
+    /// Combined with --ports when both are given
+    #[arg(long = "profile", value_delimiter = ',')]
+    pub profile: Vec<String>,
+
+    /// Named port group NAME=SPEC (repeatable): its ports are scanned too, and results on them
+    /// carry `group` (e.g. --port-group web=80,443 --port-group db=3306,5432)
+    #[arg(long = "port-group", value_name = "NAME=SPEC")]
+    pub port_group: Vec<String>,
+
+    /// Scan only popular ports (overrides --ports when set)
+    #[arg(short = 'P', long = "popular", action = ArgAction::SetTrue)]
+    pub popular: bool,
+
+    /// Maximum number of hosts a CIDR target may expand to
+    #[arg(long = "max-hosts", default_value_t = 100_000_usize)]
+    pub max_hosts: usize,
+
+    /// IPv6 CIDRs with at least this prefix length are expanded fully
+    #[arg(long = "ipv6-expand-prefix", default_value_t = 120, value_parser = clap::value_parser!(u8).range(0..=128))]
+    pub ipv6_expand_prefix: u8,
+
+    /// Allow wider IPv6 CIDRs, scanning only their first --max-hosts addresses
+    #[arg(long = "allow-large-ipv6", action = ArgAction::SetTrue)]
+    pub allow_large_ipv6: bool,
+
+    /// Max concurrent port scans per target
+    #[arg(short = 'c', long, default_value_t = 100, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=1_000_000))]
+    pub concurrency: usize,
+
+    /// Max simultaneous connections to any one host [default: --concurrency]
+    #[arg(long = "per-host-connections", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=1_000_000))]
+    pub per_host_connections: Option<usize>,
+
+    /// Max targets scanned simultaneously (each with up to --concurrency ports in flight)
+    #[arg(long = "target-concurrency", default_value_t = 1_000_usize, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=1_000_000))]
+    pub target_concurrency: usize,
+
+    /// Report targets in input order rather than as each finishes; a target that completes early is
+    /// held until every one before it has been reported
+    #[arg(long = "ordered-output", action = ArgAction::SetTrue)]
+    pub ordered_output: bool,
+
+    /// Per-port timeout milliseconds (1 to 600000)
+    #[arg(short = 't', long, default_value_t = 1000, value_parser = clap::value_parser!(u64).range(1..=600_000))]
+    pub timeout_ms: u64,
+
+    /// Cap on each port's whole connect and probe sequence in milliseconds; when it runs out
+    /// mid-probe, the port is reported open with whatever banner arrived so far
+    #[arg(long = "total-timeout", value_name = "MS", value_parser = clap::value_parser!(u64).range(1..=3_600_000))]
+    pub total_timeout_ms: Option<u64>,
+
+    /// Adaptive probe timeouts: banner/probe waits become max(--timeout-ms, connect RTT × F), up to 60s
+    #[arg(long = "rtt-multiplier", value_name = "F", value_parser = parse_rtt_multiplier)]
+    pub rtt_multiplier: Option<f64>,
+
+    /// Bytes to read for banner/probe
+    #[arg(
+        short = 'b',
+        long,
+        default_value_t = 512_u32,
+        value_parser = clap::value_parser!(u32).range(1..=16384),
+    )]
+    pub banner_bytes: u32,
+
+    /// Keep reading banners and probe replies until the peer goes quiet (or --banner-bytes is reached)
+    /// instead of taking the first read, for services that send multi-packet banners
+    #[arg(long = "read-until-idle", action = ArgAction::SetTrue)]
+    pub read_until_idle: bool,
+
+    /// With --read-until-idle, the pause in milliseconds that ends a banner
+    #[arg(
+        long = "idle-timeout-ms",
+        default_value_t = 200_u64,
+        requires = "read_until_idle",
+        value_parser = clap::value_parser!(u64).range(1..=60_000)
+    )]
+    pub idle_timeout_ms: u64,
+
+    /// Max characters of a banner to store/display; longer banners are cut with a truncation marker.
+    /// Reading (and detection) still uses --banner-bytes
+    #[arg(long = "banner-max-chars", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=1_000_000))]
+    pub banner_max_chars: Option<usize>,
+
+    /// Store banners as the hex encoding of every received byte (with --banner-max-chars: of the first N bytes)
+    #[arg(long = "banner-hex", visible_alias = "banner-hex-always", action = ArgAction::SetTrue)]
+    pub banner_hex: bool,
+
+    /// Decode banners with this character encoding (WHATWG label, e.g. windows-1252, gbk, shift_jis) instead of UTF-8
+    #[arg(long = "banner-encoding", value_name = "LABEL", value_parser = parse_banner_encoding)]
+    pub banner_encoding: Option<String>,
+
+    /// Stop reading banners once the whole run has read N bytes of banners and probe replies; later open ports are reported without one
+    #[arg(long = "max-total-bytes", value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_total_bytes: Option<u64>,
+
+    /// Passive mode: do not send any probe data; only perform passive banner reads
+    #[arg(long = "passive", action = ArgAction::SetTrue)]
+    pub passive: bool,
+
+    /// After detecting plaintext SMTP, IMAP, POP3 or FTP, try to upgrade the connection with STARTTLS
+    #[arg(long = "starttls", action = ArgAction::SetTrue, conflicts_with = "passive")]
+    pub starttls: bool,
+
+    /// Send the HTTP probe as a WebSocket upgrade (GET) and report whether the server switches protocols
+    #[arg(long = "ws-probe", action = ArgAction::SetTrue, conflicts_with = "passive")]
+    pub ws_probe: bool,
+
+    /// Follow one HTTP redirect on the same host (over TLS for https://) and report the final status and server
+    #[arg(long = "follow-redirect", action = ArgAction::SetTrue, conflicts_with = "passive")]
+    pub follow_redirect: bool,
+
+    /// Connect-only mode: report open ports without reading banners or probing
+    #[arg(long = "no-banner", visible_alias = "connect-only", action = ArgAction::SetTrue, conflicts_with = "passive")]
+    pub no_banner: bool,
+
+    /// Ports to connect-check only, never read from or probed (fragile services, e.g. printers on 9100); same syntax as --ports
+    #[arg(long = "no-banner-ports", value_name = "LIST", conflicts_with = "no_banner")]
+    pub no_banner_ports: Option<String>,
+
+    /// Local ports to connect from, in rotation (same syntax as --ports); connections wait when every port is in use
+    #[arg(long = "source-ports", value_name = "LIST", conflicts_with = "proxy")]
+    pub source_ports: Option<String>,
+
+    /// Reconnect up to N times to open ports that stayed silent and unidentified, reading until idle and waiting longer each time (for services slow to send a banner)
+    #[arg(long = "reprobe-unknown", value_name = "N", conflicts_with = "no_banner", value_parser = clap::value_parser!(u32).range(1..=10))]
+    pub reprobe_unknown: Option<u32>,
+
+    /// Add a coarse OS family guess from the TCP options of each open port's handshake (Linux only)
+    #[arg(long = "os-hint", action = ArgAction::SetTrue)]
+    pub os_hint: bool,
+
+    /// Try at most N active probes per open port, likeliest first (1 = only the probe the port suggests)
+    #[arg(long = "max-probes", visible_alias = "max-banner-probes", value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=1_000))]
+    pub max_probes: Option<usize>,
+
+    /// Two-phase scan: connect-only sweep of every port first, then banner/probe only the open ones
+    #[arg(long = "two-phase", visible_alias = "probe-only-open", action = ArgAction::SetTrue, conflicts_with = "no_banner")]
+    pub two_phase: bool,
+
+    /// Hold each new connection for a grace period first; ports whose peer closes it without sending
+    /// anything (accept-only load balancers) are reported with reason "open-but-hollow" and not probed
+    #[arg(long = "verify-open", action = ArgAction::SetTrue)]
+    pub verify_open: bool,
+
+    /// With --verify-open, how long in milliseconds a connection must survive
+    #[arg(
+        long = "verify-grace-ms",
+        default_value_t = 500_u64,
+        requires = "verify_open",
+        value_parser = clap::value_parser!(u64).range(1..=60_000)
+    )]
+    pub verify_grace_ms: u64,
+
+    /// HTTP probe method; GET also reads the body to extract the page <title>
+    #[arg(long = "http-method", value_enum, ignore_case = true, default_value_t = HttpMethod::Head)]
+    pub http_method: HttpMethod,
+
+    /// Path requested by the HTTP probe
+    #[arg(long = "http-path", default_value = "/", value_parser = parse_http_path)]
+    pub http_path: String,
+
+    /// User-Agent header sent by the HTTP probe
+    #[arg(long = "http-user-agent", default_value = "ospine", value_parser = parse_header_value)]
+    pub http_user_agent: String,
+
+    /// Host header sent by the HTTP probe (default: the target host, plus the port when not 80)
+    #[arg(long = "http-host", value_parser = parse_header_value)]
+    pub http_host: Option<String>,
+
+    /// Load service probe definitions from this file instead of the built-in set
+    #[arg(long = "probe-file")]
+    pub probe_file: Option<PathBuf>,
+
+    /// Label results using an offline CIDR-to-label file (longest prefix wins; IP targets only)
+    #[arg(long = "annotate-file")]
+    pub annotate_file: Option<PathBuf>,
+
+    /// Classify raw bytes from FILE (e.g. a --capture-dir .recv file) offline and exit; no network
+    #[arg(long = "classify-file", value_name = "FILE")]
+    pub classify_file: Option<PathBuf>,
+
+    /// Port hint for --classify-file (default: taken from a `<target>_<port>.recv` file name)
+    #[arg(long = "classify-port", value_name = "PORT", requires = "classify_file")]
+    pub classify_port: Option<u16>,
+
+    /// Record the raw bytes sent and received per port to <DIR>/<target>_<port>.send/.recv
+    #[arg(long = "capture-dir", value_name = "DIR")]
+    pub capture_dir: Option<PathBuf>,
+
+    /// Reach every port through this HTTP proxy with CONNECT (http://[user:pass@]host[:port]);
+    /// defaults to HTTPS_PROXY/HTTP_PROXY, honoring NO_PROXY
+    #[arg(long = "proxy", value_name = "URL")]
+    pub proxy: Option<String>,
+
+    /// Ignore HTTPS_PROXY/HTTP_PROXY and connect directly
+    #[arg(long = "no-proxy", action = ArgAction::SetTrue, conflicts_with = "proxy")]
+    pub no_proxy: bool,
+
+    /// JSON output
+    #[arg(short = 'j', long, action = ArgAction::SetTrue)]
+    pub json: bool,
+
+    /// JSON output nested by host: {"hosts": {"<target>": {"ports": {"<port>": {...}}}}}; implies --json
+    #[arg(long = "json-grouped", action = ArgAction::SetTrue)]
+    pub json_grouped: bool,
+
+    /// Indented JSON on stdout and in the save file; implies --json. Buffers the whole document
+    /// until the scan ends instead of streaming it
+    #[arg(long = "json-pretty", action = ArgAction::SetTrue)]
+    pub json_pretty: bool,
+
+    /// Hold the JSON document and print it whole when the scan ends; implies --json. Streamed JSON
+    /// is cut off mid-array if the scan is killed, this prints nothing then, at the cost of no
+    /// output until the end and every result held in memory
+    #[arg(long = "json-safe", action = ArgAction::SetTrue)]
+    pub json_safe: bool,
+
+    /// Print the command line that reproduces this scan, every effective setting spelled out, to
+    /// stderr (and as "command" in JSON output)
+    #[arg(long = "emit-command", action = ArgAction::SetTrue)]
+    pub emit_command: bool,
+
+    /// Print the effective configuration (options as resolved, defaults included) as JSON and exit
+    /// without scanning; <target> may be left out
+    #[arg(long = "show-config", action = ArgAction::SetTrue)]
+    pub show_config: bool,
+
+    /// Output only open ports (filters out closed/timeouts); applies to the console, --xml and the saved artifact
+    #[arg(short = 'o', long = "open-only", action = ArgAction::SetTrue)]
+    pub open_only: bool,
+
+    /// Save only open ports to the artifact (save file, --stream-save), while the console shows every result
+    #[arg(
+        long = "artifact-open-only",
+        visible_alias = "exclude-closed-from-artifact",
+        action = ArgAction::SetTrue,
+        conflicts_with = "no_artifact"
+    )]
+    pub artifact_open_only: bool,
+
+    /// Output only open ports that sent a banner or were identified as a known protocol
+    #[arg(long = "only-responsive", action = ArgAction::SetTrue)]
+    pub only_responsive: bool,
+
+    /// Compare with a prior artifact and mark each result new-open, now-closed, changed-banner or unchanged
+    #[arg(long = "baseline", value_name = "FILE")]
+    pub baseline: Option<PathBuf>,
+
+    /// With --baseline, output only results that changed
+    #[arg(long = "changes-only", action = ArgAction::SetTrue, requires = "baseline")]
+    pub changes_only: bool,
+
+    /// Output only ports identified as one of these protocols (comma-separated, e.g. ssh,http)
+    #[arg(long = "protocol", visible_alias = "only-ports-with-protocol", value_name = "NAMES", value_delimiter = ',')]
+    pub protocol: Vec<String>,
+
+    /// Log probe failures to stderr (whether a probe could not be sent or got no reply)
+    #[arg(short = 'v', long = "verbose", action = ArgAction::SetTrue)]
+    pub verbose: bool,
+
+    /// Print the summary as one JSON object on the last line of stderr, whatever the output format
+    #[arg(long = "summary-json", action = ArgAction::SetTrue)]
+    pub summary_json: bool,
+
+    /// Sort human-readable output by this key (buffers all results until the scan ends)
+    #[arg(long = "sort-by", value_enum)]
+    pub sort_by: Option<SortKey>,
+
+    /// Show results as a grid, hosts by ports (● open, ○ closed, · filtered), once the scan ends;
+    /// falls back to the list when the grid would be too large
+    #[arg(
+        long = "matrix",
+        visible_alias = "target-ports-matrix",
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["json", "json_grouped", "json_pretty", "json_safe", "count_only", "sort_by"]
+    )]
+    pub matrix: bool,
+
+    /// Reverse the --sort-by order
+    #[arg(long = "sort-desc", action = ArgAction::SetTrue, requires = "sort_by")]
+    pub sort_desc: bool,
+
+    /// Print a summary with a census of detected protocols at the end of the scan
+    #[arg(long = "summary", action = ArgAction::SetTrue)]
+    pub summary: bool,
+
+    /// Print only the end-of-scan summary, as one JSON object with --json; results still go to the save file
+    #[arg(long = "count-only", action = ArgAction::SetTrue)]
+    pub count_only: bool,
+
+    /// Add the N hosts with the highest median connect latency to the summary (implies --summary)
+    #[arg(long = "report-slowest", value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=1_000_000))]
+    pub report_slowest: Option<usize>,
+
+    /// Show why each port is in its state (syn-ack, conn-refused, no-response, ...) in human output
+    #[arg(long = "reason", action = ArgAction::SetTrue)]
+    pub reason: bool,
+
+    /// Show raw banner text (human-readable mode only)
+    #[arg(short = 'r', long = "raw-banner", action = ArgAction::SetTrue)]
+    pub raw_banner: bool,
+
+    /// Circuit breaker: once a target has had N connect timeouts, report its remaining ports
+    /// filtered without trying them
+    #[arg(
+        long = "max-host-timeouts",
+        visible_alias = "max-retries-total",
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=1_000_000)
+    )]
+    pub max_host_timeouts: Option<usize>,
+
+    /// Stop scanning a target once N of its ports were found open; its remaining ports are not
+    /// tried or reported (for "does this host run anything?" sweeps)
+    #[arg(
+        long = "stop-after-open",
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=65_535)
+    )]
+    pub stop_after_open: Option<usize>,
+
+    /// Report a single "host appears down/filtered" note when every scanned port on a target timed out
+    #[arg(long = "detect-dead-hosts", action = ArgAction::SetTrue)]
+    pub detect_dead_hosts: bool,
+
+    /// With --detect-dead-hosts, drop the per-port results of dead hosts from output and the save file
+    #[arg(long = "suppress-dead-ports", action = ArgAction::SetTrue, requires = "detect_dead_hosts")]
+    pub suppress_dead_ports: bool,
+
+    /// Note hosts that report almost every scanned port open (likely tarpits or honeypots)
+    #[arg(long = "detect-tarpit", action = ArgAction::SetTrue)]
+    pub detect_tarpit: bool,
+
+    /// With --detect-tarpit, drop the open-port results of likely tarpits from output and the save file
+    #[arg(long = "suppress-tarpit", action = ArgAction::SetTrue, requires = "detect_tarpit")]
+    pub suppress_tarpit: bool,
+
+    /// Save completed JSON artifact to this file when the scan finishes
+    #[arg(short = 's', long = "save-file", default_value = "last_scan.output")]
+    pub save_file: String,
+
+    /// Append results to the save file as NDJSON instead of rewriting it as a single document
+    #[arg(long = "append", action = ArgAction::SetTrue)]
+    pub append: bool,
+
+    /// Write each result to the save file as NDJSON as soon as its port completes (for tailing); with
+    /// --suppress-dead-ports or --suppress-tarpit, once its target completes
+    #[arg(long = "stream-save", action = ArgAction::SetTrue)]
+    pub stream_save: bool,
+
+    /// With --stream-save, fsync the save file after every result written
+    #[arg(long = "stream-fsync", action = ArgAction::SetTrue, requires = "stream_save")]
+    pub stream_fsync: bool,
+
+    /// With --stream-save, rewrite the save file as a single JSON document when the scan finishes
+    #[arg(long = "finalize-array", action = ArgAction::SetTrue, requires = "stream_save", conflicts_with = "append")]
+    pub finalize_array: bool,
+
+    /// Also stream each result as an NDJSON line to a collector as its target completes:
+    /// tcp://host:port or unix:/path
+    #[arg(long = "sink", value_name = "URL")]
+    pub sink: Option<String>,
+
+    /// Do not write a save file at all (results go to stdout only)
+    #[arg(
+        long = "no-artifact",
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["save_file", "append", "stream_save", "no_temp_file"]
+    )]
+    pub no_artifact: bool,
+
+    /// Stage results in memory instead of a temp file (for read-only temp and save directories)
+    #[arg(long = "no-temp-file", action = ArgAction::SetTrue)]
+    pub no_temp_file: bool,
+
+    /// Also write an nmap-style XML report to this file
+    #[arg(long = "xml")]
+    pub xml: Option<PathBuf>,
+
+    /// Global cap on in-flight TCP connections across all targets
+    /// [default: 10000, lowered to 80% of the open file limit when that is smaller]
+    #[arg(long = "max-connections", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=1_000_000))]
+    pub max_connections: Option<usize>,
+
+    /// Global rate limit for connection attempts per second
+    #[arg(long = "rate", default_value_t = 5_000_u64, value_parser = clap::value_parser!(u64).range(1..=10_000_000))]
+    pub rate: u64,
+
+    /// Base delay in milliseconds before each connection attempt
+    #[arg(long = "scan-delay", default_value_t = 0)]
+    pub scan_delay_ms: u64,
+
+    /// Maximum random jitter in milliseconds added to --scan-delay for each attempt
+    #[arg(long = "max-jitter", default_value_t = 0)]
+    pub max_jitter_ms: u64,
+
+    /// Pause in milliseconds before launching each target after the first; with --target-concurrency > 1
+    /// targets still overlap, they just start at least this far apart
+    #[arg(long = "inter-target-delay", visible_alias = "wait-between-targets", value_name = "MS", default_value_t = 0)]
+    pub inter_target_delay_ms: u64,
+
+    /// Resolve hostname targets to every A/AAAA address and scan each one (results carry `resolved_addr`)
+    #[arg(long = "all-addresses", action = ArgAction::SetTrue)]
+    pub all_addresses: bool,
+
+    /// Never resolve names: every target must be an IP address or CIDR, and hostnames are an error
+    #[arg(long = "no-dns", action = ArgAction::SetTrue, conflicts_with = "all_addresses")]
+    pub no_dns: bool,
+
+    /// Scan the ports found open most often in earlier scans first, learning from this one
+    /// (counts kept in ~/.cache/ospine/port-stats)
+    #[arg(long = "smart-order", visible_alias = "ports-weight-by-openness", action = ArgAction::SetTrue)]
+    pub smart_order: bool,
+
+    /// Scan ports from highest to lowest instead of lowest first; output order is unchanged
+    #[arg(long = "reverse-ports", action = ArgAction::SetTrue, conflicts_with = "smart_order")]
+    pub reverse_ports: bool,
+
+    /// Shuffle the expanded target list so adjacent hosts are not scanned back to back
+    #[arg(long = "randomize-targets", action = ArgAction::SetTrue)]
+    pub randomize_targets: bool,
+
+    /// Seed for the random number generator (makes jitter and target order reproducible)
+    #[arg(long = "seed")]
+    pub seed: Option<u64>,
+}
+
+// Request-target for the HTTP probe: must be a single token, and is made absolute.
+fn parse_http_path(s: &str) -> Result<String, String> {
+    if s.is_empty() || s.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err("path must be non-empty and contain no whitespace or control characters".into());
+    }
+    Ok(if s.starts_with('/') { s.to_string() } else { format!("/{}", s) })
+}
+
+// Finite and between 1 and 1000; below 1 a probe could not wait even one round trip.
+fn parse_rtt_multiplier(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(f) if (1.0..=1000.0).contains(&f) => Ok(f),
+        _ => Err("multiplier must be a number from 1 to 1000".into()),
+    }
+}
+
+// A WHATWG encoding label, stored as the encoding's canonical name. The replacement encoding
+// (labels such as iso-2022-kr) would turn every banner into one U+FFFD, so it is refused.
+fn parse_banner_encoding(s: &str) -> Result<String, String> {
+    Encoding::for_label_no_replacement(s.trim().as_bytes())
+        .map(|e| e.name().to_string())
+        .ok_or_else(|| format!("unknown encoding '{}' (expected a label such as utf-8, windows-1252, gbk or shift_jis)", s))
+}
+
+// The encoding `parse_banner_encoding` accepted.
+pub fn banner_encoding(cli: &Cli) -> &'static Encoding {
+    cli.banner_encoding.as_deref().and_then(|name| Encoding::for_label(name.as_bytes())).unwrap_or(encoding_rs::UTF_8)
+}
+
+// HTTP header values must stay on one line so they cannot inject extra headers.
+fn parse_header_value(s: &str) -> Result<String, String> {
+    if s.trim().is_empty() || s.chars().any(|c| c.is_control()) {
+        return Err("value must be non-empty and contain no control characters".into());
+    }
+    Ok(s.to_string())
+}
+
+// Compact form of a sorted port list, e.g. "22,80,8000-8100".
+fn format_port_ranges(ports: &[u16]) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < ports.len() {
+        let start = ports[i];
+        let mut end = start;
+        while i + 1 < ports.len() && ports[i + 1] == end.wrapping_add(1) && end < u16::MAX {
+            i += 1;
+            end = ports[i];
+        }
+        parts.push(if start == end { start.to_string() } else { format!("{}-{}", start, end) });
+        i += 1;
+    }
+    parts.join(",")
+}
+
+// The "config" object: every option as parsed, with the port set, connection cap and proxy as
+// resolved. The proxy is reduced to its address, so credentials in its URL stay out of artifacts.
+pub fn effective_config(cli: &Cli, ports: &[u16], max_connections: usize, proxy: Option<&HttpProxy>) -> Result<serde_json::Value> {
+    let mut config = serde_json::to_value(cli)?;
+    // With --endpoints-file/--rescan-open every target has its own port list, recorded in its results only
+    config["ports"] = if cli.endpoints_file.is_some() || cli.rescan_open.is_some() {
+        serde_json::Value::Null
+    } else {
+        serde_json::Value::String(format_port_ranges(ports))
+    };
+    config["max_connections"] = serde_json::Value::from(max_connections);
+    config["proxy"] = proxy.map_or(serde_json::Value::Null, |p| serde_json::Value::from(p.address()));
+    Ok(config)
+}
+
+// --emit-command: `settings` (the "config" object) back as arguments, in declaration order with
+// the target last. Where the port set was resolved, it replaces the options it came from. Defaults
+// are spelled out too, except where clap would reject them (conflicts, or options that require one
+// not given).
+pub fn reproduce_command(settings: &serde_json::Value) -> String {
+    let cmd = Cli::command();
+    let resolved_ports = settings["ports"].is_string();
+    // (arg, its words, whether they only restate the default)
+    let mut options: Vec<(&clap::Arg, Vec<String>, bool)> = Vec::new();
+    for arg in cmd.get_arguments() {
+        let id = arg.get_id().as_str();
+        if id == "emit_command" || (resolved_ports && matches!(id, "popular" | "profile" | "port_group")) {
+            continue;
+        }
+        let values = match &settings[id] {
+            serde_json::Value::Null | serde_json::Value::Bool(false) => continue,
+            serde_json::Value::Array(items) => items.clone(),
+            value => vec![value.clone()],
+        };
+        let defaults: Vec<String> = arg.get_default_values().iter().map(|v| v.to_string_lossy().into_owned()).collect();
+        let is_default = !defaults.is_empty() && values.iter().map(plain_value).eq(defaults);
+        let mut words = Vec::new();
+        for value in &values {
+            match arg.get_long() {
+                Some(long) if value.is_boolean() => words.push(format!("--{}", long)),
+                Some(long) => words.extend([format!("--{}", long), plain_value(value)]),
+                None => words.push(plain_value(value)),
+            }
+        }
+        options.push((arg, words, is_default));
+    }
+
+    let (positional, named): (Vec<_>, Vec<_>) = options.into_iter().partition(|(arg, _, _)| arg.is_positional());
+    let line = |chosen: &[&Vec<String>]| -> Vec<String> {
+        let mut words = vec!["ospine".to_string()];
+        words.extend(chosen.iter().copied().flatten().cloned());
+        words.extend(positional.iter().flat_map(|(_, w, _)| w.iter().cloned()));
+        words
+    };
+    let explicit: Vec<&Vec<String>> = named.iter().filter(|(_, _, d)| !d).map(|(_, w, _)| w).collect();
+    let chosen: Vec<&Vec<String>> = named
+        .iter()
+        .filter(|(_, words, is_default)| {
+            !is_default || {
+                let mut with = explicit.clone();
+                with.push(words);
+                Cli::try_parse_from(line(&with)).is_ok()
+            }
+        })
+        .map(|(_, w, _)| w)
+        .collect();
+    line(&chosen).iter().map(|w| shell_word(w)).collect::<Vec<_>>().join(" ")
+}
+
+fn plain_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+// A POSIX shell word, single-quoted unless it is made of only safe characters
+fn shell_word(s: &str) -> String {
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:,=@%+".contains(c)) {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
+
+fn profile_ports(name: &str) -> Result<&'static [u16]> {
+    PORT_PROFILES
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name.trim()))
+        .map(|(_, ports)| *ports)
+        .ok_or_else(|| {
+            let known: Vec<&str> = PORT_PROFILES.iter().map(|(n, _)| *n).collect();
+            anyhow::anyhow!("unknown port profile '{}' (available: {})", name, known.join(", "))
+        })
+}
+
+// The port set to scan, sorted: --popular on its own, otherwise the union of --profile, --port-group
+// and --ports, or DEFAULT_PORTS when none of them is given.
+pub fn resolve_ports(cli: &Cli, port_groups: &[(String, Vec<u16>)]) -> Result<Vec<u16>> {
+    let mut ports = Vec::new();
+    if cli.popular {
+        ports.extend_from_slice(POPULAR_PORTS);
+    } else {
+        for name in &cli.profile {
+            ports.extend_from_slice(profile_ports(name)?);
+        }
+        for (_, group_ports) in port_groups {
+            ports.extend_from_slice(group_ports);
+        }
+        match &cli.ports {
+            Some(spec) => ports.extend(parse_ports(spec)?),
+            None if ports.is_empty() => ports = parse_ports(DEFAULT_PORTS)?,
+            None => {}
+        }
+    }
+    ports.sort_unstable();
+    ports.dedup();
+    Ok(ports)
+}
+
+// --port-group values as (name, ports), in the order given
+pub fn parse_port_groups(specs: &[String]) -> Result<Vec<(String, Vec<u16>)>> {
+    specs
+        .iter()
+        .map(|spec| {
+            let (name, ports) = spec
+                .split_once('=')
+                .filter(|(name, _)| !name.trim().is_empty())
+                .ok_or_else(|| anyhow::anyhow!("invalid --port-group '{}': expected NAME=SPEC", spec))?;
+            let ports = parse_ports(ports).map_err(|e| anyhow::anyhow!("invalid --port-group '{}': {}", spec, e))?;
+            Ok((name.trim().to_string(), ports))
+        })
+        .collect()
+}
+
+// Names of the groups containing `port`, comma-separated; None for ungrouped ports
+pub fn port_group_label(groups: &[(String, Vec<u16>)], port: u16) -> Option<String> {
+    let names: Vec<&str> = groups
+        .iter()
+        .filter(|(_, ports)| ports.binary_search(&port).is_ok())
+        .map(|(name, _)| name.as_str())
+        .collect();
+    (!names.is_empty()).then(|| names.join(","))
+}
+
+// Keys accepted by --sort-by
+#[derive(Clone, Copy, Debug, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    Port,
+    Target,
+    Protocol,
+    Latency,
+}
+
+const DEFAULT_MAX_CONNECTIONS: usize = 10_000;
+
+// Default for --max-connections: the fixed default, capped at 80% of the soft RLIMIT_NOFILE so an
+// untuned shell (often 1024) does not run into EMFILE; the rest is left for stdio, files and DNS.
+#[cfg(unix)]
+pub fn default_max_connections() -> usize {
+    let Ok((soft, _hard)) = rlimit::Resource::NOFILE.get() else {
+        return DEFAULT_MAX_CONNECTIONS;
+    };
+    let cap = usize::try_from(soft / 10 * 8).unwrap_or(usize::MAX).max(1);
+    if cap < DEFAULT_MAX_CONNECTIONS {
+        eprintln!("note: --max-connections defaults to {} (80% of the open file limit {})", cap, soft);
+        return cap;
+    }
+    DEFAULT_MAX_CONNECTIONS
+}
+
+#[cfg(not(unix))]
+pub fn default_max_connections() -> usize {
+    DEFAULT_MAX_CONNECTIONS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ports_for(args: &[&str]) -> Vec<u16> {
+        let cli = Cli::try_parse_from(["ospine", "127.0.0.1"].iter().chain(args)).unwrap();
+        let groups = parse_port_groups(&cli.port_group).unwrap();
+        resolve_ports(&cli, &groups).unwrap()
+    }
+
+    #[test]
+    fn default_range() {
+        assert_eq!(ports_for(&[]), (1..=1024).collect::<Vec<u16>>());
+    }
+
+    #[test]
+    fn popular_overrides_ports() {
+        let mut popular = POPULAR_PORTS.to_vec();
+        popular.sort_unstable();
+        assert_eq!(ports_for(&["--popular", "-p", "9999"]), popular);
+        assert_eq!(ports_for(&["-P", "--profile", "db"]), popular);
+    }
+
+    #[test]
+    fn profile_and_ports_combine() {
+        let mut expected = profile_ports("mail").unwrap().to_vec();
+        expected.extend([22, 8000, 8001]);
+        expected.sort_unstable();
+        assert_eq!(ports_for(&["--profile", "mail", "-p", "22,8000-8001,25"]), expected);
+    }
+
+    #[test]
+    fn port_groups_replace_the_default() {
+        assert_eq!(ports_for(&["--port-group", "web=443,80", "--port-group", "db=5432"]), [80, 443, 5432]);
+        assert_eq!(ports_for(&["--port-group", "web=80", "-p", "22"]), [22, 80]);
+    }
+}
@@ -0,0 +1,168 @@
+//! Product, version and platform details parsed out of the bytes a service sent, for
+//! `ScanResult.service`. Protocols without a parser get a `ServiceInfo` with only the name.
+
+use crate::types::{Protocol, ServiceInfo};
+
+/// Details of the service that sent `buf`, identified as `protocol`; `raw` is left for the caller.
+pub(crate) fn service_info(protocol: &Protocol, buf: &[u8]) -> ServiceInfo {
+    let mut info = ServiceInfo::named(protocol);
+    let details = match protocol {
+        Protocol::Ssh => ssh_details(buf),
+        Protocol::Http | Protocol::Https => http_server_details(buf),
+        Protocol::Tls => tls_server_hello_details(buf),
+        _ => None,
+    };
+    if let Some((product, version, extra)) = details {
+        (info.product, info.version, info.extra) = (product, version, extra);
+    }
+    if let Protocol::Tls = protocol {
+        info.alpn = tls_server_hello_alpn(buf);
+    }
+    info
+}
+
+type Details = (Option<String>, Option<String>, Option<String>);
+
+// `SSH-2.0-OpenSSH_8.2p1 Ubuntu-4ubuntu0.5`: software `product_version`, then optional comments.
+fn ssh_details(buf: &[u8]) -> Option<Details> {
+    let line = first_line(buf)?;
+    let rest = line.strip_prefix("SSH-")?.split_once('-')?.1;
+    let (software, comments) = match rest.split_once(' ') {
+        Some((software, comments)) => (software, Some(comments.trim().to_string())),
+        None => (rest, None),
+    };
+    let (product, version) = split_product_version(software, '_')
+        .or_else(|| split_product_version(software, '-'))
+        .unwrap_or((software, None));
+    Some((Some(product.to_string()), version, comments.filter(|c| !c.is_empty())))
+}
+
+// `Server: nginx/1.18.0 (Ubuntu)`: the first product token, then whatever follows it.
+fn http_server_details(buf: &[u8]) -> Option<Details> {
+    let head = String::from_utf8_lossy(buf);
+    let value = head
+        .lines()
+        .take_while(|l| !l.is_empty())
+        .find_map(|l| l.split_once(':').filter(|(name, _)| name.eq_ignore_ascii_case("server")))?
+        .1
+        .trim();
+    let (token, rest) = value.split_once(' ').unwrap_or((value, ""));
+    let (product, version) = split_product_version(token, '/').unwrap_or((token, None));
+    let extra = rest.trim().trim_start_matches('(').trim_end_matches(')');
+    Some((
+        Some(product.to_string()).filter(|p| !p.is_empty()),
+        version,
+        Some(extra.to_string()).filter(|e| !e.is_empty()),
+    ))
+}
+
+// ServerHello: the negotiated protocol version, and the cipher suite as extra.
+fn tls_server_hello_details(buf: &[u8]) -> Option<Details> {
+    // Record header (5), handshake type 2 and length (4), version (2), random (32), session id
+    if buf.get(5) != Some(&2) {
+        return None;
+    }
+    let version = match buf.get(9..11)? {
+        [3, 0] => "SSL 3.0",
+        [3, 1] => "1.0",
+        [3, 2] => "1.1",
+        [3, 3] => "1.2",
+        _ => return None,
+    };
+    let session_id_len = *buf.get(43)? as usize;
+    let cipher = buf
+        .get(44 + session_id_len..46 + session_id_len)
+        .and_then(|c| <[u8; 2]>::try_from(c).ok())
+        .map(|c| match c {
+            // The two suites our ClientHello offers
+            [0x00, 0x2f] => "TLS_RSA_WITH_AES_128_CBC_SHA".to_string(),
+            [0x00, 0x35] => "TLS_RSA_WITH_AES_256_CBC_SHA".to_string(),
+            [hi, lo] => format!("cipher 0x{:02x}{:02x}", hi, lo),
+        });
+    Some((None, Some(version.to_string()), cipher))
+}
+
+// ServerHello ALPN extension: the one protocol the server selected, if any.
+fn tls_server_hello_alpn(buf: &[u8]) -> Option<String> {
+    if buf.get(5) != Some(&2) {
+        return None;
+    }
+    // Only what the handshake message (and this record) holds, not whatever follows it
+    let hello_end = (9 + u32::from_be_bytes([0, *buf.get(6)?, *buf.get(7)?, *buf.get(8)?]) as usize).min(buf.len());
+    let hello = &buf[..hello_end];
+    // Past the session id: cipher suite (2), compression method (1), extensions length (2)
+    let session_id_len = *hello.get(43)? as usize;
+    let mut ext = hello.get(49 + session_id_len..)?;
+    while ext.len() >= 4 {
+        let (kind, len) = (u16::from_be_bytes([ext[0], ext[1]]), u16::from_be_bytes([ext[2], ext[3]]) as usize);
+        let data = ext.get(4..4 + len)?;
+        if kind == 0x0010 {
+            // Protocol name list (2-byte length) holding exactly one length-prefixed name
+            let name_len = *data.get(2)? as usize;
+            let name = data.get(3..3 + name_len)?;
+            return Some(String::from_utf8_lossy(name).to_string()).filter(|n| !n.is_empty());
+        }
+        ext = &ext[4 + len..];
+    }
+    None
+}
+
+/// Subject common name (OID 2.5.4.3) of a DER-encoded X.509 certificate.
+pub(crate) fn cert_common_name(der: &[u8]) -> Option<String> {
+    let (_, cert, _) = der_element(der)?;
+    let (_, tbs, _) = der_element(cert)?;
+    // Optional [0] version, then serial, signature algorithm, issuer and validity before the subject
+    let mut rest = tbs;
+    if rest.first() == Some(&0xa0) {
+        rest = der_element(rest)?.2;
+    }
+    for _ in 0..4 {
+        rest = der_element(rest)?.2;
+    }
+    // Subject: a sequence of RDN sets, each holding (OID, value) pairs
+    let (_, mut rdns, _) = der_element(rest)?;
+    while !rdns.is_empty() {
+        let (_, mut set, next) = der_element(rdns)?;
+        rdns = next;
+        while !set.is_empty() {
+            let (_, attr, next) = der_element(set)?;
+            set = next;
+            let (_, oid, value) = der_element(attr)?;
+            if oid == [0x55, 0x04, 0x03] {
+                let (_, name, _) = der_element(value)?;
+                return Some(String::from_utf8_lossy(name).to_string());
+            }
+        }
+    }
+    None
+}
+
+// One DER element: (tag, contents, what follows it). Lengths over 4 bytes are refused.
+fn der_element(buf: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *buf.first()?;
+    let first = *buf.get(1)? as usize;
+    let (len, head) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let n = first & 0x7f;
+        if n == 0 || n > 4 {
+            return None;
+        }
+        let len = buf.get(2..2 + n)?.iter().fold(0usize, |acc, &b| acc << 8 | b as usize);
+        (len, 2 + n)
+    };
+    let contents = buf.get(head..head.checked_add(len)?)?;
+    Some((tag, contents, &buf[head + len..]))
+}
+
+// `OpenSSH_8.2p1` -> ("OpenSSH", "8.2p1") when what follows the last `sep` starts with a digit.
+fn split_product_version(token: &str, sep: char) -> Option<(&str, Option<String>)> {
+    let (product, version) = token.rsplit_once(sep)?;
+    (!product.is_empty() && version.starts_with(|c: char| c.is_ascii_digit()))
+        .then(|| (product, Some(version.to_string())))
+}
+
+fn first_line(buf: &[u8]) -> Option<String> {
+    let line = buf.split(|&b| b == b'\n').next()?;
+    Some(String::from_utf8_lossy(line).trim_end_matches('\r').to_string())
+}
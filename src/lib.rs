@@ -0,0 +1,54 @@
+//! ospine as a library: the port scanner, protocol identification and probe definitions behind
+//! the `ospine` binary, for programs that want to embed scanning instead of shelling out.
+//!
+//! Build a [`ScanConfig`] for one target (`ScanConfig::builder()`) and hand it to [`scan_ports`] (all results, sorted by port)
+//! or [`scan_ports_stream`] (results as ports complete). Output formats, target expansion and
+//! artifacts stay in the binary.
+
+use anyhow::Result;
+
+mod builder;
+mod capture;
+mod fingerprint;
+mod matchers;
+mod protocols;
+mod proxy;
+mod scanner;
+mod service_probes;
+mod services;
+mod tls;
+mod types;
+
+pub use builder::ScanConfigBuilder;
+pub use proxy::HttpProxy;
+pub use protocols::{classify_banner, classify_capture, render_banner, render_banner_decoded};
+pub use scanner::{scan_ports, scan_ports_stream};
+pub use service_probes::{ProbeSet, ServiceProbe};
+pub use types::{
+    ByteBudget, HttpMethod, PortSpec, Protocol, RateLimiter, ReadStrategy, ScanConfig, ScanDelay, ScanResult, ServiceInfo,
+    SourcePorts, TlsSession,
+};
+
+/// Parse a port spec such as `22,80,8000-8100,https` into a sorted, deduplicated list; names are
+/// looked up in `/etc/services`.
+pub fn parse_ports(spec: &str) -> Result<Vec<u16>> {
+    let mut ports = Vec::new();
+    for part in spec.split(',') {
+        let p = part.trim();
+        if p.is_empty() { continue; }
+        // Anything that is not a number or a numeric range is a service name ("ms-sql-s" has dashes too)
+        let range = p.split_once('-').and_then(|(a, b)| Some((a.trim().parse::<u16>().ok()?, b.trim().parse::<u16>().ok()?)));
+        if let Some((s, e)) = range {
+            for port in s.min(e)..=s.max(e) {
+                ports.push(port);
+            }
+        } else if p.bytes().all(|b| b.is_ascii_digit()) {
+            ports.push(p.parse()?);
+        } else {
+            ports.push(services::port_by_name(p)?);
+        }
+    }
+    ports.sort_unstable();
+    ports.dedup();
+    Ok(ports)
+}
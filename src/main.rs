@@ -1,319 +1,687 @@
 use anyhow::Result;
-use clap::{ArgAction, Parser};
+use clap::Parser;
+use std::net::IpAddr;
+use std::collections::{BTreeMap, HashMap};
 use std::str::FromStr;
 use std::time::Duration;
-use ipnet::IpNet;
 
-mod scanner;
-mod protocols;
-mod types;
-
-use scanner::scan_ports;
-use types::{PortSpec, ScanConfig, RateLimiter};
+mod annotate;
+mod artifact;
+mod baseline;
+mod cli;
+mod matrix;
+mod output;
+mod port_stats;
+mod sink;
+mod summary;
+mod targets;
+mod xml;
+
+use annotate::Annotations;
+use artifact::{create_staging_file, finalize_artifact, live_write, Staging};
+use baseline::{load_open_endpoints, load_prior_results, Change};
+use cli::{
+    banner_encoding, default_max_connections, effective_config, parse_port_groups, port_group_label, reproduce_command,
+    resolve_ports, Cli, APP_VERSION,
+};
+use output::{classify_file, compare_results, grouped_host_json, human_line, pretty_json, JsonStdout};
+use port_stats::PortStats;
+use sink::ResultSink;
+use summary::ScanSummary;
+use targets::{expand_addresses, group_endpoints, load_endpoints, parse_targets, seeded_rng, CidrLimits, Target};
+use ospine::{
+    ByteBudget, HttpProxy, parse_ports, scan_ports_stream, PortSpec, SourcePorts,
+    ProbeSet, Protocol, ReadStrategy, ScanConfig, ScanDelay, ScanResult,
+};
+use rand::seq::SliceRandom;
+use xml::XmlReport;
 use futures::stream::{self, StreamExt};
-use std::io::{self, Write, BufRead};
-use std::fs::{File, OpenOptions};
-use std::io::{BufWriter, BufReader, Seek, SeekFrom};
-use std::path::PathBuf;
+use std::io::Write;
+use std::fs::OpenOptions;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
 use std::env::temp_dir;
 use std::sync::Arc;
-use tokio::sync::Semaphore;
-
-// Build-time version: Major.Minor.Patch.Build
-const APP_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), ".", env!("APP_BUILD"));
-
-// Popular TCP ports list used by --popular flag
-const POPULAR_PORTS: &[u16] = &[
-    20,21,22,23,25,53,67,68,69,80,110,111,123,135,137,138,139,143,161,162,443,445,500,514,520,631,993,995,1434,1723,1900,3306,3389,4500,5900,8080,49152,
-];
-
-#[derive(Parser, Debug)]
-#[command(name = "ospine", version = APP_VERSION, about = "Open Source Port Interrogation & Network Enumeration")] 
-struct Cli {
-    /// Target (IP, hostname, or CIDR range)
-    target: String,
-
-    /// Ports to scan (e.g. 80,443,8000-8100). Comma-separated list and/or ranges
-    #[arg(short, long, default_value = "1-1024")]
-    ports: String,
-
-    /// Scan only popular ports (overrides --ports when set)
-    #[arg(short = 'P', long = "popular", action = ArgAction::SetTrue)]
-    popular: bool,
-
-    /// Max concurrent connections
-    #[arg(short = 'c', long, default_value_t = 100)]
-    concurrency: usize,
-
-    /// Per-port timeout milliseconds
-    #[arg(short = 't', long, default_value_t = 1000)]
-    timeout_ms: u64,
-
-    /// Bytes to read for banner/probe
-    #[arg(
-        short = 'b',
-        long,
-        default_value_t = 512_u32,
-        value_parser = clap::value_parser!(u32).range(1..=16384),
-    )]
-    banner_bytes: u32,
-
-    /// Passive mode: do not send any probe data; only perform passive banner reads
-    #[arg(long = "passive", action = ArgAction::SetTrue)]
-    passive: bool,
-
-    /// JSON output
-    #[arg(short = 'j', long, action = ArgAction::SetTrue)]
-    json: bool,
-
-    /// Output only open ports (filters out closed/timeouts)
-    #[arg(short = 'o', long = "open-only", action = ArgAction::SetTrue)]
-    open_only: bool,
-
-    /// Show raw banner text (human-readable mode only)
-    #[arg(short = 'r', long = "raw-banner", action = ArgAction::SetTrue)]
-    raw_banner: bool,
-
-    /// Save completed JSON artifact to this file when the scan finishes
-    #[arg(short = 's', long = "save-file", default_value = "last_scan.output")]
-    save_file: String,
-
-    /// Global cap on in-flight TCP connections across all targets
-    #[arg(long = "max-connections", default_value_t = 10_000_usize)]
-    max_connections: usize,
-
-    /// Global rate limit for connection attempts per second
-    #[arg(long = "rate", default_value_t = 5_000_u64)]
-    rate: u64,
-}
+use tokio::sync::mpsc;
+
+// --detect-tarpit: a host is flagged when more than 90% of its scanned ports are open and more than
+// this many ports were scanned
+const TARPIT_MIN_PORTS: usize = 50;
 
-fn parse_ports(spec: &str) -> Result<Vec<u16>> {
-    let mut ports = Vec::new();
-    for part in spec.split(',') {
-        let p = part.trim();
-        if p.is_empty() { continue; }
-        if let Some((start, end)) = p.split_once('-') {
-            let s: u16 = start.parse()?;
-            let e: u16 = end.parse()?;
-            for port in s.min(e)..=s.max(e) {
-                ports.push(port);
+// Results in flight from the targets to the --stream-save/--sink writer before targets wait
+const LIVE_RESULTS_BUFFER: usize = 1024;
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let started = std::time::Instant::now();
+
+    let port_groups = parse_port_groups(&cli.port_group)?;
+    let ports = resolve_ports(&cli, &port_groups)?;
+
+    let no_banner_ports = match &cli.no_banner_ports {
+        Some(spec) => parse_ports(spec).map_err(|e| anyhow::anyhow!("invalid --no-banner-ports '{}': {}", spec, e))?,
+        None => Vec::new(),
+    };
+
+    // One pool for the whole run, so the rotation spans targets
+    let source_ports = match &cli.source_ports {
+        Some(spec) => {
+            let list = parse_ports(spec).map_err(|e| anyhow::anyhow!("invalid --source-ports '{}': {}", spec, e))?;
+            if list.is_empty() || list.contains(&0) {
+                anyhow::bail!("invalid --source-ports '{}': ports must be between 1 and 65535", spec);
             }
-        } else {
-            ports.push(p.parse()?);
+            Some(Arc::new(SourcePorts::new(list)))
         }
+        None => None,
+    };
+
+    // Shared by every target's config; the second handle reports on it once the scan ends
+    let byte_budget = cli.max_total_bytes.map(|n| Arc::new(ByteBudget::new(n)));
+    let bytes_read = byte_budget.clone();
+
+    let max_connections = cli.max_connections.unwrap_or_else(default_max_connections);
+
+    // --smart-order: open counts from earlier scans, saved back with this one's once it ends
+    let mut port_stats = if cli.smart_order {
+        let path = PortStats::default_path()
+            .ok_or_else(|| anyhow::anyhow!("--smart-order: no cache directory (set HOME or XDG_CACHE_HOME)"))?;
+        Some((PortStats::load(&path)?, path))
+    } else {
+        None
+    };
+
+    // An explicit --proxy applies to every target; one from the environment skips NO_PROXY targets
+    let (proxy, proxy_from_env) = match &cli.proxy {
+        Some(url) => (Some(Arc::new(HttpProxy::parse(url)?)), false),
+        // Connections through a proxy leave from the proxy, so a source port pool means direct ones
+        None if cli.no_proxy || cli.source_ports.is_some() => (None, false),
+        None => (HttpProxy::from_env()?.map(Arc::new), true),
+    };
+    if let (Some(p), true) = (&proxy, proxy_from_env) {
+        eprintln!("note: connecting through proxy {} from the environment (--no-proxy to connect directly)", p.address());
     }
-    ports.sort_unstable();
-    ports.dedup();
-    Ok(ports)
-}
 
-fn parse_targets(input: &str) -> Result<Vec<String>> {
-    // Try CIDR first
-    if let Ok(net) = IpNet::from_str(input) {
-        // Put a safety cap to avoid accidental huge scans
-        const MAX_HOSTS: usize = 100_000;
-        let hosts: Vec<String> = net.hosts().map(|ip| ip.to_string()).collect();
-        if hosts.len() > MAX_HOSTS {
-            anyhow::bail!("CIDR expands to {} hosts which exceeds the safety cap of {}", hosts.len(), MAX_HOSTS);
+    // Effective settings, recorded in JSON output so artifacts describe how they were produced
+    let config_json = effective_config(&cli, &ports, max_connections, proxy.as_deref())?;
+    if cli.show_config {
+        println!("{}", serde_json::to_string_pretty(&config_json)?);
+        return Ok(());
+    }
+    // The proxy as given, credentials included, so the command really does reproduce the scan
+    let command = cli.emit_command.then(|| {
+        let mut settings = config_json.clone();
+        if let Some(url) = &cli.proxy {
+            settings["proxy"] = serde_json::Value::from(url.as_str());
         }
-        return Ok(hosts);
+        reproduce_command(&settings)
+    });
+    if let Some(command) = &command {
+        eprintln!("command: {}", command);
     }
-    // Otherwise, treat as single IP or hostname string
-    Ok(vec![input.to_string()])
-}
+    let config_json = serde_json::to_string(&config_json)?;
+    // Appended after "config" in the JSON envelope
+    let command_json = match &command {
+        Some(command) => format!(",\"command\":{}", serde_json::to_string(command)?),
+        None => String::new(),
+    };
 
-// Escape control characters so untrusted banners can't manipulate the terminal.
-// - Converts '\\n', '\\r', and '\\t' into visible sequences ("\\n", "\\r", "\\t").
-// - Converts other control bytes (including ESC) to hex escapes like "\\x1b".
-// - Leaves printable Unicode characters as-is.
-fn escape_nonprintable(s: &str) -> String {
-    let mut out = String::with_capacity(s.len());
-    for ch in s.chars() {
-        match ch {
-            '\n' => { out.push('\\'); out.push('n'); }
-            '\r' => { out.push('\\'); out.push('r'); }
-            '\t' => { out.push('\\'); out.push('t'); }
-            c if c.is_control() => {
-                // Render as \xNN for BMP control chars
-                let v = c as u32;
-                if v <= 0xFF {
-                    use std::fmt::Write as _;
-                    out.push('\\'); out.push('x');
-                    let _ = write!(&mut out, "{v:02x}");
-                } else {
-                    // Fallback for any odd control-like codepoints
-                    use std::fmt::Write as _;
-                    out.push('\\'); out.push('u'); out.push('{');
-                    let _ = write!(&mut out, "{v:x}");
-                    out.push('}');
-                }
+    let probes = Arc::new(match &cli.probe_file {
+        Some(path) => ProbeSet::load(path)?,
+        None => ProbeSet::builtin(),
+    });
+
+    // (target, address, port) -> (open, banner) of the baseline; later records win, as in appended NDJSON
+    let baseline = match &cli.baseline {
+        Some(path) => Some(
+            load_prior_results(path)?
+                .into_iter()
+                .map(|r| ((r.target, r.resolved_addr, r.port), (r.open, r.banner)))
+                .collect::<HashMap<_, _>>(),
+        ),
+        None => None,
+    };
+
+    // Built-in protocol names, plus any service the loaded probes can report
+    let wanted_protocols = cli
+        .protocol
+        .iter()
+        .map(|name| {
+            let proto = Protocol::from_service_name(name.trim());
+            let known = !matches!(proto, Protocol::Other(_))
+                || probes.services().any(|s| s.eq_ignore_ascii_case(name.trim()));
+            if known {
+                Ok(proto)
+            } else {
+                Err(anyhow::anyhow!("unknown protocol '{}' for --protocol", name))
             }
-            _ => out.push(ch),
+        })
+        .collect::<Result<Vec<Protocol>>>()?;
+
+    if let Some(path) = &cli.classify_file {
+        return classify_file(path, cli.classify_port, &probes, &cli);
+    }
+
+    let cidr_limits = CidrLimits {
+        max_hosts: cli.max_hosts,
+        ipv6_expand_prefix: cli.ipv6_expand_prefix,
+        allow_large_ipv6: cli.allow_large_ipv6,
+    };
+    // Each target with its own ports (--endpoints-file, --rescan-open), or None for the shared port list
+    let endpoints = match (&cli.endpoints_file, &cli.rescan_open) {
+        (Some(path), _) => Some(load_endpoints(path)?),
+        (None, Some(path)) => Some(load_open_endpoints(path)?),
+        (None, None) => None,
+    };
+    let mut targets: Vec<(Target, Option<Vec<u16>>)> = match endpoints {
+        Some(pairs) => group_endpoints(pairs, &cidr_limits)?
+            .into_iter()
+            .map(|(t, ports)| (t, Some(ports)))
+            .collect(),
+        None => parse_targets(cli.target.as_deref().unwrap_or_default(), &cidr_limits)?
+            .into_iter()
+            .map(|t| (t, None))
+            .collect(),
+    };
+    if cli.no_dns {
+        if let Some((t, _)) = targets.iter().find(|(t, _)| IpAddr::from_str(&t.host).is_err()) {
+            anyhow::bail!("--no-dns: '{}' is not an IP address or CIDR", t.name);
+        }
+        if let Some(p) = proxy.as_ref().filter(|p| IpAddr::from_str(p.host()).is_err()) {
+            anyhow::bail!("--no-dns: proxy {} must be given as an IP address", p.address());
+        }
+    }
+    if cli.all_addresses {
+        targets = expand_addresses(targets, cli.max_hosts).await?;
+    }
+    if cli.randomize_targets {
+        targets.shuffle(&mut seeded_rng(cli.seed));
+    }
+    // Scan order: likeliest-open first (--smart-order) or highest first (--reverse-ports); the
+    // recorded `ports` setting and the output stay sorted
+    let mut ports = ports;
+    if let Some((stats, _)) = &port_stats {
+        stats.order(&mut ports);
+        for own_ports in targets.iter_mut().filter_map(|(_, p)| p.as_mut()) {
+            stats.order(own_ports);
+        }
+    } else if cli.reverse_ports {
+        ports.sort_unstable_by(|a, b| b.cmp(a));
+        for own_ports in targets.iter_mut().filter_map(|(_, p)| p.as_mut()) {
+            own_ports.sort_unstable_by(|a, b| b.cmp(a));
         }
     }
-    out
-}
 
-#[tokio::main(flavor = "multi_thread")] 
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let annotations = match &cli.annotate_file {
+        Some(path) => Some(Annotations::load(path)?),
+        None => None,
+    };
 
-    let ports = if cli.popular {
-        let mut v = POPULAR_PORTS.to_vec();
-        v.sort_unstable();
-        v.dedup();
-        v
+    if let Some(dir) = &cli.capture_dir {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow::anyhow!("failed to create capture directory {}: {}", dir.display(), e))?;
+    }
+
+    // Target-level concurrency; --max-connections still caps the total number of sockets
+    let target_concurrency = cli.target_concurrency.min(targets.len()).max(1);
+
+    // Settings shared by every target, built and checked once (named after the first target); each
+    // target's copy gets its own name, address and ports. The connection cap, rate limiter and
+    // pacing are created here and shared by all copies.
+    let mut template = ScanConfig::builder()
+        .target(targets.first().map_or("-", |(t, _)| t.name.as_str()))
+        .no_dns(cli.no_dns)
+        .concurrency(cli.concurrency)
+        .timeout(Duration::from_millis(cli.timeout_ms))
+        .banner_read_len(cli.banner_bytes as usize)
+        .read_strategy(if cli.read_until_idle {
+            ReadStrategy::UntilIdle(Duration::from_millis(cli.idle_timeout_ms))
+        } else {
+            ReadStrategy::Single
+        })
+        .banner_hex(cli.banner_hex)
+        .passive(cli.passive)
+        .connect_only(cli.no_banner)
+        .no_banner_ports(no_banner_ports)
+        .reprobe_unknown(cli.reprobe_unknown.unwrap_or(0))
+        .two_phase(cli.two_phase)
+        .os_hint(cli.os_hint)
+        .starttls(cli.starttls)
+        .verbose(cli.verbose)
+        .rate(cli.rate)
+        .max_connections(max_connections)
+        .scan_delay(Arc::new(ScanDelay::new(
+            Duration::from_millis(cli.scan_delay_ms),
+            Duration::from_millis(cli.max_jitter_ms),
+            cli.seed,
+        )))
+        .probes(probes.clone())
+        .http_method(cli.http_method)
+        .http_path(cli.http_path.clone())
+        .http_user_agent(cli.http_user_agent.clone())
+        .ws_probe(cli.ws_probe)
+        .follow_redirect(cli.follow_redirect);
+    if let Some(limit) = cli.per_host_connections {
+        template = template.per_host_connections(limit);
+    }
+    if let Some(ms) = cli.total_timeout_ms {
+        template = template.total_timeout(Duration::from_millis(ms));
+    }
+    if let Some(max) = cli.max_host_timeouts {
+        template = template.max_host_timeouts(max);
+    }
+    if let Some(open) = cli.stop_after_open {
+        template = template.stop_after_open(open);
+    }
+    if let Some(multiplier) = cli.rtt_multiplier {
+        template = template.rtt_multiplier(multiplier);
+    }
+    if let Some(max_chars) = cli.banner_max_chars {
+        template = template.banner_max_chars(max_chars);
+    }
+    if cli.banner_encoding.is_some() {
+        template = template.banner_encoding(banner_encoding(&cli));
+    }
+    if cli.verify_open {
+        template = template.verify_open(Duration::from_millis(cli.verify_grace_ms));
+    }
+    if let Some(max) = cli.max_probes {
+        template = template.max_probes(max);
+    }
+    if let Some(host) = &cli.http_host {
+        template = template.http_host(host.clone());
+    }
+    if let Some(dir) = &cli.capture_dir {
+        template = template.capture_dir(dir.clone());
+    }
+    if let Some(pool) = source_ports {
+        template = template.source_ports(pool);
+    }
+    if let Some(budget) = byte_budget {
+        template = template.byte_budget(budget);
+    }
+    let template = template.build()?;
+    // Generated once per run so results of the same invocation stay correlatable
+    let scan_id = template.scan_id.clone();
+    let mut summary = ScanSummary::new(&scan_id);
+
+    // The next target is only pulled (and so launched) once the pause has passed; targets already
+    // running are unaffected
+    let inter_target_delay = Duration::from_millis(cli.inter_target_delay_ms);
+    let paced_targets = stream::iter(targets).enumerate().then(move |(i, target)| async move {
+        if i > 0 && !inter_target_delay.is_zero() {
+            tokio::time::sleep(inter_target_delay).await;
+        }
+        target
+    });
+    // --stream-save and --sink get each result as its port completes, through this channel, unless
+    // a whole-target verdict (--suppress-dead-ports, --suppress-tarpit) may still drop it
+    let live_output = (cli.stream_save || cli.sink.is_some()) && !cli.suppress_dead_ports && !cli.suppress_tarpit;
+    let (live_tx, mut live_rx) = mpsc::channel::<ScanResult>(LIVE_RESULTS_BUFFER);
+    let live_tx = live_output.then_some(live_tx);
+    let target_stream = paced_targets.enumerate().map(move |(i, (t, own_ports))| {
+        let proxy = proxy.clone().filter(|_| !proxy_from_env || !HttpProxy::bypassed_by_env(&t.name));
+        // Offline lookup only: hostnames are not resolved for annotation
+        let label = annotations
+            .as_ref()
+            .zip(IpAddr::from_str(&t.host).ok())
+            .and_then(|(a, ip)| a.lookup(ip).map(str::to_string));
+        let mut cfg = template.clone();
+        cfg.target = t.name;
+        cfg.host = t.host;
+        cfg.scope_id = t.scope_id;
+        cfg.label = label;
+        cfg.resolved_addr = t.resolved_addr;
+        cfg.port_spec = PortSpec::List(own_ports.unwrap_or_else(|| ports.clone()));
+        cfg.proxy = proxy;
+        let live = live_tx.clone();
+        async move {
+            let mut list = Vec::new();
+            let mut results = Box::pin(scan_ports_stream(cfg));
+            while let Some(r) = results.next().await {
+                if let Some(tx) = &live {
+                    let _ = tx.send(r.clone()).await;
+                }
+                list.push(r);
+            }
+            list.sort_by_key(|r| r.port);
+            (i, list)
+        }
+    });
+
+    let finished = target_stream.buffer_unordered(target_concurrency);
+    let mut in_flight = if cli.ordered_output {
+        // Reorder buffer: results wait here, keyed by target index, until all earlier targets are out
+        let mut held = BTreeMap::new();
+        let mut next = 0;
+        finished
+            .flat_map(move |(i, res)| {
+                held.insert(i, res);
+                let mut ready = Vec::new();
+                while let Some(res) = held.remove(&next) {
+                    ready.push(res);
+                    next += 1;
+                }
+                stream::iter(ready)
+            })
+            .boxed()
     } else {
-        parse_ports(&cli.ports)?
+        finished.map(|(_, res)| res).boxed()
     };
 
-    let targets = parse_targets(&cli.target)?;
-
-    // Global target concurrency limit to mitigate resource exhaustion
-    const MAX_TARGET_CONCURRENCY: usize = 1_000;
-    let target_concurrency = MAX_TARGET_CONCURRENCY.min(targets.len().max(1));
-
-    // Prepare a stream of scan futures and buffer them with the global limit
-    let ports_arc = ports.clone();
-    // Create a global semaphore to enforce the connection cap
-    let global_limit = Arc::new(Semaphore::new(cli.max_connections));
-    // Create a global rate limiter shared across all targets
-    let rate_limiter = Arc::new(RateLimiter::new(cli.rate));
-
-    let target_stream = stream::iter(targets.into_iter().map(move |t| {
-        let cfg = ScanConfig {
-            target: t,
-            port_spec: PortSpec::List(ports_arc.clone()),
-            concurrency: cli.concurrency,
-            timeout: Duration::from_millis(cli.timeout_ms),
-            banner_read_len: cli.banner_bytes as usize,
-            passive: cli.passive,
-            global_limit: global_limit.clone(),
-            rate_limiter: rate_limiter.clone(),
+    // Prepare temp streaming persistence for final artifact construction without in-memory aggregation.
+    // The OS temp directory comes first; hardened containers often make it read-only, so fall back to
+    // the save file's directory (the staged artifact lands there anyway), or memory with --no-temp-file.
+    let (tmp_path, mut tmp_writer) = if cli.no_artifact {
+        (None, None)
+    } else if cli.no_temp_file {
+        (None, Some(Staging::Memory(Vec::new())))
+    } else {
+        let save_dir = match Path::new(&cli.save_file).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
         };
-        async move { scan_ports(cfg).await }
-    }));
-
-    let mut in_flight = target_stream.buffer_unordered(target_concurrency);
-
-    // Prepare temp streaming persistence for final artifact construction without in-memory aggregation
-    // Create a randomized temp file in the OS temp directory with O_EXCL semantics to avoid clobber/symlink issues.
-    let mut tmp_path: Option<PathBuf> = None;
-    let mut tmp_file: Option<File> = None;
-    // Attempt a few times to avoid extremely unlikely name collisions
-    for attempt in 0..3u8 {
-        let mut path = temp_dir();
-        let nanos = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_nanos())
-            .unwrap_or(0);
-        let pid = std::process::id();
-        let name = format!("ospine.{pid}.{nanos}.{attempt}.ndjson.tmp");
-        path.push(name);
-        match OpenOptions::new().read(true).write(true).create_new(true).open(&path) {
-            Ok(f) => {
-                tmp_path = Some(path);
-                tmp_file = Some(f);
-                break;
+        let (path, file) = match create_staging_file(&temp_dir()) {
+            Ok(staged) => staged,
+            Err(e) => {
+                eprintln!(
+                    "warning: cannot create a temp file in {} ({}); staging results in {}",
+                    temp_dir().display(),
+                    e,
+                    save_dir.display()
+                );
+                create_staging_file(&save_dir).map_err(|e| {
+                    anyhow::anyhow!(
+                        "failed to create secure temp file in {} ({}); use --no-temp-file to stage results in memory",
+                        save_dir.display(),
+                        e
+                    )
+                })?
             }
-            Err(_) => continue,
+        };
+        (Some(path), Some(Staging::File(BufWriter::new(file))))
+    };
+
+    // With --stream-save, results also go to the save file as NDJSON as each target completes
+    let mut stream_writer = if cli.stream_save {
+        let mut opts = OpenOptions::new();
+        if cli.append { opts.append(true) } else { opts.write(true).truncate(true) };
+        let file = opts
+            .create(true)
+            .open(&cli.save_file)
+            .map_err(|e| anyhow::anyhow!("failed to open {} for streaming: {}", cli.save_file, e))?;
+        Some(BufWriter::new(file))
+    } else {
+        None
+    };
+
+    // Connected before scanning, so an unreachable collector is reported up front
+    let mut sink = match &cli.sink {
+        Some(url) => Some(ResultSink::connect(url).await?),
+        None => None,
+    };
+
+    let mut xml_report = match &cli.xml {
+        Some(path) => {
+            let args: Vec<String> = std::env::args().collect();
+            Some(XmlReport::create(path, &args.join(" "), APP_VERSION)?)
         }
-    }
-    let tmp_path = tmp_path.ok_or_else(|| anyhow::anyhow!("failed to create secure temp file after several attempts"))?;
-    let tmp_file = tmp_file.expect("temp file handle must exist if path is set");
-    let mut tmp_writer = BufWriter::new(tmp_file);
+        None => None,
+    };
 
     // Streaming output: do not accumulate all results in memory
     let mut first_json_item = true;
-    if cli.json {
+    let mut sorted_results: Vec<ScanResult> = Vec::new();
+    let json_out = cli.json || cli.json_grouped || cli.json_pretty || cli.json_safe;
+    let mut json_stdout = JsonStdout::new(&cli);
+    if cli.count_only {
+        // Per-port output is skipped entirely; only the summary is printed at the end
+    } else if cli.json_grouped {
+        // Grouping needs a target's results together; each target still streams as it completes
+        json_stdout.write(&format!("{{\"config\":{}{},\"hosts\":{{", config_json, command_json));
+    } else if json_out {
         // Start streaming a JSON object with a results array
-        print!("{{\"results\":[");
-        io::stdout().flush().ok();
+        json_stdout.write(&format!("{{\"config\":{}{},\"results\":[", config_json, command_json));
     }
 
-    while let Some(res) = in_flight.next().await {
-        match res {
-            Ok(mut list) => {
-                if cli.open_only {
-                    list.retain(|r| r.open);
-                }
+    // Nothing to serialize results for with --no-artifact
+    let persist = tmp_writer.is_some() || stream_writer.is_some() || sink.is_some();
+    // Per-result annotations and filters, shared by the live stream and the per-target reports
+    let annotate = |r: &mut ScanResult| {
+        if !port_groups.is_empty() {
+            r.group = port_group_label(&port_groups, r.port);
+        }
+        if let Some(baseline) = &baseline {
+            let before = baseline.get(&(r.target.clone(), r.resolved_addr.clone(), r.port));
+            let change = Change::between(before.map(|(open, banner)| (*open, banner.as_deref())), r);
+            r.change = Some(change.as_str().to_string());
+        }
+    };
+    let reported = |r: &ScanResult| {
+        (!cli.changes_only || r.change.as_deref() != Some(Change::Unchanged.as_str()))
+            && (!cli.open_only || r.open)
+            && (wanted_protocols.is_empty() || r.protocol.as_ref().is_some_and(|p| wanted_protocols.contains(p)))
+            && (!cli.only_responsive
+                || r.open
+                    && (r.banner.as_deref().is_some_and(|b| !b.is_empty())
+                        || r.protocol.as_ref().is_some_and(|p| !matches!(p, Protocol::Unknown))))
+    };
 
-                if cli.json {
-                    for r in list {
-                        // Persist to temp file as NDJSON (one ScanResult per line)
-                        let line = serde_json::to_string(&r)?;
-                        writeln!(tmp_writer, "{}", line)?;
-                        let line = serde_json::to_string(&r)?;
-                        if !first_json_item { print!(","); }
-                        print!("{}", line);
-                        first_json_item = false;
+    loop {
+        // Results waiting in the live channel go first; a target only completes after all of its
+        // results were sent there
+        let mut list = tokio::select! {
+            biased;
+            Some(mut r) = live_rx.recv(), if live_output => {
+                annotate(&mut r);
+                if persist && reported(&r) && (r.open || !cli.artifact_open_only) {
+                    live_write(&mut stream_writer, &mut sink, serde_json::to_string(&r)?, cli.stream_fsync)?;
+                    if let Some(s) = sink.as_mut() {
+                        s.flush().await;
                     }
-                    // Flush periodically for streaming behavior
-                    io::stdout().flush().ok();
-                } else {
-                    // For human-readable output, sort per-target ports for stability
+                }
+                continue;
+            }
+            next = in_flight.next() => match next {
+                Some(list) => list,
+                None => break,
+            },
+        };
+        if let Some((stats, _)) = port_stats.as_mut() {
+            stats.record(&list);
+        }
+        list.iter_mut().for_each(annotate);
+        // A target where every port timed out (or was skipped after too many timeouts) is most
+        // likely down or fully filtered
+        if cli.detect_dead_hosts && !list.is_empty()
+            && list.iter().all(|r| r.error.as_deref() == Some("timeout") || r.skipped_for_host_timeouts())
+        {
+            let note = format!(
+                "{}: host appears down/filtered (all {} ports timed out)",
+                list[0].target,
+                list.len()
+            );
+            // Keep stdout a valid JSON document in JSON mode
+            if json_out || cli.count_only { eprintln!("{}", note); } else { println!("{}", note); }
+            if cli.suppress_dead_ports {
+                list.clear();
+            }
+        }
+
+        // Nearly everything open on a sizeable scan: real hosts do not look like this
+        let open = list.iter().filter(|r| r.open).count();
+        if cli.detect_tarpit && list.len() > TARPIT_MIN_PORTS && open * 10 > list.len() * 9 {
+            let note = format!(
+                "{}: host looks like a tarpit ({} of {} ports open)",
+                list[0].target,
+                open,
+                list.len()
+            );
+            if json_out || cli.count_only { eprintln!("{}", note); } else { println!("{}", note); }
+            if cli.suppress_tarpit {
+                list.retain(|r| !r.open);
+            }
+        }
+
+        summary.targets += 1;
+        for r in &list {
+            summary.record(r);
+        }
+        if let Some(n) = cli.report_slowest {
+            summary.record_host_latency(&list, n);
+        }
+
+        list.retain(|r| reported(r));
+
+        if let Some(report) = xml_report.as_mut() {
+            report.write_host(&list)?;
+        }
+        for r in list.iter().filter(|r| persist && (r.open || !cli.artifact_open_only)) {
+            // Persist to temp file as NDJSON (one ScanResult per line)
+            let line = serde_json::to_string(r)?;
+            if let Some(w) = tmp_writer.as_mut() {
+                writeln!(w, "{}", line)?;
+            }
+            if !live_output {
+                live_write(&mut stream_writer, &mut sink, line, cli.stream_fsync)?;
+            }
+        }
+        if let Some(s) = sink.as_mut().filter(|_| !live_output) {
+            s.flush().await;
+        }
+
+        if cli.count_only {
+            continue;
+        }
+        if cli.json_grouped {
+            if let Some(host) = grouped_host_json(&mut list)? {
+                if !first_json_item { json_stdout.write(","); }
+                json_stdout.write(&host);
+                first_json_item = false;
+            }
+        } else if json_out {
+            for r in list {
+                let line = serde_json::to_string(&r)?;
+                if !first_json_item { json_stdout.write(","); }
+                json_stdout.write(&line);
+                first_json_item = false;
+            }
+        } else {
+            match cli.sort_by {
+                // An explicit sort order spans targets, so hold lines until the scan ends
+                Some(_) => sorted_results.extend(list),
+                // So does the grid, which needs every host and port
+                None if cli.matrix => sorted_results.extend(list),
+                None => {
+                    // Stream per target, ports in order for stability
                     list.sort_by(|a, b| a.target.cmp(&b.target).then(a.port.cmp(&b.port)));
-                    for r in list {
-                        // Persist to temp and accumulation as well in human-readable mode
-                        let json_line = serde_json::to_string(&r)?;
-                        writeln!(tmp_writer, "{}", json_line)?;
-                        let status = if r.open { "open" } else { "closed" };
-                        let mut line = format!("{}:{} {}", r.target, r.port, status);
-                        if let Some(proto) = r.protocol {
-                            line.push_str(&format!(" [{}]", proto));
-                        }
-                        if let Some(banner) = r.banner {
-                            if cli.raw_banner {
-                                let safe = escape_nonprintable(&banner);
-                                line.push_str(&format!(" — {}", safe));
-                            }
-                        }
-                        println!("{}", line);
+                    for r in &list {
+                        println!("{}", human_line(r, cli.raw_banner, cli.reason));
                     }
                 }
             }
-            Err(e) => eprintln!("scan task error: {}", e),
         }
     }
 
-    if cli.json {
-        println!("]}}");
+    summary.finish(started.elapsed());
+
+    if let Some(budget) = bytes_read.filter(|b| b.exhausted()) {
+        eprintln!(
+            "note: the --max-total-bytes budget was spent ({} bytes read); open ports after that have no banner",
+            budget.used()
+        );
     }
 
-    // Ensure temp file is flushed before reading it back; keep the handle and seek instead of reopening by path
-    tmp_writer.flush().ok();
-    // Recover the underlying File handle from BufWriter
-    let mut tmp_file = match tmp_writer.into_inner() {
-        Ok(f) => f,
-        Err(e) => {
-            // If into_inner fails (e.g., due to prior write error), propagate the underlying error
-            return Err(e.into_error().into());
+    if let Some(s) = sink.take() {
+        let fallback = PathBuf::from(format!("{}.sink-pending", cli.save_file));
+        if let Some(n) = s.finish(&fallback).await? {
+            eprintln!("warning: {} result(s) never reached the sink; saved to {}", n, fallback.display());
         }
-    };
-    // Rewind to the beginning for reading
-    tmp_file.seek(SeekFrom::Start(0))?;
-    let reader = BufReader::new(tmp_file);
-    let mut out = BufWriter::new(File::create(&cli.save_file)?);
-    write!(&mut out, "{{\"results\":[")?;
-    let mut first = true;
-    for line_res in reader.lines() {
-        let line = line_res?;
-        if line.is_empty() { continue; }
-        if !first { write!(&mut out, ",")?; }
-        first = false;
-        // Each line is already a serialized ScanResult JSON object
-        write!(&mut out, "{}", line)?;
-    }
-    write!(&mut out, "]}}")?;
-    out.flush().ok();
-
-    // Remove the temporary file
-    if let Err(e) = std::fs::remove_file(&tmp_path) {
-        eprintln!(
-            "warning: failed to remove temp file {}: {}",
-            tmp_path.display(),
-            e
+    }
+
+    if let Some((stats, path)) = &port_stats {
+        if let Err(e) = stats.save(path) {
+            eprintln!("warning: failed to update port stats: {:#}", e);
+        }
+    }
+
+    if let Some(report) = xml_report.take() {
+        report.finish()?;
+    }
+
+    if let Some(key) = cli.sort_by {
+        sorted_results.sort_by(|a, b| {
+            let ord = compare_results(a, b, key);
+            if cli.sort_desc { ord.reverse() } else { ord }
+        });
+        for r in &sorted_results {
+            println!("{}", human_line(r, cli.raw_banner, cli.reason));
+        }
+    }
+    if cli.matrix && !sorted_results.is_empty() {
+        match matrix::render(&sorted_results, matrix::use_color()) {
+            Some(grid) => println!("{}", grid),
+            None => {
+                eprintln!(
+                    "warning: too many hosts or ports for --matrix (at most {} ports and {} cells); listing results instead",
+                    matrix::MAX_COLUMNS,
+                    matrix::MAX_CELLS
+                );
+                sorted_results.sort_by(|a, b| a.target.cmp(&b.target).then(a.port.cmp(&b.port)));
+                for r in &sorted_results {
+                    println!("{}", human_line(r, cli.raw_banner, cli.reason));
+                }
+            }
+        }
+    }
+
+    let show_summary = cli.summary || cli.report_slowest.is_some() || cli.count_only;
+    if cli.count_only && json_out {
+        let line = serde_json::to_string(&summary)?;
+        println!("{}", if cli.json_pretty { pretty_json(&line) } else { line });
+    } else if json_out {
+        let close = if cli.json_grouped { "}" } else { "]" };
+        if show_summary {
+            json_stdout.write(&format!("{},\"summary\":{}}}", close, serde_json::to_string(&summary)?));
+        } else {
+            json_stdout.write(&format!("{}}}", close));
+        }
+        json_stdout.finish();
+    } else if show_summary {
+        println!(
+            "Summary: {} target(s), {} port(s) scanned, {} open, {} closed, {} filtered in {:.1}s",
+            summary.targets,
+            summary.ports_scanned,
+            summary.open,
+            summary.closed,
+            summary.filtered,
+            summary.duration_ms as f64 / 1000.0
         );
+        if !summary.protocol_counts.is_empty() {
+            println!("Protocols: {}", summary.protocol_census());
+        }
+        if !summary.slowest_hosts.is_empty() {
+            println!("Slowest hosts (median/avg connect latency):");
+            for h in &summary.slowest_hosts {
+                println!("  {}  {} ms / {:.1} ms over {} port(s)", h.target, h.median_ms, h.avg_ms, h.samples);
+            }
+        }
+    }
+
+    // --no-artifact: nothing was staged and no save file is written
+    if let Some(staging) = tmp_writer {
+        finalize_artifact(
+            staging,
+            tmp_path.as_deref(),
+            stream_writer.take(),
+            &cli,
+            &config_json,
+        )?;
+    }
+
+    // Last line of stderr, so scripts can pick it up with `tail -1`
+    if cli.summary_json {
+        eprintln!("{}", serde_json::to_string(&summary)?);
     }
 
     Ok(())
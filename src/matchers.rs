@@ -0,0 +1,200 @@
+//! Byte-level recognition of unprompted banners: one `BannerMatcher` per protocol, tried in the
+//! order of `BANNER_MATCHERS` (the most distinctive greetings first). Port-only guesses are not
+//! matchers; `protocols::detect_from_bytes` falls back to them when nothing here matches.
+
+use crate::protocols::{parse_irc_line, tw_contains_ci};
+use crate::types::Protocol;
+
+/// Recognizes one protocol from the first bytes a server sent.
+pub trait BannerMatcher: Sync {
+    /// The protocol and a short description of what identified it (usually the greeting line),
+    /// or None when `buf` is not this protocol. `port` is a hint only.
+    fn matches(&self, buf: &[u8], port: u16) -> Option<(Protocol, String)>;
+}
+
+/// Built-in matchers in priority order.
+pub static BANNER_MATCHERS: &[&dyn BannerMatcher] =
+    &[&SshMatcher, &SmtpMatcher, &HttpMatcher, &TelnetMatcher, &TlsMatcher, &IrcMatcher, &DnsMatcher];
+
+/// First matcher that recognizes `buf`.
+pub fn match_banner(buf: &[u8], port: u16) -> Option<(Protocol, String)> {
+    BANNER_MATCHERS.iter().find_map(|m| m.matches(buf, port))
+}
+
+// First line of `buf` as text, without the line ending
+fn first_line(buf: &[u8]) -> String {
+    let line = buf.split(|&b| b == b'\n').next().unwrap_or_default();
+    String::from_utf8_lossy(line).trim_end_matches('\r').to_string()
+}
+
+/// `SSH-2.0-OpenSSH_8.2p1 Ubuntu-4ubuntu0.5`: the identification string.
+pub struct SshMatcher;
+
+impl BannerMatcher for SshMatcher {
+    fn matches(&self, buf: &[u8], _port: u16) -> Option<(Protocol, String)> {
+        buf.starts_with(b"SSH-").then(|| (Protocol::Ssh, first_line(buf)))
+    }
+}
+
+/// `220 mail.example.org ESMTP Postfix`: the greeting text.
+pub struct SmtpMatcher;
+
+impl BannerMatcher for SmtpMatcher {
+    fn matches(&self, buf: &[u8], _port: u16) -> Option<(Protocol, String)> {
+        buf.starts_with(b"220 ").then(|| (Protocol::Smtp, first_line(&buf[4..])))
+    }
+}
+
+/// `HTTP/1.1 400 Bad Request`: the status line (servers rarely speak first, but some do on errors).
+pub struct HttpMatcher;
+
+impl BannerMatcher for HttpMatcher {
+    fn matches(&self, buf: &[u8], _port: u16) -> Option<(Protocol, String)> {
+        buf.starts_with(b"HTTP/").then(|| (Protocol::Http, first_line(buf)))
+    }
+}
+
+/// IAC option negotiation, or a login prompt.
+pub struct TelnetMatcher;
+
+impl BannerMatcher for TelnetMatcher {
+    fn matches(&self, buf: &[u8], _port: u16) -> Option<(Protocol, String)> {
+        if buf.first() == Some(&0xff) {
+            return Some((Protocol::Telnet, "option negotiation".to_string()));
+        }
+        ["login:", "username:", "password:"]
+            .into_iter()
+            .find(|prompt| tw_contains_ci(buf, prompt.as_bytes()))
+            .map(|prompt| (Protocol::Telnet, format!("{} prompt", prompt.trim_end_matches(':'))))
+    }
+}
+
+/// A TLS handshake record; servers wait for the ClientHello, so this is mostly seen in replies.
+pub struct TlsMatcher;
+
+impl BannerMatcher for TlsMatcher {
+    fn matches(&self, buf: &[u8], _port: u16) -> Option<(Protocol, String)> {
+        match buf {
+            [0x16, 0x03, minor, ..] => Some((Protocol::Tls, format!("handshake record, version 3.{}", minor))),
+            _ => None,
+        }
+    }
+}
+
+/// `:irc.example.net NOTICE * :*** Looking up your hostname...`: the server name.
+pub struct IrcMatcher;
+
+impl BannerMatcher for IrcMatcher {
+    fn matches(&self, buf: &[u8], _port: u16) -> Option<(Protocol, String)> {
+        let message = parse_irc_line(buf.split(|&b| b == b'\n').next()?)?;
+        if !message.is_server_reply() {
+            return None;
+        }
+        Some((Protocol::Irc, message.prefix.unwrap_or_else(|| "NOTICE AUTH".to_string())))
+    }
+}
+
+/// A DNS response over TCP (length prefix, then a header with the QR bit set); port 53 only,
+/// since two length bytes and a set bit say little elsewhere.
+pub struct DnsMatcher;
+
+impl BannerMatcher for DnsMatcher {
+    fn matches(&self, buf: &[u8], port: u16) -> Option<(Protocol, String)> {
+        let flags_hi = *buf.get(4)?;
+        (port == 53 && buf.len() >= 14 && flags_hi & 0x80 != 0).then(|| (Protocol::Dns, "response".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ssh() {
+        let banner = b"SSH-2.0-OpenSSH_8.2p1 Ubuntu-4ubuntu0.5\r\n";
+        assert_eq!(
+            SshMatcher.matches(banner, 22),
+            Some((Protocol::Ssh, "SSH-2.0-OpenSSH_8.2p1 Ubuntu-4ubuntu0.5".to_string()))
+        );
+        assert_eq!(SshMatcher.matches(b"ssh-2.0-dropbear\r\n", 22), None);
+    }
+
+    #[test]
+    fn smtp() {
+        assert_eq!(
+            SmtpMatcher.matches(b"220 mail.example.org ESMTP Postfix\r\n", 25),
+            Some((Protocol::Smtp, "mail.example.org ESMTP Postfix".to_string()))
+        );
+        // Multi-line greetings continue with "220-"; FTP shares the code but not this form
+        assert_eq!(SmtpMatcher.matches(b"220-mail.example.org ESMTP\r\n", 25), None);
+    }
+
+    #[test]
+    fn http() {
+        assert_eq!(
+            HttpMatcher.matches(b"HTTP/1.1 400 Bad Request\r\nServer: nginx\r\n\r\n", 80),
+            Some((Protocol::Http, "HTTP/1.1 400 Bad Request".to_string()))
+        );
+        assert_eq!(HttpMatcher.matches(b"GET / HTTP/1.1\r\n", 80), None);
+    }
+
+    #[test]
+    fn telnet() {
+        // IAC DO TERMINAL-TYPE, as sent by a BusyBox telnetd
+        assert_eq!(
+            TelnetMatcher.matches(&[0xff, 0xfd, 0x18], 23),
+            Some((Protocol::Telnet, "option negotiation".to_string()))
+        );
+        assert_eq!(
+            TelnetMatcher.matches(b"\r\nrouter Login: ", 23),
+            Some((Protocol::Telnet, "login prompt".to_string()))
+        );
+        assert_eq!(TelnetMatcher.matches(b"Please log in\r\n", 23), None);
+    }
+
+    #[test]
+    fn tls() {
+        assert_eq!(
+            TlsMatcher.matches(&[0x16, 0x03, 0x03, 0x00, 0x55, 0x02], 443),
+            Some((Protocol::Tls, "handshake record, version 3.3".to_string()))
+        );
+        // An alert record, not a handshake
+        assert_eq!(TlsMatcher.matches(&[0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x28], 443), None);
+    }
+
+    #[test]
+    fn irc() {
+        assert_eq!(
+            IrcMatcher.matches(b":irc.example.net NOTICE * :*** Looking up your hostname...\r\n", 6667),
+            Some((Protocol::Irc, "irc.example.net".to_string()))
+        );
+        assert_eq!(
+            IrcMatcher.matches(b"NOTICE AUTH :*** Processing connection\r\n", 6667),
+            Some((Protocol::Irc, "NOTICE AUTH".to_string()))
+        );
+        // A client command, not a server reply
+        assert_eq!(IrcMatcher.matches(b"NICK guest\r\n", 6667), None);
+    }
+
+    #[test]
+    fn dns() {
+        // Length prefix, then a response header (QR set) for one question
+        let response = [0x00, 0x1d, 0x12, 0x34, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00];
+        assert_eq!(DnsMatcher.matches(&response, 53), Some((Protocol::Dns, "response".to_string())));
+        assert_eq!(DnsMatcher.matches(&response, 5353), None);
+        // A query (QR clear) on the DNS port
+        let query = [0x00, 0x1d, 0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        assert_eq!(DnsMatcher.matches(&query, 53), None);
+    }
+
+    #[test]
+    fn registry_order_wins_over_port() {
+        // The greeting decides, not the port it came from
+        let (proto, _) = match_banner(b"SSH-2.0-OpenSSH_9.6\r\n", 80).unwrap();
+        assert_eq!(proto, Protocol::Ssh);
+        // A login prompt that also looks like an SMTP greeting: SMTP is tried first
+        let (proto, _) = match_banner(b"220 welcome, login: ", 23).unwrap();
+        assert_eq!(proto, Protocol::Smtp);
+        assert_eq!(match_banner(b"\x00\x01random", 80), None);
+    }
+}
@@ -0,0 +1,86 @@
+//! `--matrix`: all results as one grid, hosts as rows and ports as columns, for small interactive
+//! scans where a list is harder to take in.
+//!
+//! ```text
+//!               22    80   443
+//! 10.0.0.1       ●     ●     ○
+//! 10.0.0.2       ○     ·     ·
+//! ● open  ○ closed  · filtered
+//! ```
+
+use ospine::ScanResult;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::IsTerminal;
+
+// Larger grids no longer fit a terminal; the caller falls back to the list
+pub const MAX_COLUMNS: usize = 64;
+pub const MAX_CELLS: usize = 4096;
+
+const OPEN: &str = "●";
+const CLOSED: &str = "○";
+const FILTERED: &str = "·";
+
+/// The grid for `results`, or None when it would exceed `MAX_COLUMNS` ports or `MAX_CELLS` cells.
+/// Ports a host has no result for stay blank.
+pub fn render(results: &[ScanResult], color: bool) -> Option<String> {
+    let ports: BTreeSet<u16> = results.iter().map(|r| r.port).collect();
+    let mut rows: BTreeMap<String, BTreeMap<u16, &ScanResult>> = BTreeMap::new();
+    for r in results {
+        let host = match &r.resolved_addr {
+            Some(addr) => format!("{}[{}]", r.target, addr),
+            None => r.target.clone(),
+        };
+        rows.entry(host).or_default().insert(r.port, r);
+    }
+    if ports.len() > MAX_COLUMNS || ports.len() * rows.len() > MAX_CELLS {
+        return None;
+    }
+
+    let host_width = rows.keys().map(|h| h.chars().count()).max().unwrap_or(0);
+    let cell_width = ports.iter().map(|p| p.to_string().len()).max().unwrap_or(1) + 1;
+    let mut out = format!("{:host_width$}", "");
+    for port in &ports {
+        out.push_str(&format!("{:>cell_width$}", port));
+    }
+    out.push('\n');
+    for (host, cells) in &rows {
+        out.push_str(&format!("{:host_width$}", host));
+        for port in &ports {
+            let glyph = cells.get(port).map_or(" ", |r| glyph(r));
+            out.push_str(&" ".repeat(cell_width - 1));
+            out.push_str(&paint(glyph, color));
+        }
+        out.push('\n');
+    }
+    out.push_str(&format!(
+        "{} open  {} closed  {} filtered",
+        paint(OPEN, color),
+        paint(CLOSED, color),
+        paint(FILTERED, color)
+    ));
+    Some(out)
+}
+
+/// Color only for a terminal, and never with NO_COLOR set (https://no-color.org).
+pub fn use_color() -> bool {
+    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").filter(|v| !v.is_empty()).is_none()
+}
+
+// Closed means refused, as in the summary; everything else that is not open counts as filtered
+fn glyph(r: &ScanResult) -> &'static str {
+    match (r.open, r.reason.as_str()) {
+        (true, _) => OPEN,
+        (false, "conn-refused") => CLOSED,
+        (false, _) => FILTERED,
+    }
+}
+
+fn paint(glyph: &str, color: bool) -> String {
+    let code = match glyph {
+        OPEN => "32",
+        CLOSED => "2",
+        FILTERED => "33",
+        _ => return glyph.to_string(),
+    };
+    if color { format!("\x1b[{}m{}\x1b[0m", code, glyph) } else { glyph.to_string() }
+}
@@ -0,0 +1,265 @@
+//! Results on stdout: human-readable lines, the JSON document, and `--classify-file` reports.
+
+use crate::baseline::Change;
+use crate::cli::{banner_encoding, Cli, SortKey};
+use anyhow::Result;
+use ospine::{classify_capture, render_banner_decoded, ProbeSet, ScanResult};
+use std::cmp::Ordering;
+use std::io::{self, Write};
+use std::path::Path;
+
+// One human-readable output line: "target:port state [protocol] — banner"
+pub fn human_line(r: &ScanResult, raw_banner: bool, reason: bool) -> String {
+    let status = if r.open { "open" } else { "closed" };
+    let mut line = match &r.resolved_addr {
+        Some(addr) => format!("{}[{}]:{} {}", r.target, addr, r.port, status),
+        None => format!("{}:{} {}", r.target, r.port, status),
+    };
+    if reason {
+        line.push_str(&format!(" ({})", r.reason));
+    }
+    if let Some(label) = &r.label {
+        line.push_str(&format!(" {{{}}}", label));
+    }
+    if let Some(group) = &r.group {
+        line.push_str(&format!(" (group: {})", group));
+    }
+    if let Some(proto) = &r.protocol {
+        line.push_str(&format!(" [{}]", proto));
+    }
+    if r.reset_after_connect {
+        line.push_str(" (reset after connect)");
+    }
+    // --reason already says so
+    if r.is_hollow() && !reason {
+        line.push_str(" (hollow: closed without sending data)");
+    }
+    match r.starttls {
+        Some(true) => line.push_str(" (starttls ok)"),
+        Some(false) => line.push_str(" (starttls failed)"),
+        None => {}
+    }
+    if r.websocket == Some(true) {
+        line.push_str(" (websocket)");
+    }
+    if let Some(url) = &r.redirect {
+        line.push_str(&format!(" (-> {})", url));
+    }
+    if let Some(alpn) = r.service.as_ref().and_then(|s| s.alpn.as_ref()) {
+        line.push_str(&format!(" (alpn {})", alpn));
+    }
+    if r.banner_budget_spent() {
+        line.push_str(" (banner budget exhausted)");
+    }
+    if let Some(n) = r.reprobes {
+        line.push_str(&format!(" (reprobed {}x)", n));
+    }
+    if let Some(hint) = &r.os_hint {
+        line.push_str(&format!(" (os: {})", hint));
+    }
+    if let Some(change) = r.change.as_deref().filter(|c| *c != Change::Unchanged.as_str()) {
+        line.push_str(&format!(" <{}>", change));
+    }
+    if let Some(banner) = &r.banner {
+        if raw_banner {
+            let safe = escape_nonprintable(banner);
+            line.push_str(&format!(" — {}", safe));
+        }
+    }
+    line
+}
+
+// One target's entry for --json-grouped, `"<target>":{"ports":{"<port>":{...}}}`, ports in order;
+// target and port are the keys, so they are dropped from each result. None for an empty list.
+// Each address of a hostname scanned with --all-addresses is its own `<target>[<address>]` key.
+pub fn grouped_host_json(list: &mut [ScanResult]) -> Result<Option<String>> {
+    let Some(target) = list.first().map(|r| match &r.resolved_addr {
+        Some(addr) => format!("{}[{}]", r.target, addr),
+        None => r.target.clone(),
+    }) else {
+        return Ok(None);
+    };
+    list.sort_by_key(|r| r.port);
+    let mut ports = Vec::with_capacity(list.len());
+    for r in list.iter() {
+        let mut value = serde_json::to_value(r)?;
+        if let Some(fields) = value.as_object_mut() {
+            fields.remove("target");
+            fields.remove("port");
+        }
+        ports.push(format!("\"{}\":{}", r.port, value));
+    }
+    Ok(Some(format!("{}:{{\"ports\":{{{}}}}}", serde_json::to_string(&target)?, ports.join(","))))
+}
+
+// Re-indent compact JSON two spaces per level, as `serde_json::to_string_pretty` would, keeping
+// the field order (going through `Value` would sort the keys).
+pub fn pretty_json(compact: &str) -> String {
+    let mut out = String::with_capacity(compact.len() * 2);
+    let mut depth = 0usize;
+    let (mut in_string, mut escaped) = (false, false);
+    let mut chars = compact.chars().peekable();
+    let newline = |out: &mut String, depth: usize| {
+        out.push('\n');
+        out.push_str(&"  ".repeat(depth));
+    };
+    while let Some(c) = chars.next() {
+        if in_string {
+            out.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                out.push(c);
+            }
+            '{' | '[' => {
+                out.push(c);
+                // Empty containers stay on one line
+                if matches!(chars.peek(), Some('}' | ']')) {
+                    out.extend(chars.next());
+                } else {
+                    depth += 1;
+                    newline(&mut out, depth);
+                }
+            }
+            '}' | ']' => {
+                depth = depth.saturating_sub(1);
+                newline(&mut out, depth);
+                out.push(c);
+            }
+            ',' => {
+                out.push(c);
+                newline(&mut out, depth);
+            }
+            ':' => out.push_str(": "),
+            c if c.is_whitespace() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// The JSON document on stdout: streamed as it is produced, or held for --json-pretty and
+// --json-safe and printed whole by `finish`.
+pub struct JsonStdout {
+    held: Option<String>,
+    pretty: bool,
+}
+
+impl JsonStdout {
+    pub fn new(cli: &Cli) -> Self {
+        let hold = cli.json_pretty || cli.json_safe;
+        Self { held: hold.then(String::new), pretty: cli.json_pretty }
+    }
+
+    pub fn write(&mut self, fragment: &str) {
+        match self.held.as_mut() {
+            Some(buf) => buf.push_str(fragment),
+            None => {
+                print!("{}", fragment);
+                io::stdout().flush().ok();
+            }
+        }
+    }
+
+    pub fn finish(self) {
+        match self.held {
+            Some(buf) if self.pretty => println!("{}", pretty_json(&buf)),
+            Some(buf) => println!("{}", buf),
+            None => println!(),
+        }
+    }
+}
+
+// --classify-file: report what a scan would say about previously captured bytes, without connecting.
+pub fn classify_file(path: &Path, port: Option<u16>, probes: &ProbeSet, cli: &Cli) -> Result<()> {
+    let buf = std::fs::read(path).map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+    let port = port.or_else(|| capture_port(path)).unwrap_or(0);
+    let protocol = classify_capture(&buf, port, probes);
+    let (banner, banner_is_binary) = render_banner_decoded(&buf, cli.banner_max_chars, cli.banner_hex, banner_encoding(cli));
+    if cli.json || cli.json_pretty || cli.json_safe {
+        let out = serde_json::json!({
+            "file": path.display().to_string(),
+            "port": port,
+            "protocol": protocol,
+            "banner": banner,
+            "banner_is_binary": banner_is_binary,
+        });
+        if cli.json_pretty { println!("{:#}", out); } else { println!("{}", out); }
+    } else {
+        let mut line = format!("{} (port {}) [{}]", path.display(), port, protocol);
+        if cli.raw_banner {
+            line.push_str(&format!(" — {}", escape_nonprintable(&banner)));
+        }
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+// Port from a capture file name such as `10.0.0.1_443.recv`.
+fn capture_port(path: &Path) -> Option<u16> {
+    let stem = path.file_stem()?.to_str()?;
+    stem.rsplit_once('_')?.1.parse().ok()
+}
+
+// Order for --sort-by; ties fall back to (target, port), and missing values sort last.
+pub fn compare_results(a: &ScanResult, b: &ScanResult, key: SortKey) -> Ordering {
+    let by_target = a.target.cmp(&b.target).then(a.port.cmp(&b.port));
+    match key {
+        SortKey::Target => by_target,
+        SortKey::Port => a.port.cmp(&b.port).then(a.target.cmp(&b.target)),
+        SortKey::Protocol => {
+            let name = |r: &ScanResult| r.protocol.as_ref().map(|p| p.to_string());
+            cmp_missing_last(name(a), name(b)).then(by_target)
+        }
+        SortKey::Latency => cmp_missing_last(a.latency_ms, b.latency_ms).then(by_target),
+    }
+}
+
+fn cmp_missing_last<T: Ord>(a: Option<T>, b: Option<T>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+// Escape control characters so untrusted banners can't manipulate the terminal.
+// - Converts '\\n', '\\r', and '\\t' into visible sequences ("\\n", "\\r", "\\t").
+// - Converts other control bytes (including ESC) to hex escapes like "\\x1b".
+// - Leaves printable Unicode characters as-is.
+fn escape_nonprintable(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '\n' => { out.push('\\'); out.push('n'); }
+            '\r' => { out.push('\\'); out.push('r'); }
+            '\t' => { out.push('\\'); out.push('t'); }
+            c if c.is_control() => {
+                // Render as \xNN for BMP control chars
+                let v = c as u32;
+                if v <= 0xFF {
+                    use std::fmt::Write as _;
+                    out.push('\\'); out.push('x');
+                    let _ = write!(&mut out, "{v:02x}");
+                } else {
+                    // Fallback for any odd control-like codepoints
+                    use std::fmt::Write as _;
+                    out.push('\\'); out.push('u'); out.push('{');
+                    let _ = write!(&mut out, "{v:x}");
+                    out.push('}');
+                }
+            }
+            _ => out.push(ch),
+        }
+    }
+    out
+}
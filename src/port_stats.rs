@@ -0,0 +1,102 @@
+//! Per-port open counts learned across scans, for `--smart-order`: ports that were open most often
+//! are scanned first. The cache starts empty (every port equally likely) and is updated when a scan
+//! ends.
+//!
+//! Format, one port per line (`#` starts a comment):
+//!
+//! ```text
+//! # port scanned open
+//! 22 140 97
+//! 443 140 61
+//! ```
+
+use anyhow::{anyhow, Context, Result};
+use ospine::ScanResult;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default)]
+pub struct PortStats {
+    // Port -> (times scanned, times found open)
+    counts: BTreeMap<u16, (u64, u64)>,
+}
+
+impl PortStats {
+    /// `$XDG_CACHE_HOME/ospine/port-stats`, or `~/.cache/ospine/port-stats`.
+    pub fn default_path() -> Option<PathBuf> {
+        let cache = std::env::var_os("XDG_CACHE_HOME")
+            .filter(|d| !d.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").filter(|d| !d.is_empty()).map(|h| PathBuf::from(h).join(".cache")))?;
+        Some(cache.join("ospine").join("port-stats"))
+    }
+
+    /// Read the cache; a missing file is an empty one.
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(src) => Self::parse(&src).with_context(|| format!("invalid port stats cache {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read port stats cache {}", path.display())),
+        }
+    }
+
+    pub fn parse(src: &str) -> Result<Self> {
+        let mut out = Self::default();
+        for (idx, raw) in src.lines().enumerate() {
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let lineno = idx + 1;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [port, scanned, open] = fields[..] else {
+                return Err(anyhow!("line {lineno}: expected `<port> <scanned> <open>`"));
+            };
+            let port: u16 = port.parse().map_err(|_| anyhow!("line {lineno}: invalid port `{port}`"))?;
+            let scanned: u64 = scanned.parse().map_err(|_| anyhow!("line {lineno}: invalid count `{scanned}`"))?;
+            let open: u64 = open.parse().map_err(|_| anyhow!("line {lineno}: invalid count `{open}`"))?;
+            out.counts.insert(port, (scanned, open.min(scanned)));
+        }
+        Ok(out)
+    }
+
+    /// Count a target's results; ports whose scan failed locally say nothing about the port.
+    pub fn record(&mut self, results: &[ScanResult]) {
+        for r in results.iter().filter(|r| r.reason != "error") {
+            let (scanned, open) = self.counts.entry(r.port).or_default();
+            *scanned += 1;
+            *open += u64::from(r.open);
+        }
+    }
+
+    /// Most-likely-open first. The estimate is smoothed, (open + 1) / (scanned + 2), so a port never
+    /// seen sits between ones that are usually open and ones that are usually closed; ties keep
+    /// their order.
+    pub fn order(&self, ports: &mut [u16]) {
+        let score = |port: &u16| {
+            let (scanned, open) = self.counts.get(port).copied().unwrap_or_default();
+            (open + 1) as f64 / (scanned + 2) as f64
+        };
+        ports.sort_by(|a, b| score(b).total_cmp(&score(a)));
+    }
+
+    /// Write the cache, replacing the previous one in a single rename.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create cache directory {}", dir.display()))?;
+        }
+        let tmp = path.with_extension("tmp");
+        let mut out = std::io::BufWriter::new(
+            std::fs::File::create(&tmp).with_context(|| format!("failed to write {}", tmp.display()))?,
+        );
+        writeln!(out, "# port scanned open")?;
+        for (port, (scanned, open)) in &self.counts {
+            writeln!(out, "{} {} {}", port, scanned, open)?;
+        }
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(&tmp, path).with_context(|| format!("failed to replace {}", path.display()))?;
+        Ok(())
+    }
+}
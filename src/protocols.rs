@@ -1,143 +1,742 @@
-use crate::types::Protocol;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tokio::time::{timeout, Duration};
+use crate::capture::ProbeStream;
+use crate::fingerprint::service_info;
+use crate::matchers::match_banner;
+use crate::service_probes::{ProbeSet, ServiceProbe};
+use crate::tls;
+use crate::types::{ByteBudget, HttpMethod, Protocol, ReadStrategy, ScanConfig, ServiceInfo, TlsSession};
+use encoding_rs::{Encoding, UTF_8};
+use std::io::ErrorKind;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::{timeout, Duration, Instant};
+
+/// What protocol identification learned about an open port.
+#[derive(Debug, Default)]
+pub struct Identification {
+    pub protocol: Option<Protocol>,
+    pub banner: Option<String>,
+    /// Telnet options the server negotiated (e.g. "WILL ECHO"), when it spoke Telnet
+    pub telnet_options: Option<Vec<String>>,
+    /// HTML `<title>` of the page fetched by a GET probe
+    pub http_title: Option<String>,
+    /// True when `banner` is a hexdump of binary data rather than text
+    pub banner_is_binary: bool,
+    /// The peer accepted the connection but reset it as soon as we read or probed
+    pub reset_after_connect: bool,
+    /// Outcome of the STARTTLS upgrade, when `--starttls` applied to the detected protocol
+    pub starttls: Option<bool>,
+    /// Whether the HTTP service accepted a WebSocket upgrade (only set with `--ws-probe`)
+    pub websocket: Option<bool>,
+    /// Details parsed from the response bytes, when the banner was rendered from them
+    pub service: Option<ServiceInfo>,
+    /// URL of the redirect the HTTP probe followed (only set with `--follow-redirect`)
+    pub redirect: Option<String>,
+}
+
+/// Why a read or probe produced no identification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProbeError {
+    /// No data, a timeout, or a response the probe does not recognize
+    NoMatch,
+    /// The peer reset the connection; further probes on this stream are pointless
+    Reset,
+    /// A probe's write or flush timed out or stopped part way, so the peer may still be reading it;
+    /// any later probe's reply could be mixed up with it, so none is sent on this stream
+    Broken,
+    /// The run's `--max-total-bytes` budget is spent; no more is read anywhere
+    OutOfBudget,
+}
+
+impl Identification {
+    fn new(protocol: Protocol, banner: Option<String>) -> Self {
+        Self { protocol: Some(protocol), banner, ..Self::default() }
+    }
+
+    // Banner rendered from raw response bytes (text, hexdump or hex, see `render_banner`)
+    fn from_bytes(protocol: Protocol, buf: &[u8], cfg: &ScanConfig) -> Self {
+        let encoding = cfg.banner_encoding.unwrap_or(UTF_8);
+        let (banner, banner_is_binary) = render_banner_decoded(buf, cfg.banner_max_chars, cfg.banner_hex, encoding);
+        let service = Some(service_info(&protocol, buf));
+        Self { banner_is_binary, service, ..Self::new(protocol, Some(banner)) }
+    }
+
+    fn reset() -> Self {
+        Self { reset_after_connect: true, ..Self::new(Protocol::Unknown, None) }
+    }
+
+    // Outcome of one probe: a final identification, or None to try the next probe. A broken
+    // stream ends probing with what the bytes received so far say (see `partial_identification`).
+    fn settle(res: Result<Self, ProbeError>, stream: &ProbeStream, port: u16, cfg: &ScanConfig) -> Option<Self> {
+        match res {
+            Ok(ident) => Some(ident),
+            Err(ProbeError::Reset) => Some(Self::reset()),
+            Err(ProbeError::Broken | ProbeError::OutOfBudget) => {
+                Some(partial_identification(stream.received_head(), port, cfg))
+            }
+            Err(ProbeError::NoMatch) => None,
+        }
+    }
+}
 
 /// Try to identify protocol and obtain a banner by passively reading first,
-/// then sending light probes (HTTP HEAD, Telnet CRLF, TLS ClientHello) when appropriate.
-pub async fn identify_and_banner(
-    stream: &mut TcpStream,
-    port: u16,
-    max_bytes: usize,
-    op_timeout: Duration,
-    passive: bool,
-) -> (Option<Protocol>, Option<String>) {
+/// then sending light probes (HTTP HEAD, Telnet CRLF, TLS ClientHello; see `builtin_probe_order`),
+/// and finally the data-driven probes from `probes` in rarity order. Probing stops early when a
+/// probe could not be sent whole, since later replies could no longer be told apart.
+pub async fn identify_and_banner(stream: &mut ProbeStream, port: u16, cfg: &ScanConfig) -> Identification {
+    let max_bytes = cfg.banner_read_len;
+    let op_timeout = cfg.timeout;
+    let probes: &ProbeSet = &cfg.probes;
+
     // First, try to read any immediate banner without sending data (e.g., SSH, SMTP, Telnet IAC)
-    match read_some(stream, max_bytes, op_timeout).await {
+    match read_some(stream, max_bytes, op_timeout, cfg.read_strategy, cfg.byte_budget.as_deref()).await {
         Ok(buf) if !buf.is_empty() => {
-            let (proto, banner) = detect_from_bytes(&buf, port);
-            let banner_s = to_safe_string(&buf);
-            return (Some(proto), Some(banner.unwrap_or(banner_s)));
+            let proto = classify_banner(&buf, port, probes);
+            if let Protocol::Telnet = proto {
+                return telnet_identification(&buf, cfg);
+            }
+            // An IRC greeting only names the server; registering also gets its version
+            if matches!(proto, Protocol::Irc) && !cfg.passive {
+                if let Ok(ident) = irc_register(stream, port, cfg, buf.clone()).await {
+                    return ident;
+                }
+            }
+            let upgrade = if cfg.starttls && !cfg.passive {
+                starttls_upgrade(stream, port, cfg, &buf).await
+            } else {
+                None
+            };
+            let mut ident = Identification::from_bytes(proto, &buf, cfg);
+            ident.starttls = upgrade.as_ref().map(Option::is_some);
+            if let Some(service) = ident.service.as_mut() {
+                service.tls = upgrade.flatten();
+            }
+            return ident;
         }
+        // Accepted, then reset straight away: open, and itself a fingerprint (tarpits, filters)
+        Err(ProbeError::Reset) => return Identification::reset(),
+        Err(ProbeError::OutOfBudget) => return Identification::new(Protocol::Unknown, None),
         _ => {}
     }
 
     // If nothing came in, optionally try protocol-specific probes unless passive mode is enabled
-    if passive {
+    if cfg.passive {
         // In passive mode, we do not send any bytes. Report unknown/open with no banner.
-        return (Some(Protocol::Unknown), None);
+        return Identification::new(Protocol::Unknown, None);
     }
 
-    // Active probing path (not in passive mode)
-    // 0) DNS-over-TCP probe if port suggests DNS
-    if port == 53 {
-        if let Ok((proto, banner)) = dns_probe(stream, max_bytes, op_timeout).await {
-            return (Some(proto), banner);
+    // Active probing path (not in passive mode): built-in probes, most likely for the port first,
+    // then the data-driven ones, together capped by --max-probes
+    let mut budget = cfg.max_probes.unwrap_or(usize::MAX);
+    for probe in builtin_probe_order(port) {
+        if budget == 0 {
+            return Identification::new(Protocol::Unknown, None);
+        }
+        budget -= 1;
+        let res = match probe {
+            BuiltinProbe::Dns => dns_probe(stream, port, cfg)
+                .await
+                .map(|(proto, banner)| Identification::new(proto, banner)),
+            BuiltinProbe::DnsOverTls => dot_probe(stream, port, cfg).await,
+            BuiltinProbe::Epmap => epmap_probe(stream, port, cfg).await,
+            BuiltinProbe::Smb => smb_probe(stream, port, cfg).await,
+            BuiltinProbe::Amqp => amqp_probe(stream, port, cfg).await,
+            BuiltinProbe::Irc => irc_probe(stream, port, cfg).await,
+            BuiltinProbe::Finger => finger_probe(stream, port, cfg).await,
+            BuiltinProbe::Gopher => gopher_probe(stream, port, cfg).await,
+            BuiltinProbe::Tds => tds_probe(stream, port, cfg).await,
+            BuiltinProbe::ContainerApi => container_api_probe(stream, port, cfg).await,
+            BuiltinProbe::Http => http_probe(stream, port, cfg).await,
+            BuiltinProbe::Telnet => telnet_probe(stream, port, cfg).await,
+            BuiltinProbe::Tls => tls_probe(stream, port, cfg).await,
+        };
+        if let Some(ident) = Identification::settle(res, stream, port, cfg) {
+            return ident;
         }
     }
 
-    // 1) HTTP probe
-    if let Ok((proto, banner)) = http_probe(stream, max_bytes, op_timeout).await {
-        return (Some(proto), banner);
+    // Then the data-driven probes for this port, in rarity order
+    for probe in probes.active_for_port(port).into_iter().take(budget) {
+        let res = file_probe(stream, port, probe, cfg).await;
+        if let Some(ident) = Identification::settle(res, stream, port, cfg) {
+            return ident;
+        }
     }
 
-    // 2) Telnet probe (send CRLF to coax a login/banner)
-    if let Ok((proto, banner)) = telnet_probe(stream, max_bytes, op_timeout).await {
-        return (Some(proto), banner);
-    }
+    // If still unknown, mark as open/unknown without banner
+    Identification::new(Protocol::Unknown, None)
+}
 
-    // 3) TLS probe (may succeed on TLS services like HTTPS, SMTPS, etc.)
-    if let Ok((proto, banner)) = tls_probe(stream, max_bytes, op_timeout).await {
-        return (Some(proto), banner);
+/// What `identify_and_banner` would have reported from `buf` alone, for a probe sequence cut off
+/// by `--total-timeout`: the bytes received so far, classified, or an unknown service without a
+/// banner when nothing arrived.
+pub(crate) fn partial_identification(buf: &[u8], port: u16, cfg: &ScanConfig) -> Identification {
+    if buf.is_empty() {
+        return Identification::new(Protocol::Unknown, None);
     }
+    Identification::from_bytes(classify_banner(buf, port, &cfg.probes), buf, cfg)
+}
 
-    // If still unknown, mark as open/unknown without banner
-    (Some(Protocol::Unknown), None)
+/// Built-in active probes tried by `identify_and_banner` (see `builtin_probe_order`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BuiltinProbe {
+    Dns,
+    DnsOverTls,
+    Epmap,
+    Smb,
+    Amqp,
+    Irc,
+    Finger,
+    Gopher,
+    Tds,
+    ContainerApi,
+    Http,
+    Telnet,
+    Tls,
+}
+
+// Probes for `port`, the likeliest first so the common case costs one round trip. DNS,
+// DNS-over-TLS, MSRPC, SMB, AMQP, IRC, Finger, Gopher, MSSQL and the container APIs only run on their own ports (too expensive or too odd to try
+// elsewhere); HTTP, Telnet and TLS run everywhere, with the one the port suggests moved to the front.
+fn builtin_probe_order(port: u16) -> Vec<BuiltinProbe> {
+    let mut order = match port {
+        53 => vec![BuiltinProbe::Dns],
+        // Before the plain TLS probe: its ClientHello would use up the only handshake we get
+        853 => vec![BuiltinProbe::DnsOverTls],
+        135 => vec![BuiltinProbe::Epmap],
+        139 | 445 => vec![BuiltinProbe::Smb],
+        5672 => vec![BuiltinProbe::Amqp],
+        79 => vec![BuiltinProbe::Finger],
+        70 => vec![BuiltinProbe::Gopher],
+        // 1434 is the dedicated admin connection, which speaks TDS too
+        1433 | 1434 => vec![BuiltinProbe::Tds],
+        // 6697 is IRC over TLS, so it also goes before the plain TLS probe
+        6660..=6669 | 6697 => vec![BuiltinProbe::Irc],
+        // TLS on all but 2375, so this too must run before the plain TLS probe's ClientHello
+        2375 | 2376 | 6443 | 10250 => vec![BuiltinProbe::ContainerApi],
+        _ => Vec::new(),
+    };
+    let likely = match port {
+        443 | 465 | 636 | 990 | 993 | 995 | 5061 | 8443 => Some(BuiltinProbe::Tls),
+        23 | 2323 => Some(BuiltinProbe::Telnet),
+        _ => None,
+    };
+    order.extend(likely);
+    order.extend(
+        [BuiltinProbe::Http, BuiltinProbe::Telnet, BuiltinProbe::Tls]
+            .into_iter()
+            .filter(|p| Some(*p) != likely),
+    );
+    order
 }
 
-fn detect_from_bytes(buf: &[u8], port_hint: u16) -> (Protocol, Option<String>) {
-    // SSH servers send something like: SSH-2.0-OpenSSH_8.2p1 Ubuntu-4ubuntu0.5\r\n
-    if buf.starts_with(b"SSH-") {
-        return (Protocol::Ssh, Some(to_safe_string(buf)));
+/// Protocol reported for a banner the server sent unprompted: the built-in heuristics, then the
+/// data-driven probes with an empty payload. Pure function of its inputs; no network involved.
+pub fn classify_banner(buf: &[u8], port_hint: u16, probes: &ProbeSet) -> Protocol {
+    match detect_from_bytes(buf, port_hint) {
+        Protocol::Unknown => probes
+            .match_banner(buf, port_hint)
+            .map(Protocol::from_service_name)
+            .unwrap_or(Protocol::Unknown),
+        known => known,
     }
-    // SMTP often starts with "220 <banner>\r\n"
-    if buf.starts_with(b"220 ") {
-        return (Protocol::Smtp, Some(to_safe_string(buf)));
+}
+
+/// Offline classification of captured bytes (`--classify-file`). Like `classify_banner`, but the
+/// bytes may answer any probe, so every probe for the port is matched, not just the passive ones.
+pub fn classify_capture(buf: &[u8], port_hint: u16, probes: &ProbeSet) -> Protocol {
+    match classify_banner(buf, port_hint, probes) {
+        Protocol::Unknown => probes
+            .match_any(buf, port_hint)
+            .map(Protocol::from_service_name)
+            .unwrap_or(Protocol::Unknown),
+        known => known,
     }
-    // HTTP responses start with HTTP/1.x or HTTP/2 preface (unlikely without client preface)
-    if buf.starts_with(b"HTTP/") {
-        return (Protocol::Http, Some(to_safe_string(buf)));
+}
+
+/// Built-in byte-level heuristics: the `BannerMatcher` registry, then well-known port hints.
+pub fn detect_from_bytes(buf: &[u8], port_hint: u16) -> Protocol {
+    if let Some((proto, _)) = match_banner(buf, port_hint) {
+        return proto;
     }
-    // Telnet often emits IAC (0xFF) negotiations or login prompts
-    if buf.first() == Some(&0xff)
-        || tw_contains_ci(buf, b"login:")
-        || tw_contains_ci(buf, b"username:")
-        || tw_contains_ci(buf, b"password:")
-    {
-        return (Protocol::Telnet, Some(to_safe_string(buf)));
+    // Heuristics by port
+    match port_hint {
+        80 | 8080 | 8000 | 8888 => Protocol::Http,
+        443 | 8443 => Protocol::Https,
+        22 => Protocol::Ssh,
+        23 => Protocol::Telnet,
+        25 | 587 | 465 => Protocol::Smtp,
+        53 => Protocol::Dns,
+        _ => Protocol::Unknown,
     }
-    // TLS servers typically wait for ClientHello; but if we received TLS alert or handshake, detect
-    if buf.get(0) == Some(&0x16) && matches!(buf.get(1), Some(b) if *b == 0x03) {
-        return (Protocol::Tls, Some(hex_preview(buf)));
+}
+
+// Read a banner or reply of at most `max_bytes`, waiting up to `op_timeout` for the first byte.
+// With `ReadStrategy::UntilIdle`, reading continues until the peer pauses for the idle time
+// (bounded by a further `op_timeout`), so banners spread over several segments arrive whole.
+// What is read counts against `budget` (`--max-total-bytes`); once that is spent, nothing more is read.
+async fn read_some<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    max_bytes: usize,
+    op_timeout: Duration,
+    strategy: ReadStrategy,
+    budget: Option<&ByteBudget>,
+) -> Result<Vec<u8>, ProbeError> {
+    if budget.is_some_and(|b| b.exhausted()) {
+        return Err(ProbeError::OutOfBudget);
     }
-    // DNS over TCP likely starts with 2-byte length prefix, then 12-byte header where QR bit may be 1 in responses.
-    if buf.len() >= 14 {
-        let header_start = 2; // after TCP length prefix
-        let flags_hi = buf.get(header_start + 2).copied().unwrap_or(0);
-        let is_response = (flags_hi & 0x80) != 0; // QR bit
-        if is_response && port_hint == 53 {
-            return (Protocol::Dns, Some(hex_preview(buf)));
+    let mut buf = vec![0u8; max_bytes];
+    let mut filled = match timeout(op_timeout, stream.read(&mut buf)).await {
+        Ok(Ok(n)) if n > 0 => n,
+        Ok(Err(e)) if e.kind() == ErrorKind::ConnectionReset => return Err(ProbeError::Reset),
+        _ => return Err(ProbeError::NoMatch),
+    };
+    if let ReadStrategy::UntilIdle(idle) = strategy {
+        let deadline = Instant::now() + op_timeout;
+        while filled < max_bytes {
+            let wait = idle.min(deadline.saturating_duration_since(Instant::now()));
+            match timeout(wait, stream.read(&mut buf[filled..])).await {
+                Ok(Ok(n)) if n > 0 => filled += n,
+                // Idle, closed, or reset: what arrived so far is the banner
+                _ => break,
+            }
         }
     }
-    // Heuristics by port
-    match port_hint {
-        80 | 8080 | 8000 | 8888 => (Protocol::Http, None),
-        443 | 8443 => (Protocol::Https, None),
-        22 => (Protocol::Ssh, None),
-        23 => (Protocol::Telnet, None),
-        25 | 587 | 465 => (Protocol::Smtp, None),
-        53 => (Protocol::Dns, None),
-        _ => (Protocol::Unknown, None),
+    buf.truncate(filled);
+    if let Some(budget) = budget {
+        budget.record(filled);
     }
+    Ok(buf)
 }
 
-async fn read_some(stream: &mut TcpStream, max_bytes: usize, op_timeout: Duration) -> Result<Vec<u8>, ()> {
-    let mut buf = vec![0u8; max_bytes];
-    match timeout(op_timeout, stream.read(&mut buf)).await {
-        Ok(Ok(n)) if n > 0 => {
-            buf.truncate(n);
-            Ok(buf)
+// Fill `buf` completely, for length-prefixed protocols; a timeout or short read is NoMatch. Counts
+// against --max-total-bytes like `read_some`.
+async fn read_exact_in(stream: &mut ProbeStream, buf: &mut [u8], cfg: &ScanConfig) -> Result<(), ProbeError> {
+    let budget = cfg.byte_budget.as_deref();
+    if budget.is_some_and(|b| b.exhausted()) {
+        return Err(ProbeError::OutOfBudget);
+    }
+    match timeout(cfg.timeout, stream.read_exact(buf)).await {
+        Ok(Ok(_)) => {
+            if let Some(budget) = budget {
+                budget.record(buf.len());
+            }
+            Ok(())
         }
-        _ => Err(()),
+        Ok(Err(e)) if e.kind() == ErrorKind::ConnectionReset => Err(ProbeError::Reset),
+        _ => Err(ProbeError::NoMatch),
     }
 }
 
-async fn http_probe(
-    stream: &mut TcpStream,
+// Send a probe payload and read the first chunk of the reply. Where the exchange broke down is
+// reported on stderr: always for a partial send, with --verbose for the other cases.
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    port: u16,
+    cfg: &ScanConfig,
+    name: &str,
+    payload: &[u8],
     max_bytes: usize,
-    op_timeout: Duration,
-) -> Result<(Protocol, Option<String>), ()> {
-    let probe = b"HEAD / HTTP/1.1\r\nHost: remotehost\r\nUser-Agent: ospine\r\nConnection: close\r\n\r\n";
-    if timeout(op_timeout, stream.write_all(probe)).await.is_err() {
-        return Err(());
+) -> Result<Vec<u8>, ProbeError> {
+    send_probe(stream, port, cfg, name, payload).await?;
+    let res = read_some(stream, max_bytes, cfg.timeout, cfg.read_strategy, cfg.byte_budget.as_deref()).await;
+    if cfg.verbose {
+        match res {
+            Err(ProbeError::NoMatch) => eprintln!("{}:{} {} probe sent, no reply", cfg.target, port, name),
+            Err(ProbeError::Reset) => eprintln!("{}:{} {} probe sent, connection reset", cfg.target, port, name),
+            Err(ProbeError::Broken | ProbeError::OutOfBudget) | Ok(_) => {}
+        }
     }
-    if timeout(op_timeout, stream.flush()).await.is_err() {
-        return Err(());
+    res
+}
+
+async fn send_probe<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    port: u16,
+    cfg: &ScanConfig,
+    name: &str,
+    payload: &[u8],
+) -> Result<(), ProbeError> {
+    // Write in a loop (rather than write_all) so we know how much left before a failure
+    let mut sent = 0usize;
+    let res = timeout(cfg.timeout, async {
+        while sent < payload.len() {
+            let n = stream.write(&payload[sent..]).await?;
+            if n == 0 {
+                return Err(ErrorKind::WriteZero.into());
+            }
+            sent += n;
+        }
+        stream.flush().await
+    })
+    .await;
+    let err: std::io::Error = match res {
+        Ok(Ok(())) => return Ok(()),
+        Ok(Err(e)) => e,
+        Err(_) => ErrorKind::TimedOut.into(),
+    };
+    // Bytes the peer may still be reading, or a write the timeout cut off: the stream is unusable
+    let broken = sent > 0 || err.kind() == ErrorKind::TimedOut;
+    if sent > 0 {
+        eprintln!(
+            "warning: {}:{} {} probe only partially sent ({} of {} bytes): {}",
+            cfg.target,
+            port,
+            name,
+            sent,
+            payload.len(),
+            err
+        );
+    } else if cfg.verbose {
+        eprintln!("{}:{} could not send {} probe: {}", cfg.target, port, name, err);
     }
-    match read_some(stream, max_bytes, op_timeout).await {
-        Ok(buf) if !buf.is_empty() => {
-            if buf.starts_with(b"HTTP/") {
-                let banner = Some(to_safe_string(&buf));
-                return Ok((Protocol::Http, banner));
+    Err(match err.kind() {
+        ErrorKind::ConnectionReset => ProbeError::Reset,
+        _ if broken => ProbeError::Broken,
+        _ => ProbeError::NoMatch,
+    })
+}
+
+async fn http_probe(stream: &mut ProbeStream, port: u16, cfg: &ScanConfig) -> Result<Identification, ProbeError> {
+    let host = cfg.http_host.clone().unwrap_or_else(|| http_host_header(&cfg.host, port));
+    let (buf, websocket) = http_request(stream, port, cfg, &host, &cfg.http_path, cfg.ws_probe).await?;
+    if cfg.follow_redirect && websocket != Some(true) {
+        if let Some(ident) = follow_redirect(&buf, port, cfg).await {
+            return Ok(ident);
+        }
+    }
+    Ok(Identification { websocket, ..http_identification(&buf, cfg) })
+}
+
+// Send the HTTP probe request for `path` and read the response: the head, and for GET the body
+// up to the end of the title. Also whether a WebSocket upgrade was accepted, when `ws` asked for one.
+async fn http_request<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    port: u16,
+    cfg: &ScanConfig,
+    host: &str,
+    path: &str,
+    ws: bool,
+) -> Result<(Vec<u8>, Option<bool>), ProbeError> {
+    // Upper bound on how much of a GET response we read while looking for </title>
+    const MAX_TITLE_SCAN: usize = 64 * 1024;
+
+    let (max_bytes, op_timeout) = (cfg.banner_read_len, cfg.timeout);
+    let method = match cfg.http_method {
+        HttpMethod::Head if !ws => "HEAD",
+        // A WebSocket handshake must be a GET
+        _ => "GET",
+    };
+    let connection = if ws { WS_UPGRADE_HEADERS } else { "Connection: close\r\n" };
+    let probe = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\n{}\r\n",
+        method, path, host, cfg.http_user_agent, connection
+    );
+    let mut buf = exchange(stream, port, cfg, "http", probe.as_bytes(), max_bytes).await?;
+    if !buf.starts_with(b"HTTP/") {
+        return Err(ProbeError::NoMatch);
+    }
+    let websocket = ws.then(|| is_websocket_upgrade(&buf));
+    if cfg.http_method == HttpMethod::Get && websocket != Some(true) {
+        // Keep reading the body until the title is complete, the server closes, or we hit the cap
+        while !tw_contains_ci(&buf, b"</title") && buf.len() < MAX_TITLE_SCAN {
+            match read_some(stream, max_bytes, op_timeout, ReadStrategy::Single, cfg.byte_budget.as_deref()).await {
+                Ok(more) => buf.extend_from_slice(&more),
+                Err(_) => break,
+            }
+        }
+    }
+    Ok((buf, websocket))
+}
+
+// Banner and service from the response head, plus the page title when the body was read.
+fn http_identification(buf: &[u8], cfg: &ScanConfig) -> Identification {
+    let http_title = (cfg.http_method == HttpMethod::Get).then(|| extract_html_title(buf)).flatten();
+    let head = &buf[..buf.len().min(cfg.banner_read_len)];
+    Identification { http_title, ..Identification::from_bytes(Protocol::Http, head, cfg) }
+}
+
+// `--follow-redirect`: for a 3xx answer whose Location stays on the scanned host, fetch that URL
+// once over a new connection (TLS for https://) and identify the port by the page it leads to.
+// The followed response is not checked for a further redirect, so loops end after one hop. None
+// when there is nothing to follow or the hop failed; the original response is reported then.
+async fn follow_redirect(response: &[u8], port: u16, cfg: &ScanConfig) -> Option<Identification> {
+    let location = redirect_location(response)?;
+    let Some(hop) = RedirectHop::parse(&location, port, cfg) else {
+        if cfg.verbose {
+            eprintln!("{}:{} redirect to {} not followed (other host or unsupported URL)", cfg.target, port, location);
+        }
+        return None;
+    };
+    // A second connection, paced and counted like any other. This port's own permit is still held,
+    // so waiting for another is bounded: when every slot is busy the redirect is simply not followed.
+    cfg.rate_limiter.acquire().await;
+    let Ok(permit) = timeout(cfg.timeout, cfg.global_limit.clone().acquire_owned()).await else {
+        if cfg.verbose {
+            eprintln!("{}:{} redirect to {} not followed: no free connection slot", cfg.target, port, hop.url());
+        }
+        return None;
+    };
+    let _global_permit = permit.expect("global semaphore not closed");
+    let source = match &cfg.source_ports {
+        Some(pool) => Some(pool.lease().await),
+        None => None,
+    };
+    let res = timeout(cfg.timeout, crate::scanner::connect(cfg, hop.port, source.as_ref().map(|s| s.port()))).await;
+    let tcp = match res {
+        Ok(Ok(tcp)) => tcp,
+        Ok(Err(e)) => {
+            if cfg.verbose {
+                eprintln!("{}:{} redirect to {} not followed: {}", cfg.target, port, hop.url(), e);
+            }
+            return None;
+        }
+        Err(_) => {
+            if cfg.verbose {
+                eprintln!("{}:{} redirect to {} not followed: connect timed out", cfg.target, port, hop.url());
+            }
+            return None;
+        }
+    };
+    let fetched = if hop.tls {
+        let name = tls::server_name(&hop.host)?;
+        let mut session = match timeout(cfg.timeout, tls::connector().connect(name, tcp)).await {
+            Ok(Ok(session)) => session,
+            _ => {
+                if cfg.verbose {
+                    eprintln!("{}:{} redirect to {} not followed: tls handshake failed", cfg.target, hop.port, hop.url());
+                }
+                return None;
+            }
+        };
+        http_request(&mut session, hop.port, cfg, &hop.host_header(), &hop.path, false).await
+    } else {
+        let mut tcp = tcp;
+        http_request(&mut tcp, hop.port, cfg, &hop.host_header(), &hop.path, false).await
+    };
+    let (buf, _) = fetched.ok()?;
+    Some(Identification { redirect: Some(hop.url()), ..http_identification(&buf, cfg) })
+}
+
+// Location header of a 301, 302, 303, 307 or 308 response.
+fn redirect_location(response: &[u8]) -> Option<String> {
+    let head_end = find_bytes(response, b"\r\n\r\n").unwrap_or(response.len());
+    let head = String::from_utf8_lossy(&response[..head_end]);
+    let mut lines = head.lines();
+    let status = lines.next()?.split(' ').nth(1)?;
+    if !matches!(status, "301" | "302" | "303" | "307" | "308") {
+        return None;
+    }
+    lines
+        .find_map(|l| l.split_once(':').filter(|(name, _)| name.trim().eq_ignore_ascii_case("location")))
+        .map(|(_, value)| value.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+// Where a redirect leads: scheme, port and path on the scanned host.
+struct RedirectHop {
+    tls: bool,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl RedirectHop {
+    // An absolute http(s) URL on the scanned host (or the `--http-host` name), or an absolute path
+    // on the port that answered. Anything else (other hosts, relative paths) is not followed.
+    fn parse(location: &str, port: u16, cfg: &ScanConfig) -> Option<Self> {
+        let http_host = cfg.http_host.as_deref().map(|h| h.rsplit_once(':').map_or(h, |(name, _)| name));
+        if location.starts_with('/') && !location.starts_with("//") {
+            let host = http_host.unwrap_or(&cfg.host).to_string();
+            return Some(Self { tls: false, host, port, path: location.to_string() });
+        }
+        let (scheme, rest) = location.split_once("://")?;
+        let tls = match scheme.to_ascii_lowercase().as_str() {
+            "http" => false,
+            "https" => true,
+            _ => return None,
+        };
+        let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let (authority, path) = rest.split_at(authority_end);
+        // Userinfo is never sent along
+        let authority = authority.rsplit_once('@').map_or(authority, |(_, a)| a);
+        let default_port = if tls { 443 } else { 80 };
+        let (host, hop_port) = match authority.strip_prefix('[') {
+            Some(v6) => {
+                let (host, after) = v6.split_once(']')?;
+                match after.strip_prefix(':') {
+                    Some(p) => (host, p.parse().ok()?),
+                    None if after.is_empty() => (host, default_port),
+                    None => return None,
+                }
+            }
+            None => match authority.split_once(':') {
+                Some((host, p)) => (host, p.parse().ok()?),
+                None => (authority, default_port),
+            },
+        };
+        let same_host = [Some(cfg.host.as_str()), Some(cfg.target.as_str()), http_host]
+            .into_iter()
+            .flatten()
+            .any(|known| known.trim_matches(['[', ']']).eq_ignore_ascii_case(host));
+        if !same_host || hop_port == 0 {
+            return None;
+        }
+        let path = match path {
+            "" => "/".to_string(),
+            p if p.starts_with('/') => p.to_string(),
+            p => format!("/{}", p),
+        };
+        // The connection goes to the scanned host; the name in the URL is what Host and SNI carry
+        Some(Self { tls, host: host.to_string(), port: hop_port, path })
+    }
+
+    fn host_header(&self) -> String {
+        let host = if self.host.contains(':') { format!("[{}]", self.host) } else { self.host.clone() };
+        if self.port == if self.tls { 443 } else { 80 } { host } else { format!("{}:{}", host, self.port) }
+    }
+
+    fn url(&self) -> String {
+        format!("{}://{}{}", if self.tls { "https" } else { "http" }, self.host_header(), self.path)
+    }
+}
+
+// Handshake headers for `--ws-probe`. The key is the RFC 6455 sample nonce; servers only hash it
+// into Sec-WebSocket-Accept, so a fixed value is as good as a random one for detection.
+const WS_UPGRADE_HEADERS: &str = "Connection: Upgrade\r\nUpgrade: websocket\r\n\
+    Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n";
+
+// `101 Switching Protocols` with `Upgrade: websocket` in the response head.
+fn is_websocket_upgrade(buf: &[u8]) -> bool {
+    let head_end = find_bytes(buf, b"\r\n\r\n").unwrap_or(buf.len());
+    let head = &buf[..head_end];
+    let status_101 = head.split(|&b| b == b' ').nth(1) == Some(b"101".as_slice());
+    status_101 && tw_contains_ci(head, b"upgrade: websocket")
+}
+
+// Host header value for the target: IPv6 literals are bracketed, non-default ports appended.
+fn http_host_header(host: &str, port: u16) -> String {
+    let host = if host.contains(':') { format!("[{}]", host) } else { host.to_string() };
+    if port == 80 { host } else { format!("{}:{}", host, port) }
+}
+
+// Text of the first <title> element, whitespace-collapsed and capped in length.
+fn extract_html_title(buf: &[u8]) -> Option<String> {
+    const MAX_TITLE_CHARS: usize = 256;
+    let lower = buf.to_ascii_lowercase();
+    let open = find_bytes(&lower, b"<title")?;
+    let start = open + lower[open..].iter().position(|&b| b == b'>')? + 1;
+    let end = start + find_bytes(&lower[start..], b"</title").unwrap_or(lower.len() - start);
+    let title = String::from_utf8_lossy(&buf[start..end])
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if title.is_empty() {
+        return None;
+    }
+    Some(title.chars().take(MAX_TITLE_CHARS).collect())
+}
+
+// Upper bound on a container API `/version` response
+const MAX_CONTAINER_API_RESPONSE: usize = 64 * 1024;
+
+// Docker Engine (2375 plain, 2376 TLS) and Kubernetes API server / kubelet (6443, 10250; TLS) probe:
+// `GET /version`, recognized by the JSON each API returns. An HTTP answer without those
+// signatures is still reported as HTTP(S), since the request has used up the connection.
+async fn container_api_probe(stream: &mut ProbeStream, port: u16, cfg: &ScanConfig) -> Result<Identification, ProbeError> {
+    if port == 2375 {
+        return container_api_exchange(stream, port, cfg, Protocol::Http).await;
+    }
+    let name = tls::server_name(&cfg.host).ok_or(ProbeError::NoMatch)?;
+    let mut session = match timeout(cfg.timeout, tls::connector().connect(name, &mut *stream)).await {
+        Ok(Ok(session)) => session,
+        Ok(Err(e)) if e.kind() == ErrorKind::ConnectionReset => return Err(ProbeError::Reset),
+        Ok(Err(e)) => {
+            if cfg.verbose {
+                eprintln!("{}:{} container api tls handshake failed: {}", cfg.target, port, e);
+            }
+            return Err(ProbeError::NoMatch);
+        }
+        Err(_) => return Err(ProbeError::NoMatch),
+    };
+    match container_api_exchange(&mut session, port, cfg, Protocol::Https).await {
+        Ok(ident) => Ok(ident),
+        Err(_) => Ok(Identification::new(Protocol::Tls, None)),
+    }
+}
+
+async fn container_api_exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    port: u16,
+    cfg: &ScanConfig,
+    http: Protocol,
+) -> Result<Identification, ProbeError> {
+    let host = cfg.http_host.clone().unwrap_or_else(|| http_host_header(&cfg.host, port));
+    let request = format!(
+        "GET /version HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\nAccept: application/json\r\nConnection: close\r\n\r\n",
+        host, cfg.http_user_agent
+    );
+    let mut buf = exchange(stream, port, cfg, "container-api", request.as_bytes(), cfg.banner_read_len).await?;
+    if !buf.starts_with(b"HTTP/") {
+        return Err(ProbeError::NoMatch);
+    }
+    // Connection: close, so the body ends when the server hangs up
+    while buf.len() < MAX_CONTAINER_API_RESPONSE {
+        match read_some(stream, cfg.banner_read_len, cfg.timeout, ReadStrategy::Single, cfg.byte_budget.as_deref()).await {
+            Ok(more) => buf.extend_from_slice(&more),
+            Err(_) => break,
+        }
+    }
+    let head = &buf[..buf.len().min(cfg.banner_read_len)];
+    Ok(match container_api_banner(&buf, port) {
+        Some((protocol, banner)) => Identification::new(protocol, Some(cap_text(banner, cfg.banner_max_chars))),
+        None => Identification::from_bytes(http, head, cfg),
+    })
+}
+
+// Protocol and banner for a `/version` response from the Docker or Kubernetes API.
+fn container_api_banner(response: &[u8], port: u16) -> Option<(Protocol, String)> {
+    let status = response.split(|&b| b == b' ').nth(1).map(to_safe_string).unwrap_or_default();
+    let body_start = find_bytes(response, b"\r\n\r\n").map_or(response.len(), |i| i + 4);
+    let body = &response[body_start..];
+    // First to last brace, which also steps over chunked-encoding size lines
+    let json: Option<serde_json::Value> = match (body.iter().position(|&b| b == b'{'), body.iter().rposition(|&b| b == b'}')) {
+        (Some(start), Some(end)) if start < end => serde_json::from_slice(&body[start..=end]).ok(),
+        _ => None,
+    };
+    let field = |key: &str| json.as_ref().and_then(|j| j.get(key)).and_then(|v| v.as_str()).map(str::to_string);
+
+    if let Some(api_version) = field("ApiVersion") {
+        let mut banner = format!("docker api {}", api_version);
+        if let Some(version) = field("Version") {
+            banner.push_str(&format!(" (engine {}", version));
+            if let (Some(os), Some(arch)) = (field("Os"), field("Arch")) {
+                banner.push_str(&format!(", {}/{}", os, arch));
             }
-            Err(())
+            banner.push(')');
+        }
+        return Some((Protocol::DockerApi, banner));
+    }
+    if let Some(git_version) = field("gitVersion") {
+        let mut banner = format!("kubernetes api {}", git_version);
+        if let Some(platform) = field("platform") {
+            banner.push_str(&format!(" ({})", platform));
         }
-        _ => Err(()),
+        return Some((Protocol::KubeApi, banner));
     }
+    // Anonymous requests refused: the API server answers with a Status object, the kubelet in plain text
+    let denied = status == "401" || status == "403";
+    if denied && (field("kind").as_deref() == Some("Status") || (port == 10250 && tw_contains_ci(body, b"unauthorized"))) {
+        let what = if port == 10250 { "kubelet api" } else { "kubernetes api" };
+        return Some((Protocol::KubeApi, format!("{} (anonymous access denied: {})", what, status)));
+    }
+    None
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
 }
 
 // Case-insensitive ASCII search for needle in buf
-fn tw_contains_ci(haystack: &[u8], needle: &[u8]) -> bool {
+pub(crate) fn tw_contains_ci(haystack: &[u8], needle: &[u8]) -> bool {
     if needle.is_empty() { return true; }
     let n = needle.iter().map(|b| b.to_ascii_lowercase()).collect::<Vec<u8>>();
     haystack
@@ -145,44 +744,160 @@ fn tw_contains_ci(haystack: &[u8], needle: &[u8]) -> bool {
         .any(|w| w.iter().map(|b| b.to_ascii_lowercase()).eq(n.iter().cloned()))
 }
 
-async fn telnet_probe(
-    stream: &mut TcpStream,
-    max_bytes: usize,
-    op_timeout: Duration,
-) -> Result<(Protocol, Option<String>), ()> {
+async fn telnet_probe(stream: &mut ProbeStream, port: u16, cfg: &ScanConfig) -> Result<Identification, ProbeError> {
     // Send CRLF to prompt a banner/login from many Telnet daemons
-    let probe = b"\r\n";
-    if timeout(op_timeout, stream.write_all(probe)).await.is_err() {
-        return Err(());
+    let buf = exchange(stream, port, cfg, "telnet", b"\r\n", cfg.banner_read_len).await?;
+    let is_telnet = buf.first() == Some(&0xff) // IAC
+        || tw_contains_ci(&buf, b"login:")
+        || tw_contains_ci(&buf, b"username:")
+        || tw_contains_ci(&buf, b"password:");
+    if is_telnet {
+        return Ok(telnet_identification(&buf, cfg));
     }
-    let _ = timeout(op_timeout, stream.flush()).await;
+    Err(ProbeError::NoMatch)
+}
 
-    match read_some(stream, max_bytes, op_timeout).await {
-        Ok(buf) if !buf.is_empty() => {
-            let is_telnet = buf.first() == Some(&0xff) // IAC
-                || tw_contains_ci(&buf, b"login:")
-                || tw_contains_ci(&buf, b"username:")
-                || tw_contains_ci(&buf, b"password:");
-            if is_telnet {
-                return Ok((Protocol::Telnet, Some(to_safe_string(&buf))));
+// Telnet protocol bytes (RFC 854)
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+fn telnet_identification(buf: &[u8], cfg: &ScanConfig) -> Identification {
+    let (banner, options) = strip_telnet_iac(buf);
+    let banner = if cfg.banner_hex {
+        // Forensic mode keeps the raw bytes, negotiation included
+        Some(render_banner(buf, cfg.banner_max_chars, true).0)
+    } else {
+        banner.map(|b| cap_text(b, cfg.banner_max_chars))
+    };
+    Identification {
+        telnet_options: if options.is_empty() { None } else { Some(options) },
+        ..Identification::new(Protocol::Telnet, banner)
+    }
+}
+
+// Split a Telnet stream into its human-readable text and a summary of the option negotiations.
+// WILL/WONT/DO/DONT triplets become entries like "WILL ECHO" (deduplicated, in order seen),
+// subnegotiation blocks (IAC SB ... IAC SE) become "SB <option>", other commands are dropped,
+// and an escaped IAC IAC yields a literal 0xFF.
+fn strip_telnet_iac(buf: &[u8]) -> (Option<String>, Vec<String>) {
+    let mut text = Vec::with_capacity(buf.len());
+    let mut options: Vec<String> = Vec::new();
+    let mut note = |entry: String| {
+        if !options.contains(&entry) {
+            options.push(entry);
+        }
+    };
+    let mut i = 0;
+    while i < buf.len() {
+        if buf[i] != IAC {
+            text.push(buf[i]);
+            i += 1;
+            continue;
+        }
+        match buf.get(i + 1).copied() {
+            Some(IAC) => {
+                text.push(IAC);
+                i += 2;
+            }
+            Some(cmd @ (WILL | WONT | DO | DONT)) => {
+                if let Some(&opt) = buf.get(i + 2) {
+                    let verb = match cmd {
+                        WILL => "WILL",
+                        WONT => "WONT",
+                        DO => "DO",
+                        _ => "DONT",
+                    };
+                    note(format!("{} {}", verb, telnet_option_name(opt)));
+                }
+                i += 3;
             }
-            Err(())
+            Some(SB) => {
+                if let Some(&opt) = buf.get(i + 2) {
+                    note(format!("SB {}", telnet_option_name(opt)));
+                }
+                // Skip to the terminating IAC SE (or the end of what we received)
+                let mut j = i + 3;
+                while j < buf.len() && !(buf[j] == IAC && buf.get(j + 1) == Some(&SE)) {
+                    j += 1;
+                }
+                i = j + 2;
+            }
+            // Two-byte commands (NOP, GA, AYT, ...) or a truncated trailing IAC
+            _ => i += 2,
         }
-        _ => Err(()),
     }
+    let s = to_safe_string(&text);
+    let trimmed = s.trim();
+    let banner = if trimmed.is_empty() { None } else { Some(trimmed.to_string()) };
+    (banner, options)
+}
+
+fn telnet_option_name(opt: u8) -> String {
+    let name = match opt {
+        0 => "BINARY",
+        1 => "ECHO",
+        3 => "SUPPRESS-GO-AHEAD",
+        5 => "STATUS",
+        6 => "TIMING-MARK",
+        24 => "TERMINAL-TYPE",
+        31 => "NAWS",
+        32 => "TERMINAL-SPEED",
+        33 => "TOGGLE-FLOW-CONTROL",
+        34 => "LINEMODE",
+        35 => "X-DISPLAY-LOCATION",
+        36 => "ENVIRON",
+        37 => "AUTHENTICATION",
+        38 => "ENCRYPT",
+        39 => "NEW-ENVIRON",
+        other => return other.to_string(),
+    };
+    name.to_string()
 }
 
 async fn dns_probe(
-    stream: &mut TcpStream,
-    max_bytes: usize,
-    op_timeout: Duration,
-) -> Result<(Protocol, Option<String>), ()> {
-    // Build a minimal DNS query over TCP for A record of example.com
+    stream: &mut ProbeStream,
+    port: u16,
+    cfg: &ScanConfig,
+) -> Result<(Protocol, Option<String>), ProbeError> {
+    let (id, msg) = dns_query();
+    // Send the query and read some response bytes
+    let buf = exchange(stream, port, cfg, "dns", &msg, cfg.banner_read_len.saturating_add(2)).await?;
+    let banner = dns_response_banner(&buf, id).ok_or(ProbeError::NoMatch)?;
+    Ok((Protocol::Dns, Some(banner)))
+}
+
+// DNS-over-TLS (RFC 7858, port 853): a full TLS handshake, then the same length-prefixed query as
+// `dns_probe` inside the session. A handshake without a DNS answer still identifies TLS.
+async fn dot_probe(stream: &mut ProbeStream, port: u16, cfg: &ScanConfig) -> Result<Identification, ProbeError> {
+    let name = tls::server_name(&cfg.host).ok_or(ProbeError::NoMatch)?;
+    let mut session = match timeout(cfg.timeout, tls::connector().connect(name, &mut *stream)).await {
+        Ok(Ok(session)) => session,
+        Ok(Err(e)) if e.kind() == ErrorKind::ConnectionReset => return Err(ProbeError::Reset),
+        Ok(Err(e)) => {
+            if cfg.verbose {
+                eprintln!("{}:{} dns-over-tls handshake failed: {}", cfg.target, port, e);
+            }
+            return Err(ProbeError::NoMatch);
+        }
+        Err(_) => return Err(ProbeError::NoMatch),
+    };
+    let (id, msg) = dns_query();
+    let reply = exchange(&mut session, port, cfg, "dns-over-tls", &msg, cfg.banner_read_len.saturating_add(2)).await;
+    match reply.ok().and_then(|buf| dns_response_banner(&buf, id)) {
+        Some(banner) => Ok(Identification::new(Protocol::DnsOverTls, Some(banner))),
+        None => Ok(Identification::new(Protocol::Tls, None)),
+    }
+}
+
+// Minimal DNS-over-TCP query (A record of example.com) with its two-byte length prefix.
+fn dns_query() -> (u16, Vec<u8>) {
     // DNS header: ID(2) | Flags(2: RD=1) | QDCOUNT=1 | ANCOUNT=0 | NSCOUNT=0 | ARCOUNT=0
-    let mut msg: Vec<u8> = Vec::with_capacity(2 + 12 + 17);
     let id: u16 = 0x4f53; // 'OS'
-    // Two-byte length prefix placeholder (we'll insert after building message)
-    // Header
     let mut dns: Vec<u8> = Vec::with_capacity(12 + 17);
     dns.extend_from_slice(&id.to_be_bytes());
     dns.extend_from_slice(&0x0100u16.to_be_bytes()); // RD=1
@@ -198,87 +913,956 @@ async fn dns_probe(
     dns.push(0); // root
     dns.extend_from_slice(&1u16.to_be_bytes()); // QTYPE=A
     dns.extend_from_slice(&1u16.to_be_bytes()); // QCLASS=IN
-    let len = dns.len() as u16;
-    msg.extend_from_slice(&len.to_be_bytes());
+    let mut msg: Vec<u8> = Vec::with_capacity(2 + dns.len());
+    msg.extend_from_slice(&(dns.len() as u16).to_be_bytes());
     msg.extend_from_slice(&dns);
+    (id, msg)
+}
 
-    if timeout(op_timeout, stream.write_all(&msg)).await.is_err() {
-        return Err(());
-    }
-    let _ = timeout(op_timeout, stream.flush()).await;
-
-    // Read some response bytes
-    let buf = match read_some(stream, max_bytes.saturating_add(2), op_timeout).await {
-        Ok(b) if !b.is_empty() => b,
-        _ => return Err(()),
-    };
-
+// Banner for a length-prefixed DNS response, or None when `buf` is not one.
+fn dns_response_banner(buf: &[u8], id: u16) -> Option<String> {
     // Try to parse DNS over TCP header
     let (header_start, total_len_ok) = if buf.len() >= 2 {
         let total = u16::from_be_bytes([buf[0], buf[1]]) as usize;
         if buf.len() >= 2 + 12 { (2usize, buf.len() >= 2 + total) } else { (2usize, false) }
     } else { (0usize, false) };
 
-    if buf.len() < header_start + 12 { return Err(()); }
+    if buf.len() < header_start + 12 { return None; }
     let flags_hi = buf[header_start + 2];
     let flags_lo = buf[header_start + 3];
     let qr = (flags_hi & 0x80) != 0;
     let rcode = flags_lo & 0x0f;
     let qdcount = u16::from_be_bytes([buf[header_start + 4], buf[header_start + 5]]);
     let ancount = u16::from_be_bytes([buf[header_start + 6], buf[header_start + 7]]);
-    let resp_id = u16::from_be_bytes([buf[header_start + 0], buf[header_start + 1]]);
+    let resp_id = u16::from_be_bytes([buf[header_start], buf[header_start + 1]]);
 
-    if !qr { return Err(()); }
+    if !qr { return None; }
     if resp_id != id { /* not fatal, some servers may rewrite ID behind proxies */ }
 
-    let banner = Some(format!(
+    Some(format!(
         "dns id=0x{resp_id:04x} qd={qd} an={an} rcode={rcode}{}",
         if total_len_ok { " complete" } else { "" },
         qd = qdcount,
         an = ancount
-    ));
+    ))
+}
+
+// DCE/RPC (MS-RPCE) packet types and flags used by the endpoint mapper probe
+const RPC_REQUEST: u8 = 0;
+const RPC_RESPONSE: u8 = 2;
+const RPC_BIND_ACK: u8 = 12;
+const RPC_BIND_NAK: u8 = 13;
+const RPC_LAST_FRAG: u8 = 0x02;
+// Upper bound on a reassembled ept_lookup response
+const MAX_RPC_STUB: usize = 64 * 1024;
+// Endpoints asked for in one ept_lookup call, and how many of them the banner lists
+const EPM_MAX_ENTRIES: u32 = 64;
+const EPM_BANNER_ENTRIES: usize = 5;
+
+// Bind to the endpoint mapper (e1af8308-5d1f-11c9-91a4-08002b14a0fa v3.0) using NDR
+// (8a885d04-1ceb-11c9-9fe8-08002b104860 v2). UUIDs are in their little-endian wire form.
+const EPM_BIND: &[u8] = &[
+    0x05, 0x00, 0x0b, 0x03, // v5.0, bind, first+last fragment
+    0x10, 0x00, 0x00, 0x00, // data representation: little-endian, ASCII, IEEE
+    0x48, 0x00, 0x00, 0x00, // frag length 72, auth length 0
+    0x01, 0x00, 0x00, 0x00, // call id
+    0xb8, 0x10, 0xb8, 0x10, // max xmit/recv fragment 4280
+    0x00, 0x00, 0x00, 0x00, // association group
+    0x01, 0x00, 0x00, 0x00, // one presentation context
+    0x00, 0x00, 0x01, 0x00, // context id 0, one transfer syntax
+    0x08, 0x83, 0xaf, 0xe1, 0x1f, 0x5d, 0xc9, 0x11, 0x91, 0xa4, 0x08, 0x00, 0x2b, 0x14, 0xa0, 0xfa,
+    0x03, 0x00, 0x00, 0x00, // interface version 3.0
+    0x04, 0x5d, 0x88, 0x8a, 0xeb, 0x1c, 0xc9, 0x11, 0x9f, 0xe8, 0x08, 0x00, 0x2b, 0x10, 0x48, 0x60,
+    0x02, 0x00, 0x00, 0x00, // NDR version 2
+];
+
+// MSRPC endpoint mapper probe (port 135): bind to the EPM interface, then enumerate registered
+// endpoints with ept_lookup and list a few of them (interface UUID and transport) in the banner.
+async fn epmap_probe(stream: &mut ProbeStream, port: u16, cfg: &ScanConfig) -> Result<Identification, ProbeError> {
+    send_probe(stream, port, cfg, "msrpc", EPM_BIND).await?;
+    let ack = read_rpc_pdu(stream, cfg).await?;
+    // Any bind reply, even a rejection, means the port speaks DCE/RPC
+    let accepted = match ack[2] {
+        RPC_BIND_ACK => rpc_bind_accepted(&ack),
+        RPC_BIND_NAK => false,
+        _ => return Err(ProbeError::NoMatch),
+    };
+    let known = |note: &str| Ok(Identification::new(Protocol::Msrpc, Some(format!("msrpc epmap: {}", note))));
+    if !accepted {
+        return known("bind rejected");
+    }
+
+    if send_probe(stream, port, cfg, "msrpc", &ept_lookup_request()).await.is_err() {
+        return known("lookup not sent");
+    }
+    let mut stub = Vec::new();
+    loop {
+        let pdu = match read_rpc_pdu(stream, cfg).await {
+            Ok(pdu) if pdu[2] == RPC_RESPONSE && pdu.len() >= 24 => pdu,
+            _ => return known("lookup failed"),
+        };
+        let auth_len = u16::from_le_bytes([pdu[10], pdu[11]]) as usize;
+        let end = pdu.len().saturating_sub(if auth_len > 0 { auth_len + 8 } else { 0 }).max(24);
+        stub.extend_from_slice(&pdu[24..end]);
+        if pdu[3] & RPC_LAST_FRAG != 0 || stub.len() > MAX_RPC_STUB {
+            break;
+        }
+    }
+
+    let endpoints = parse_ept_entries(&stub).unwrap_or_default();
+    let mut banner = format!("msrpc epmap: {} endpoint(s)", endpoints.len());
+    if !endpoints.is_empty() {
+        banner.push_str(": ");
+        banner.push_str(&endpoints.iter().take(EPM_BANNER_ENTRIES).cloned().collect::<Vec<_>>().join(", "));
+        if endpoints.len() > EPM_BANNER_ENTRIES {
+            banner.push_str(", …");
+        }
+    }
+    Ok(Identification::new(Protocol::Msrpc, Some(banner)))
+}
 
-    Ok((Protocol::Dns, banner))
+// Read one DCE/RPC PDU (connection-oriented, little-endian) using its fragment length.
+async fn read_rpc_pdu(stream: &mut ProbeStream, cfg: &ScanConfig) -> Result<Vec<u8>, ProbeError> {
+    let mut pdu = vec![0u8; 16];
+    read_exact_in(stream, &mut pdu, cfg).await?;
+    // Version 5.0, little-endian integer representation
+    if pdu[0] != 5 || pdu[1] != 0 || pdu[4] & 0xf0 != 0x10 {
+        return Err(ProbeError::NoMatch);
+    }
+    let frag_len = u16::from_le_bytes([pdu[8], pdu[9]]) as usize;
+    if frag_len < 16 {
+        return Err(ProbeError::NoMatch);
+    }
+    pdu.resize(frag_len, 0);
+    read_exact_in(stream, &mut pdu[16..], cfg).await?;
+    Ok(pdu)
 }
 
-async fn tls_probe(
-    stream: &mut TcpStream,
-    max_bytes: usize,
-    op_timeout: Duration,
-) -> Result<(Protocol, Option<String>), ()> {
-    // Minimal TLS ClientHello (no SNI), works for many servers. Not a full handshake implementation.
-    // This is a commonly used small ClientHello payload.
-    const CLIENT_HELLO: &[u8] = &[
-        0x16, 0x03, 0x01, 0x00, 0x31, // Handshake record, TLS 1.0, length 0x0031
-        0x01, 0x00, 0x00, 0x2d, // ClientHello, length 0x002d
-        0x03, 0x03, // TLS 1.2
-        // Random (32 bytes)
-        0x53, 0x43, 0x4e, 0x52, 0x00, 0x01, 0x02, 0x03,
-        0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b,
-        0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13,
-        0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b,
-        0x00, // session id length
-        0x00, 0x04, // cipher suites length (4 bytes)
-        0x00, 0x2f, // TLS_RSA_WITH_AES_128_CBC_SHA
-        0x00, 0x35, // TLS_RSA_WITH_AES_256_CBC_SHA
-        0x01, // compression methods length
-        0x00, // null compression
-        0x00, 0x00, // extensions length = 0
-    ];
-
-    if timeout(op_timeout, stream.write_all(CLIENT_HELLO)).await.is_err() {
-        return Err(());
-    }
-    let _ = timeout(op_timeout, stream.flush()).await;
-
-    match read_some(stream, max_bytes, op_timeout).await {
-        Ok(buf) if !buf.is_empty() => {
-            if buf.get(0) == Some(&0x16) && matches!(buf.get(1), Some(0x03)) {
-                return Ok((Protocol::Tls, Some(hex_preview(&buf))));
+// Whether the first presentation context of a bind_ack was accepted.
+fn rpc_bind_accepted(ack: &[u8]) -> bool {
+    // max xmit/recv (4) + assoc group (4), then the secondary address string, aligned to 4
+    let Some(sec_len) = ack.get(24..26).map(|b| u16::from_le_bytes([b[0], b[1]]) as usize) else {
+        return false;
+    };
+    let results = (26 + sec_len + 3) & !3;
+    // n_results (1) + reserved (3), then result (2) of the first context
+    matches!(ack.get(results), Some(n) if *n > 0)
+        && ack.get(results + 4..results + 6) == Some(&[0, 0][..])
+}
+
+// ept_lookup (opnum 2): all entries, no object/interface filter, fresh context handle.
+fn ept_lookup_request() -> Vec<u8> {
+    let mut stub = Vec::with_capacity(40);
+    stub.extend_from_slice(&0u32.to_le_bytes()); // inquiry type: RPC_C_EP_ALL_ELTS
+    stub.extend_from_slice(&0u32.to_le_bytes()); // object: null
+    stub.extend_from_slice(&0u32.to_le_bytes()); // interface id: null
+    stub.extend_from_slice(&1u32.to_le_bytes()); // version option: RPC_C_VERS_ALL
+    stub.extend_from_slice(&[0u8; 20]); // entry handle
+    stub.extend_from_slice(&EPM_MAX_ENTRIES.to_le_bytes());
+
+    let mut pdu = Vec::with_capacity(24 + stub.len());
+    pdu.extend_from_slice(&[0x05, 0x00, RPC_REQUEST, 0x03, 0x10, 0x00, 0x00, 0x00]);
+    pdu.extend_from_slice(&((24 + stub.len()) as u16).to_le_bytes());
+    pdu.extend_from_slice(&0u16.to_le_bytes()); // auth length
+    pdu.extend_from_slice(&2u32.to_le_bytes()); // call id
+    pdu.extend_from_slice(&(stub.len() as u32).to_le_bytes()); // alloc hint
+    pdu.extend_from_slice(&0u16.to_le_bytes()); // context id
+    pdu.extend_from_slice(&2u16.to_le_bytes()); // opnum: ept_lookup
+    pdu.extend_from_slice(&stub);
+    pdu
+}
+
+// Decode the entries of an ept_lookup response stub into "uuid transport" strings.
+fn parse_ept_entries(stub: &[u8]) -> Option<Vec<String>> {
+    let u32_at = |off: usize| stub.get(off..off + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize);
+    // entry handle (20), num_ents (4), then the conformant varying array: max, offset, actual
+    let count = u32_at(32)?;
+    let mut off = 36;
+    let mut has_tower = Vec::new();
+    for _ in 0..count {
+        // object uuid (16), tower pointer (4), annotation: offset (4), length (4), chars
+        has_tower.push(u32_at(off + 16)? != 0);
+        let annotation_len = u32_at(off + 24)?;
+        off = (off + 28 + annotation_len + 3) & !3;
+    }
+    let mut out = Vec::new();
+    for present in has_tower {
+        if !present {
+            continue;
+        }
+        // Deferred tower: conformance (4), tower length (4), tower octets
+        let len = u32_at(off + 4)?;
+        let tower = stub.get(off + 8..off + 8 + len)?;
+        if let Some(desc) = describe_tower(tower) {
+            out.push(desc);
+        }
+        off = (off + 8 + len + 3) & !3;
+    }
+    Some(out)
+}
+
+// Interface UUID and endpoint of a protocol tower, e.g. "12345778-1234-abcd-ef00-0123456789ac tcp/49668".
+fn describe_tower(tower: &[u8]) -> Option<String> {
+    let u16_le = |b: &[u8]| u16::from_le_bytes([b[0], b[1]]) as usize;
+    let floors = u16_le(tower.get(0..2)?);
+    let mut off = 2;
+    let mut interface = None;
+    let mut endpoint = None;
+    for _ in 0..floors {
+        let lhs_len = u16_le(tower.get(off..off + 2)?);
+        let lhs = tower.get(off + 2..off + 2 + lhs_len)?;
+        off += 2 + lhs_len;
+        let rhs_len = u16_le(tower.get(off..off + 2)?);
+        let rhs = tower.get(off + 2..off + 2 + rhs_len)?;
+        off += 2 + rhs_len;
+        let text = || String::from_utf8_lossy(rhs).trim_end_matches('\0').to_string();
+        match lhs.first()? {
+            0x0d if interface.is_none() && lhs.len() >= 17 => interface = Some(format_uuid(&lhs[1..17])),
+            0x07 if rhs.len() >= 2 => endpoint = Some(format!("tcp/{}", u16::from_be_bytes([rhs[0], rhs[1]]))),
+            0x08 if rhs.len() >= 2 => endpoint = Some(format!("udp/{}", u16::from_be_bytes([rhs[0], rhs[1]]))),
+            0x1f if rhs.len() >= 2 => endpoint = Some(format!("http/{}", u16::from_be_bytes([rhs[0], rhs[1]]))),
+            0x0f => endpoint = Some(format!("np:{}", text())),
+            0x10 => endpoint = Some(format!("ncalrpc:{}", text())),
+            _ => {}
+        }
+    }
+    Some(format!("{} {}", interface?, endpoint.unwrap_or_else(|| "?".into())))
+}
+
+// Canonical text form of a UUID stored in DCE little-endian layout.
+fn format_uuid(b: &[u8]) -> String {
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+        u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        u16::from_le_bytes([b[4], b[5]]),
+        u16::from_le_bytes([b[6], b[7]]),
+        b[8], b[9], b[10], b[11], b[12], b[13], b[14], b[15]
+    )
+}
+
+// SMB2 command codes and the NTLMSSP signature used by the SMB probe
+const SMB2_NEGOTIATE: u16 = 0;
+const SMB2_SESSION_SETUP: u16 = 1;
+const NTLMSSP_SIGNATURE: &[u8] = b"NTLMSSP\0";
+// Upper bound on one NetBIOS session frame we are willing to read
+const MAX_NETBIOS_FRAME: usize = 64 * 1024;
+
+// SMB probe (139/445): SMB2 NEGOTIATE for the dialect and signing policy, then an anonymous
+// SESSION_SETUP carrying an NTLMSSP NEGOTIATE; the server's CHALLENGE reveals its OS version and
+// NetBIOS/DNS names, which are added to the banner when present.
+async fn smb_probe(stream: &mut ProbeStream, port: u16, cfg: &ScanConfig) -> Result<Identification, ProbeError> {
+    if port == 139 {
+        // NetBIOS session service: a positive or negative session response both mean SMB
+        send_probe(stream, port, cfg, "smb", &netbios_session_request()).await?;
+        let mut reply = [0u8; 4];
+        read_exact_in(stream, &mut reply, cfg).await?;
+        match reply[0] {
+            0x82 => {}
+            0x83 => {
+                return Ok(Identification::new(Protocol::Smb, Some("smb netbios session rejected".into())));
             }
-            Err(())
+            _ => return Err(ProbeError::NoMatch),
+        }
+    }
+
+    // Dialects 2.0.2, 2.1, 3.0 and 3.0.2 (3.1.1 would require negotiate contexts)
+    let mut negotiate = Vec::with_capacity(44);
+    negotiate.extend_from_slice(&36u16.to_le_bytes()); // structure size
+    negotiate.extend_from_slice(&4u16.to_le_bytes()); // dialect count
+    negotiate.extend_from_slice(&1u16.to_le_bytes()); // security mode: signing enabled
+    negotiate.extend_from_slice(&[0u8; 2 + 4 + 16 + 8]); // reserved, capabilities, client guid, start time
+    for dialect in [0x0202u16, 0x0210, 0x0300, 0x0302] {
+        negotiate.extend_from_slice(&dialect.to_le_bytes());
+    }
+    send_probe(stream, port, cfg, "smb", &smb2_message(SMB2_NEGOTIATE, 0, &negotiate)).await?;
+    let reply = read_netbios_frame(stream, cfg).await?;
+    if reply.starts_with(b"\xffSMB") {
+        return Ok(Identification::new(Protocol::Smb, Some("smb dialect=1 (SMB2 not supported)".into())));
+    }
+    // SMB2 header (64) + negotiate response: structure size, security mode, dialect
+    if !reply.starts_with(b"\xfeSMB") || reply.len() < 64 + 6 {
+        return Err(ProbeError::NoMatch);
+    }
+    let security_mode = u16::from_le_bytes([reply[66], reply[67]]);
+    let dialect = u16::from_le_bytes([reply[68], reply[69]]);
+    let mut banner = format!(
+        "smb dialect={} signing={}",
+        smb_dialect_name(dialect),
+        if security_mode & 0x02 != 0 { "required" } else { "optional" }
+    );
+
+    // Best effort from here on: the dialect alone already identifies SMB
+    let mut setup = Vec::with_capacity(24 + 40);
+    setup.extend_from_slice(&25u16.to_le_bytes()); // structure size
+    setup.push(0); // flags
+    setup.push(1); // security mode: signing enabled
+    setup.extend_from_slice(&[0u8; 4 + 4]); // capabilities, channel
+    setup.extend_from_slice(&(64u16 + 24).to_le_bytes()); // security buffer offset
+    setup.extend_from_slice(&(NTLMSSP_NEGOTIATE.len() as u16).to_le_bytes());
+    setup.extend_from_slice(&[0u8; 8]); // previous session id
+    setup.extend_from_slice(NTLMSSP_NEGOTIATE);
+    if send_probe(stream, port, cfg, "smb", &smb2_message(SMB2_SESSION_SETUP, 1, &setup)).await.is_ok() {
+        if let Ok(reply) = read_netbios_frame(stream, cfg).await {
+            if let Some(start) = find_bytes(&reply, NTLMSSP_SIGNATURE) {
+                banner.push_str(&describe_ntlm_challenge(&reply[start..]));
+            }
+        }
+    }
+    Ok(Identification::new(Protocol::Smb, Some(banner)))
+}
+
+// NTLMSSP NEGOTIATE: Unicode, request target, NTLM, always sign, extended session security,
+// version, 128/56-bit; no domain or workstation; client version 6.1.7601 (NTLM revision 15).
+const NTLMSSP_NEGOTIATE: &[u8] = &[
+    b'N', b'T', b'L', b'M', b'S', b'S', b'P', 0x00,
+    0x01, 0x00, 0x00, 0x00, // message type
+    0x05, 0x82, 0x08, 0xa2, // negotiate flags
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // domain name fields
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // workstation fields
+    0x06, 0x01, 0xb1, 0x1d, 0x00, 0x00, 0x00, 0x0f, // version
+];
+
+// An SMB2 request with a zeroed 64-byte header, framed for the NetBIOS session service.
+fn smb2_message(command: u16, message_id: u64, body: &[u8]) -> Vec<u8> {
+    let len = 64 + body.len();
+    let mut msg = Vec::with_capacity(4 + len);
+    msg.push(0x00); // session message
+    msg.extend_from_slice(&(len as u32).to_be_bytes()[1..]);
+    msg.extend_from_slice(b"\xfeSMB");
+    msg.extend_from_slice(&64u16.to_le_bytes()); // structure size
+    msg.extend_from_slice(&[0u8; 2 + 4]); // credit charge, status
+    msg.extend_from_slice(&command.to_le_bytes());
+    msg.extend_from_slice(&1u16.to_le_bytes()); // credits requested
+    msg.extend_from_slice(&[0u8; 4 + 4]); // flags, next command
+    msg.extend_from_slice(&message_id.to_le_bytes());
+    msg.extend_from_slice(&0xfeffu32.to_le_bytes()); // process id
+    msg.extend_from_slice(&[0u8; 4 + 8 + 16]); // tree id, session id, signature
+    msg.extend_from_slice(body);
+    msg
+}
+
+// NetBIOS session request from "OSPINE" to the generic "*SMBSERVER" name.
+fn netbios_session_request() -> Vec<u8> {
+    // First-level encoding (RFC 1001): each nibble of the space-padded name becomes 'A' + nibble
+    fn encode(name: &str, suffix: u8) -> Vec<u8> {
+        let mut raw = format!("{:<15}", name).into_bytes();
+        raw.push(suffix);
+        let mut out = vec![0x20];
+        for b in raw {
+            out.push(b'A' + (b >> 4));
+            out.push(b'A' + (b & 0x0f));
+        }
+        out.push(0x00);
+        out
+    }
+    let mut names = encode("*SMBSERVER", 0x20);
+    names.extend(encode("OSPINE", 0x00));
+    let mut msg = vec![0x81, 0x00];
+    msg.extend_from_slice(&(names.len() as u16).to_be_bytes());
+    msg.extend(names);
+    msg
+}
+
+// Read one NetBIOS session message and return its payload.
+async fn read_netbios_frame(stream: &mut ProbeStream, cfg: &ScanConfig) -> Result<Vec<u8>, ProbeError> {
+    let mut header = [0u8; 4];
+    read_exact_in(stream, &mut header, cfg).await?;
+    let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+    if header[0] != 0x00 || len == 0 || len > MAX_NETBIOS_FRAME {
+        return Err(ProbeError::NoMatch);
+    }
+    let mut frame = vec![0u8; len];
+    read_exact_in(stream, &mut frame, cfg).await?;
+    Ok(frame)
+}
+
+fn smb_dialect_name(dialect: u16) -> String {
+    match dialect {
+        0x0202 => "2.0.2".into(),
+        0x0210 => "2.1".into(),
+        0x0300 => "3.0".into(),
+        0x0302 => "3.0.2".into(),
+        0x0311 => "3.1.1".into(),
+        other => format!("0x{:04x}", other),
+    }
+}
+
+// " os=10.0.19041 domain=CORP computer=WS01 ..." from an NTLMSSP CHALLENGE message.
+fn describe_ntlm_challenge(msg: &[u8]) -> String {
+    let u16_at = |off: usize| msg.get(off..off + 2).map(|b| u16::from_le_bytes([b[0], b[1]]) as usize);
+    let u32_at = |off: usize| msg.get(off..off + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    let mut out = String::new();
+    if u32_at(8) != Some(2) {
+        return out;
+    }
+    // NEGOTIATE_VERSION: major, minor, build at offset 48
+    if u32_at(20).is_some_and(|flags| flags & 0x0200_0000 != 0) {
+        if let (Some(v), Some(build)) = (msg.get(48..50), u16_at(50)) {
+            out.push_str(&format!(" os={}.{}.{}", v[0], v[1], build));
+        }
+    }
+    // Target info: AV pairs of (id, length, UTF-16LE value), terminated by MsvAvEOL
+    let (Some(len), Some(offset)) = (u16_at(40), u32_at(44)) else {
+        return out;
+    };
+    let Some(info) = msg.get(offset as usize..offset as usize + len) else {
+        return out;
+    };
+    let mut off = 0;
+    while let (Some(id), Some(len)) = (info.get(off..off + 2), info.get(off + 2..off + 4)) {
+        let id = u16::from_le_bytes([id[0], id[1]]);
+        let len = u16::from_le_bytes([len[0], len[1]]) as usize;
+        let Some(value) = info.get(off + 4..off + 4 + len) else {
+            break;
+        };
+        let label = match id {
+            0 => break,
+            1 => "computer",
+            2 => "domain",
+            3 => "dns_computer",
+            4 => "dns_domain",
+            _ => "",
+        };
+        if !label.is_empty() {
+            let units: Vec<u16> = value.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+            out.push_str(&format!(" {}={}", label, String::from_utf16_lossy(&units)));
+        }
+        off += 4 + len;
+    }
+    out
+}
+
+// TDS 7 PRELOGIN: a packet header (type 0x12, end of message, length 47), then the option table
+// (token, data offset, data length; 0xff ends it) and the option data. VERSION 0.0.0.0 and
+// ENCRYPT_OFF, so the server answers with its own version and the encryption it requires.
+const TDS_PRELOGIN: &[u8] = &[
+    0x12, 0x01, 0x00, 0x2f, 0x00, 0x00, 0x01, 0x00, // header: PRELOGIN, EOM, length, SPID, packet id, window
+    0x00, 0x00, 0x1a, 0x00, 0x06, // VERSION at 26, 6 bytes
+    0x01, 0x00, 0x20, 0x00, 0x01, // ENCRYPTION at 32, 1 byte
+    0x02, 0x00, 0x21, 0x00, 0x01, // INSTOPT at 33, 1 byte
+    0x03, 0x00, 0x22, 0x00, 0x04, // THREADID at 34, 4 bytes
+    0x04, 0x00, 0x26, 0x00, 0x01, // MARS at 38, 1 byte
+    0xff, // end of options
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // client version
+    0x00, // ENCRYPT_OFF
+    0x00, // default instance
+    0x00, 0x00, 0x00, 0x00, // thread id
+    0x00, // no MARS
+];
+// Tabular result, the packet type of a PRELOGIN response
+const TDS_RESPONSE: u8 = 0x04;
+// Upper bound on the response packet
+const MAX_TDS_PACKET: usize = 4096;
+
+// MSSQL probe (1433, 1434): a TDS PRELOGIN, whose response gives the server version and whether it
+// requires encryption (e.g. `mssql 15.0.2000 (SQL Server 2019), encryption required`).
+async fn tds_probe(stream: &mut ProbeStream, port: u16, cfg: &ScanConfig) -> Result<Identification, ProbeError> {
+    send_probe(stream, port, cfg, "tds", TDS_PRELOGIN).await?;
+    let mut header = [0u8; 8];
+    read_exact_in(stream, &mut header, cfg).await?;
+    let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+    if header[0] != TDS_RESPONSE || !(9..=MAX_TDS_PACKET).contains(&len) {
+        return Err(ProbeError::NoMatch);
+    }
+    let mut payload = vec![0u8; len - 8];
+    read_exact_in(stream, &mut payload, cfg).await?;
+    let options = tds_prelogin_options(&payload).ok_or(ProbeError::NoMatch)?;
+    let option = |token: u8| options.iter().find(|(t, _)| *t == token).map(|(_, data)| *data);
+
+    let version = option(0x00).filter(|v| v.len() >= 4).map(|v| {
+        format!("{}.{}.{}", v[0], v[1], u16::from_be_bytes([v[2], v[3]]))
+    });
+    let release = option(0x00).and_then(|v| sql_server_release(*v.first()?, *v.get(1)?));
+    let encryption = option(0x01).and_then(|e| e.first()).map(|&e| match e {
+        0x00 => "encryption off (login only)",
+        0x01 => "encryption on",
+        0x02 => "encryption not supported",
+        0x03 => "encryption required",
+        _ => "encryption unknown",
+    });
+
+    let mut banner = "mssql".to_string();
+    if let Some(version) = &version {
+        banner.push_str(&format!(" {}", version));
+    }
+    if let Some(release) = release {
+        banner.push_str(&format!(" ({})", release));
+    }
+    if let Some(encryption) = encryption {
+        banner.push_str(&format!(", {}", encryption));
+    }
+    let service = ServiceInfo {
+        product: Some("Microsoft SQL Server".to_string()),
+        version,
+        extra: encryption.map(str::to_string),
+        ..ServiceInfo::named(&Protocol::Mssql)
+    };
+    Ok(Identification { service: Some(service), ..Identification::new(Protocol::Mssql, Some(banner)) })
+}
+
+// (token, data) of each PRELOGIN option; None when the table runs past the payload.
+fn tds_prelogin_options(payload: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut out = Vec::new();
+    let mut at = 0;
+    loop {
+        let token = *payload.get(at)?;
+        if token == 0xff {
+            return Some(out);
+        }
+        let entry = payload.get(at + 1..at + 5)?;
+        let offset = u16::from_be_bytes([entry[0], entry[1]]) as usize;
+        let len = u16::from_be_bytes([entry[2], entry[3]]) as usize;
+        out.push((token, payload.get(offset..offset + len)?));
+        at += 5;
+    }
+}
+
+// Marketing name of a SQL Server major.minor version.
+fn sql_server_release(major: u8, minor: u8) -> Option<&'static str> {
+    Some(match (major, minor) {
+        (8, _) => "SQL Server 2000",
+        (9, _) => "SQL Server 2005",
+        (10, 50) => "SQL Server 2008 R2",
+        (10, _) => "SQL Server 2008",
+        (11, _) => "SQL Server 2012",
+        (12, _) => "SQL Server 2014",
+        (13, _) => "SQL Server 2016",
+        (14, _) => "SQL Server 2017",
+        (15, _) => "SQL Server 2019",
+        (16, _) => "SQL Server 2022",
+        _ => return None,
+    })
+}
+
+// AMQP 0-9-1 protocol header; the broker answers with Connection.Start, or with its own
+// protocol header (then closes) when it does not speak this version.
+const AMQP_HEADER: &[u8] = b"AMQP\x00\x00\x09\x01";
+const AMQP_FRAME_METHOD: u8 = 1;
+const AMQP_FRAME_END: u8 = 0xce;
+// Upper bound on the Connection.Start frame we are willing to read
+const MAX_AMQP_FRAME: usize = 64 * 1024;
+
+// AMQP probe (5672): send the protocol header and report the broker's product, version and
+// platform from the server-properties table of Connection.Start.
+async fn amqp_probe(stream: &mut ProbeStream, port: u16, cfg: &ScanConfig) -> Result<Identification, ProbeError> {
+    send_probe(stream, port, cfg, "amqp", AMQP_HEADER).await?;
+    let mut header = [0u8; 7];
+    read_exact_in(stream, &mut header, cfg).await?;
+    if header.starts_with(b"AMQP") {
+        // Version mismatch: the last byte of its header is still in flight, so report what we have
+        let banner = format!("amqp protocol header rejected (server offers {}-{})", header[5], header[6]);
+        return Ok(Identification::new(Protocol::Amqp, Some(banner)));
+    }
+    let size = u32::from_be_bytes([header[3], header[4], header[5], header[6]]) as usize;
+    if header[0] != AMQP_FRAME_METHOD || header[1..3] != [0, 0] || !(6..=MAX_AMQP_FRAME).contains(&size) {
+        return Err(ProbeError::NoMatch);
+    }
+    let mut payload = vec![0u8; size + 1];
+    read_exact_in(stream, &mut payload, cfg).await?;
+    // Class 10 (connection), method 10 (start), followed by the frame-end octet
+    if payload[size] != AMQP_FRAME_END || payload[..4] != [0, 10, 0, 10] {
+        return Err(ProbeError::NoMatch);
+    }
+    let mut banner = format!("amqp {}-{}", payload[4], payload[5]);
+    let props = amqp_server_properties(&payload[6..size]);
+    let field = |name: &str| props.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str());
+    if let Some(product) = field("product") {
+        banner.push(' ');
+        banner.push_str(product);
+        if let Some(version) = field("version") {
+            banner.push(' ');
+            banner.push_str(version);
+        }
+    }
+    if let Some(platform) = field("platform") {
+        banner.push_str(&format!(" ({})", platform));
+    }
+    Ok(Identification::new(Protocol::Amqp, Some(cap_text(banner, cfg.banner_max_chars))))
+}
+
+// String-valued entries of an AMQP field table (long-uint size, then name/type/value triplets).
+// Parsing stops at the first value type it cannot size; what was read so far is kept.
+fn amqp_server_properties(buf: &[u8]) -> Vec<(String, String)> {
+    let mut out = Vec::new();
+    let Some(len) = buf.get(..4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize) else {
+        return out;
+    };
+    let table = &buf[4..buf.len().min(4 + len)];
+    let long_len = |at: usize| table.get(at..at + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize);
+    let mut i = 0;
+    while let Some(&name_len) = table.get(i) {
+        let Some(name) = table.get(i + 1..i + 1 + name_len as usize) else {
+            break;
+        };
+        let at = i + 1 + name_len as usize;
+        let Some(&kind) = table.get(at) else {
+            break;
+        };
+        let value_len = match kind {
+            b'S' | b'x' | b'F' | b'A' => match long_len(at + 1) {
+                Some(n) => 4 + n,
+                None => break,
+            },
+            b's' => match table.get(at + 1) {
+                Some(&n) => 1 + n as usize,
+                None => break,
+            },
+            b't' | b'b' | b'B' => 1,
+            b'u' | b'U' => 2,
+            b'i' | b'I' | b'f' => 4,
+            b'D' => 5,
+            b'l' | b'L' | b'd' | b'T' => 8,
+            b'V' => 0,
+            _ => break,
+        };
+        let value = table.get(at + 1..at + 1 + value_len);
+        if let (b'S' | b's', Some(value)) = (kind, value) {
+            let text = if kind == b'S' { &value[4..] } else { &value[1..] };
+            out.push((to_safe_string(name), to_safe_string(text).trim().to_string()));
+        }
+        i = at + 1 + value_len;
+    }
+    out
+}
+
+// Finger probe (79): an empty query asks for the users logged in; any text reply that is not some
+// other protocol's is taken as finger, and the reply is the banner.
+async fn finger_probe(stream: &mut ProbeStream, port: u16, cfg: &ScanConfig) -> Result<Identification, ProbeError> {
+    let buf = exchange(stream, port, cfg, "finger", b"\r\n", cfg.banner_read_len).await?;
+    if looks_binary(&buf, cfg.banner_encoding.unwrap_or(UTF_8)) || match_banner(&buf, port).is_some() {
+        return Err(ProbeError::NoMatch);
+    }
+    Ok(Identification::from_bytes(Protocol::Finger, &buf, cfg))
+}
+
+// Gopher probe (70): the empty selector asks for the root menu, lines of
+// `<type><display>\t<selector>\t<host>\t<port>` ending in `.`. The banner counts the items (a
+// lower bound when the menu did not fit the read) and quotes the first one's display text, often
+// the server's title.
+async fn gopher_probe(stream: &mut ProbeStream, port: u16, cfg: &ScanConfig) -> Result<Identification, ProbeError> {
+    let buf = exchange(stream, port, cfg, "gopher", b"\r\n", cfg.banner_read_len).await?;
+    let text = String::from_utf8_lossy(&buf);
+    let items: Vec<&str> = text
+        .lines()
+        .map(|l| l.trim_end_matches('\r'))
+        .take_while(|l| *l != ".")
+        .filter(|l| l.split('\t').count() >= 4)
+        .collect();
+    let Some(first) = items.first() else {
+        return Err(ProbeError::NoMatch);
+    };
+    let title = first.get(1..).and_then(|rest| rest.split('\t').next()).unwrap_or_default().trim();
+    let complete = text.lines().any(|l| l.trim_end_matches('\r') == ".");
+    let mut banner = format!("gopher menu ({}{} items)", items.len(), if complete { "" } else { "+" });
+    if !title.is_empty() {
+        banner.push_str(&format!(": {}", title));
+    }
+    Ok(Identification::new(Protocol::Gopher, Some(cap_text(banner, cfg.banner_max_chars))))
+}
+
+// IRC registration; servers answer with the welcome numerics, 433 when the nickname is taken,
+// or NOTICEs while they look up the client
+const IRC_REGISTER: &[u8] = b"NICK ospine\r\nUSER ospine 0 * :ospine\r\n";
+// Upper bound on the registration replies we read; 004 comes before the (possibly long) MOTD
+const MAX_IRC_REPLY: usize = 8 * 1024;
+
+// IRC probe (6660-6669, 6697 over TLS): register and report the server name and version from the
+// welcome numerics (002/004), or that the nickname was taken.
+async fn irc_probe(stream: &mut ProbeStream, port: u16, cfg: &ScanConfig) -> Result<Identification, ProbeError> {
+    if port != 6697 {
+        return irc_register(stream, port, cfg, Vec::new()).await;
+    }
+    let name = tls::server_name(&cfg.host).ok_or(ProbeError::NoMatch)?;
+    let mut session = match timeout(cfg.timeout, tls::connector().connect(name, &mut *stream)).await {
+        Ok(Ok(session)) => session,
+        Ok(Err(e)) if e.kind() == ErrorKind::ConnectionReset => return Err(ProbeError::Reset),
+        Ok(Err(e)) => {
+            if cfg.verbose {
+                eprintln!("{}:{} irc tls handshake failed: {}", cfg.target, port, e);
+            }
+            return Err(ProbeError::NoMatch);
+        }
+        Err(_) => return Err(ProbeError::NoMatch),
+    };
+    match irc_register(&mut session, port, cfg, Vec::new()).await {
+        Ok(ident) => Ok(ident),
+        Err(_) => Ok(Identification::new(Protocol::Tls, None)),
+    }
+}
+
+// Register on top of whatever the server already sent (`buf`), answering PINGs, until the
+// registration settles or the server goes quiet; then QUIT.
+async fn irc_register<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    port: u16,
+    cfg: &ScanConfig,
+    mut buf: Vec<u8>,
+) -> Result<Identification, ProbeError> {
+    send_probe(stream, port, cfg, "irc", IRC_REGISTER).await?;
+    let mut pongs = 0;
+    loop {
+        let messages = irc_messages(&buf);
+        // Some servers hold registration back until their PING is answered
+        let pings: Vec<&IrcMessage> = messages.iter().filter(|m| m.command == "PING").collect();
+        for ping in &pings[pongs..] {
+            let pong = format!("PONG :{}\r\n", ping.params.first().map_or("", String::as_str));
+            send_probe(stream, port, cfg, "irc", pong.as_bytes()).await?;
+        }
+        pongs = pings.len();
+        let settled = messages
+            .iter()
+            .any(|m| matches!(m.command.as_str(), "004" | "005" | "433" | "436" | "465" | "ERROR"));
+        if settled || buf.len() >= MAX_IRC_REPLY {
+            break;
+        }
+        match read_some(stream, cfg.banner_read_len, cfg.timeout, ReadStrategy::Single, cfg.byte_budget.as_deref()).await {
+            Ok(more) => buf.extend_from_slice(&more),
+            Err(ProbeError::Reset) if buf.is_empty() => return Err(ProbeError::Reset),
+            // Quiet or closed: judge what arrived
+            Err(_) => break,
+        }
+    }
+    let Some(banner) = irc_banner(&irc_messages(&buf)) else {
+        // Not IRC, but the reply may still give the service away (e.g. an HTTP 400 before hanging up)
+        let proto = detect_from_bytes(&buf, port);
+        if !buf.is_empty() && !matches!(proto, Protocol::Unknown) {
+            return Ok(Identification::from_bytes(proto, &buf, cfg));
+        }
+        if cfg.verbose && buf.is_empty() {
+            eprintln!("{}:{} irc probe sent, no reply", cfg.target, port);
+        }
+        return Err(ProbeError::NoMatch);
+    };
+    // Best effort: leave cleanly rather than wait for the registration timeout
+    let _ = timeout(cfg.timeout, stream.write_all(b"QUIT\r\n")).await;
+    Ok(Identification::new(Protocol::Irc, Some(cap_text(banner, cfg.banner_max_chars))))
+}
+
+// One IRC protocol line: `[:prefix] COMMAND params... [:trailing]`.
+pub(crate) struct IrcMessage {
+    pub(crate) prefix: Option<String>,
+    command: String,
+    params: Vec<String>,
+}
+
+impl IrcMessage {
+    // Something only an IRC server sends: a prefixed numeric or NOTICE, or the prefix-less
+    // `NOTICE AUTH` of older daemons
+    pub(crate) fn is_server_reply(&self) -> bool {
+        let numeric = self.command.len() == 3 && self.command.bytes().all(|b| b.is_ascii_digit());
+        match self.prefix {
+            Some(_) => numeric || self.command == "NOTICE",
+            None => self.command == "NOTICE" && self.params.first().is_some_and(|p| p == "AUTH"),
         }
-        _ => Err(()),
+    }
+}
+
+pub(crate) fn parse_irc_line(line: &[u8]) -> Option<IrcMessage> {
+    let line = std::str::from_utf8(line).ok()?.trim_end_matches('\r');
+    let (prefix, rest) = match line.strip_prefix(':') {
+        Some(rest) => {
+            let (prefix, rest) = rest.split_once(' ')?;
+            (Some(prefix.to_string()), rest)
+        }
+        None => (None, line),
+    };
+    let (middle, trailing) = match rest.split_once(" :") {
+        Some((middle, trailing)) => (middle, Some(trailing)),
+        None => (rest, None),
+    };
+    let mut words = middle.split(' ').filter(|w| !w.is_empty());
+    let command = words.next()?;
+    if !command.bytes().all(|b| b.is_ascii_alphanumeric()) {
+        return None;
+    }
+    let mut params: Vec<String> = words.map(str::to_string).collect();
+    params.extend(trailing.map(str::to_string));
+    Some(IrcMessage { prefix, command: command.to_ascii_uppercase(), params })
+}
+
+// Complete lines of `buf` that parse as IRC messages.
+fn irc_messages(buf: &[u8]) -> Vec<IrcMessage> {
+    let complete = buf.iter().rposition(|&b| b == b'\n').map_or(&buf[..0], |end| &buf[..end]);
+    complete.split(|&b| b == b'\n').filter_map(parse_irc_line).collect()
+}
+
+// `irc <server> <version>`, plus what stopped registration; None unless an IRC server answered.
+fn irc_banner(messages: &[IrcMessage]) -> Option<String> {
+    // A bare "ERROR" is also memcached's answer to unknown commands; IRC's carries a reason
+    let error = messages.iter().find(|m| m.command == "ERROR").and_then(|m| m.params.last());
+    if error.is_none() && !messages.iter().any(IrcMessage::is_server_reply) {
+        return None;
+    }
+    let numeric = |code: &str| messages.iter().find(|m| m.command == code);
+    let server = numeric("004")
+        .and_then(|m| m.params.get(1).cloned())
+        .or_else(|| messages.iter().filter(|m| m.is_server_reply()).find_map(|m| m.prefix.clone()));
+    // 004: <nick> <server> <version> <modes>...; else 002: "Your host is <server>, running version <version>"
+    let version = numeric("004").and_then(|m| m.params.get(2).cloned()).or_else(|| {
+        let text = numeric("002")?.params.last()?;
+        let (_, after) = text.split_once("running version ")?;
+        after.split([',', ' ']).next().map(str::to_string)
+    });
+    let mut banner = String::from("irc");
+    for part in [server, version].into_iter().flatten() {
+        banner.push(' ');
+        banner.push_str(&part);
+    }
+    if numeric("433").is_some() {
+        banner.push_str(" (nickname in use)");
+    } else if let Some(reason) = error {
+        banner.push_str(&format!(" (error: {})", reason));
+    }
+    Some(banner)
+}
+
+// Minimal TLS ClientHello (no SNI), works for many servers. Not a full handshake implementation.
+// This is a commonly used small ClientHello payload, plus ALPN so the ServerHello says whether the
+// endpoint speaks HTTP/2.
+const CLIENT_HELLO: &[u8] = &[
+    0x16, 0x03, 0x01, 0x00, 0x43, // Handshake record, TLS 1.0, length 0x0043
+    0x01, 0x00, 0x00, 0x3f, // ClientHello, length 0x003f
+    0x03, 0x03, // TLS 1.2
+    // Random (32 bytes)
+    0x53, 0x43, 0x4e, 0x52, 0x00, 0x01, 0x02, 0x03,
+    0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b,
+    0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13,
+    0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b,
+    0x00, // session id length
+    0x00, 0x04, // cipher suites length (4 bytes)
+    0x00, 0x2f, // TLS_RSA_WITH_AES_128_CBC_SHA
+    0x00, 0x35, // TLS_RSA_WITH_AES_256_CBC_SHA
+    0x01, // compression methods length
+    0x00, // null compression
+    0x00, 0x12, // extensions length (18 bytes)
+    0x00, 0x10, 0x00, 0x0e, // ALPN, length 14
+    0x00, 0x0c, // protocol name list length (12 bytes)
+    0x02, b'h', b'2',
+    0x08, b'h', b't', b't', b'p', b'/', b'1', b'.', b'1',
+];
+
+async fn tls_probe(stream: &mut ProbeStream, port: u16, cfg: &ScanConfig) -> Result<Identification, ProbeError> {
+    // Sent in one piece; a partial send is reported by `send_probe` (often an MTU problem on tunnels)
+    let buf = exchange(stream, port, cfg, "tls", CLIENT_HELLO, cfg.banner_read_len).await?;
+    // A handshake record, or an alert: servers with ALPN configured may refuse ours that way
+    // (no_application_protocol), which is still TLS
+    if matches!(buf.first(), Some(0x16 | 0x15)) && matches!(buf.get(1), Some(0x03)) {
+        return Ok(Identification::from_bytes(Protocol::Tls, &buf, cfg));
+    }
+    Err(ProbeError::NoMatch)
+}
+
+// Upgrade command and expected positive reply prefix for each STARTTLS-capable protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StarttlsDialect {
+    Smtp,
+    Imap,
+    Pop3,
+    Ftp,
+}
+
+impl StarttlsDialect {
+    // Told apart by the greeting itself rather than the detected protocol, which for IMAP, POP3
+    // and FTP only comes from the (replaceable) probe file; FTP and SMTP both greet with "220".
+    fn from_greeting(buf: &[u8]) -> Option<Self> {
+        if buf.starts_with(b"* OK") {
+            Some(StarttlsDialect::Imap)
+        } else if buf.starts_with(b"+OK") {
+            Some(StarttlsDialect::Pop3)
+        } else if buf.starts_with(b"220 ") || buf.starts_with(b"220-") {
+            let first_line = buf.split(|&b| b == b'\n').next().unwrap_or(buf);
+            if tw_contains_ci(first_line, b"ftp") {
+                Some(StarttlsDialect::Ftp)
+            } else {
+                Some(StarttlsDialect::Smtp)
+            }
+        } else {
+            None
+        }
+    }
+
+    // The upgrade command and the prefix of the reply that accepts it
+    fn command(self) -> (&'static [u8], &'static [u8]) {
+        match self {
+            StarttlsDialect::Smtp => (b"STARTTLS\r\n", b"220"),
+            StarttlsDialect::Imap => (b"a001 STARTTLS\r\n", b"a001 OK"),
+            StarttlsDialect::Pop3 => (b"STLS\r\n", b"+OK"),
+            StarttlsDialect::Ftp => (b"AUTH TLS\r\n", b"234"),
+        }
+    }
+}
+
+// Issue the protocol's STARTTLS command after its plaintext greeting and complete a TLS handshake
+// on the upgraded stream. Some(Some(session)) when the handshake finished, Some(None) when the
+// server refused the command or the handshake failed; None if the greeting has no STARTTLS.
+async fn starttls_upgrade(
+    stream: &mut ProbeStream,
+    port: u16,
+    cfg: &ScanConfig,
+    greeting: &[u8],
+) -> Option<Option<TlsSession>> {
+    let dialect = StarttlsDialect::from_greeting(greeting)?;
+    let (command, ok_reply) = dialect.command();
+    if dialect == StarttlsDialect::Smtp {
+        // SMTP only advertises STARTTLS after EHLO; wait for the last line of the (multi-line) reply
+        send_probe(stream, port, cfg, "starttls", b"EHLO ospine\r\n").await.ok()?;
+        read_reply(stream, cfg, smtp_reply_complete).await.ok()?;
+    }
+    if send_probe(stream, port, cfg, "starttls", command).await.is_err() {
+        return Some(None);
+    }
+    match read_reply(stream, cfg, |buf| buf.ends_with(b"\n")).await {
+        Ok(reply) if reply.starts_with(ok_reply) => {}
+        _ => return Some(None),
+    }
+    let Some(name) = tls::server_name(&cfg.host) else {
+        return Some(None);
+    };
+    match timeout(cfg.timeout, tls::connector().connect(name, &mut *stream)).await {
+        Ok(Ok(session)) => Some(Some(tls::session_details(session.get_ref().1))),
+        Ok(Err(e)) => {
+            if cfg.verbose {
+                eprintln!("{}:{} starttls handshake failed: {}", cfg.target, port, e);
+            }
+            Some(None)
+        }
+        Err(_) => Some(None),
+    }
+}
+
+// True once an SMTP reply's final line ("250 ...", not "250-...") has arrived.
+fn smtp_reply_complete(buf: &[u8]) -> bool {
+    buf.ends_with(b"\n")
+        && buf[..buf.len() - 1]
+            .rsplit(|&b| b == b'\n')
+            .next()
+            .is_some_and(|last| last.get(3) == Some(&b' '))
+}
+
+// Read line-oriented reply chunks until `complete` holds (or 4 KiB, or a timeout).
+async fn read_reply(
+    stream: &mut ProbeStream,
+    cfg: &ScanConfig,
+    complete: impl Fn(&[u8]) -> bool,
+) -> Result<Vec<u8>, ProbeError> {
+    const MAX_REPLY: usize = 4096;
+    let mut buf = Vec::new();
+    while !complete(&buf) && buf.len() < MAX_REPLY {
+        buf.extend(read_some(stream, MAX_REPLY, cfg.timeout, ReadStrategy::Single, cfg.byte_budget.as_deref()).await?);
+    }
+    Ok(buf)
+}
+
+async fn file_probe(
+    stream: &mut ProbeStream,
+    port: u16,
+    probe: &ServiceProbe,
+    cfg: &ScanConfig,
+) -> Result<Identification, ProbeError> {
+    let buf = exchange(stream, port, cfg, &probe.name, &probe.payload, cfg.banner_read_len).await?;
+    match probe.match_response(&buf) {
+        Some(service) => Ok(Identification::from_bytes(Protocol::from_service_name(service), &buf, cfg)),
+        None => Err(ProbeError::NoMatch),
     }
 }
 
@@ -289,13 +1873,276 @@ fn to_safe_string(buf: &[u8]) -> String {
     s
 }
 
-fn hex_preview(buf: &[u8]) -> String {
-    const MAX: usize = 64;
-    let take = buf.len().min(MAX);
-    let mut out = String::from("hex:");
-    for b in &buf[..take] {
-        out.push_str(&format!("{:02x}", b));
+// Render a response for display: mostly-text buffers become a (lossy UTF-8) string, mostly-binary
+// ones a compact hexdump. `max_chars` caps the displayed text (or dumped bytes) independently of
+// how much was read. With `hex` (--banner-hex) every received byte is hex-encoded instead, capped
+// at `max_chars` bytes when set. Returns the rendering and whether the data looks binary.
+pub fn render_banner(buf: &[u8], max_chars: Option<usize>, hex: bool) -> (String, bool) {
+    render_banner_decoded(buf, max_chars, hex, UTF_8)
+}
+
+/// `render_banner` for text in `encoding` (`--banner-encoding`), e.g. CP1252 or GBK banners of
+/// legacy devices. Invalid sequences become U+FFFD, as with UTF-8; hex output is unaffected.
+pub fn render_banner_decoded(buf: &[u8], max_chars: Option<usize>, hex: bool, encoding: &'static Encoding) -> (String, bool) {
+    if hex {
+        let take = max_chars.map_or(buf.len(), |m| m.min(buf.len()));
+        let mut out: String = buf[..take].iter().map(|b| format!("{:02x}", b)).collect();
+        if take < buf.len() {
+            out.push_str(&format!("…[truncated {} bytes]", buf.len() - take));
+        }
+        return (out, looks_binary(buf, encoding));
+    }
+    if looks_binary(buf, encoding) {
+        (hexdump(buf, max_chars.map_or(HEXDUMP_MAX, |m| m.min(HEXDUMP_MAX))), true)
+    } else {
+        (cap_text(decode_text(buf, encoding), max_chars), false)
+    }
+}
+
+// `buf` as text in `encoding`, lossily and without trailing NULs (`to_safe_string` for any encoding).
+fn decode_text(buf: &[u8], encoding: &'static Encoding) -> String {
+    let mut s = encoding.decode_without_bom_handling(buf).0.into_owned();
+    s.truncate(s.trim_end_matches('\0').len());
+    s
+}
+
+// Keep at most `max_chars` characters, noting how many bytes were cut.
+fn cap_text(mut s: String, max_chars: Option<usize>) -> String {
+    if let Some((idx, _)) = max_chars.and_then(|max| s.char_indices().nth(max)) {
+        let dropped = s.len() - idx;
+        s.truncate(idx);
+        s.push_str(&format!("…[truncated {} bytes]", dropped));
+    }
+    s
+}
+
+// Binary if more than 1 in 8 bytes is neither printable ASCII, common whitespace, nor part of valid
+// text in `encoding`. Trailing NUL padding is ignored.
+fn looks_binary(buf: &[u8], encoding: &'static Encoding) -> bool {
+    let end = buf.len() - buf.iter().rev().take_while(|&&b| b == 0).count();
+    let data = &buf[..end];
+    if data.is_empty() {
+        return false;
+    }
+    let text = encoding.decode_without_bom_handling(data).0;
+    let odd = text
+        .chars()
+        .filter(|&c| c == char::REPLACEMENT_CHARACTER || (c.is_control() && !matches!(c, '\r' | '\n' | '\t')))
+        .count();
+    odd * 8 > text.chars().count()
+}
+
+// Most bytes a binary banner's hexdump shows
+const HEXDUMP_MAX: usize = 256;
+
+// `hexdump -C` style preview: offset, 16 hex bytes, ASCII gutter; capped at `max` bytes.
+fn hexdump(buf: &[u8], max: usize) -> String {
+    use std::fmt::Write as _;
+    let take = buf.len().min(max);
+    let mut out = String::new();
+    for (i, chunk) in buf[..take].chunks(16).enumerate() {
+        let _ = write!(out, "{:08x} ", i * 16);
+        for j in 0..16 {
+            if j == 8 {
+                out.push(' ');
+            }
+            match chunk.get(j) {
+                Some(b) => { let _ = write!(out, " {:02x}", b); }
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str("  |");
+        out.extend(chunk.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }));
+        out.push_str("|\n");
+    }
+    if buf.len() > take {
+        let _ = writeln!(out, "…[truncated {} bytes]", buf.len() - take);
     }
-    if buf.len() > MAX { out.push_str("…"); }
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+    use tokio::io::{duplex, DuplexStream, ReadBuf};
+
+    // Every buffer offered to `poll_write`, so a test can tell which probes were attempted
+    struct Recorded {
+        inner: DuplexStream,
+        offered: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    impl AsyncRead for Recorded {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for Recorded {
+        fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+            self.offered.lock().unwrap().push(buf.to_vec());
+            Pin::new(&mut self.inner).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.inner).poll_shutdown(cx)
+        }
+    }
+
+    fn options(list: &[&str]) -> Vec<String> {
+        list.iter().map(|o| o.to_string()).collect()
+    }
+
+    #[test]
+    fn telnet_will_do_pairs() {
+        // Cisco IOS: WILL ECHO, WILL SUPPRESS-GO-AHEAD, DO TERMINAL-TYPE, DO NAWS, then the banner
+        let capture = b"\xff\xfb\x01\xff\xfb\x03\xff\xfd\x18\xff\xfd\x1f\r\n\r\nUser Access Verification\r\n\r\nUsername: ";
+        let (text, opts) = strip_telnet_iac(capture);
+        assert_eq!(text.as_deref(), Some("User Access Verification\r\n\r\nUsername:"));
+        assert_eq!(opts, options(&["WILL ECHO", "WILL SUPPRESS-GO-AHEAD", "DO TERMINAL-TYPE", "DO NAWS"]));
+
+        // BusyBox telnetd repeats WILL ECHO; each entry is kept once
+        let capture = b"\xff\xfd\x01\xff\xfd\x1f\xff\xfb\x01\xff\xfb\x03\xff\xfb\x01";
+        let (text, opts) = strip_telnet_iac(capture);
+        assert_eq!(text, None);
+        assert_eq!(opts, options(&["DO ECHO", "DO NAWS", "WILL ECHO", "WILL SUPPRESS-GO-AHEAD"]));
+    }
+
+    #[test]
+    fn telnet_subnegotiation() {
+        // DO TERMINAL-TYPE, then SB TERMINAL-TYPE SEND IAC SE (the server asking for our terminal)
+        let capture = b"\xff\xfd\x18\xff\xfa\x18\x01\xff\xf0login: ";
+        let (text, opts) = strip_telnet_iac(capture);
+        assert_eq!(text.as_deref(), Some("login:"));
+        assert_eq!(opts, options(&["DO TERMINAL-TYPE", "SB TERMINAL-TYPE"]));
+    }
+
+    #[test]
+    fn telnet_escaped_iac() {
+        // IAC IAC is one literal 0xFF data byte, rendered as a replacement character
+        let (text, opts) = strip_telnet_iac(b"code \xff\xff end\r\n");
+        assert_eq!(text.as_deref(), Some("code \u{fffd} end"));
+        assert!(opts.is_empty());
+    }
+
+    #[test]
+    fn telnet_truncated_iac() {
+        // The read ended right after an IAC, or after IAC WILL without its option
+        let (text, opts) = strip_telnet_iac(b"\xff\xfb\x01Password: \xff");
+        assert_eq!(text.as_deref(), Some("Password:"));
+        assert_eq!(opts, options(&["WILL ECHO"]));
+        let (text, opts) = strip_telnet_iac(b"Password: \xff\xfb");
+        assert_eq!(text.as_deref(), Some("Password:"));
+        assert!(opts.is_empty());
+    }
+
+    fn test_config() -> ScanConfig {
+        ScanConfig::builder().target("127.0.0.1").timeout(Duration::from_millis(100)).build().unwrap()
+    }
+
+    #[tokio::test]
+    async fn stalled_write_is_broken() {
+        // A peer that never reads: 16 bytes fit in the pipe, the rest of the probe never leaves
+        let (mut client, _peer) = duplex(16);
+        let res = send_probe(&mut client, 80, &test_config(), "test", &[b'x'; 64]).await;
+        assert_eq!(res, Err(ProbeError::Broken));
+    }
+
+    #[tokio::test]
+    async fn no_probe_follows_a_broken_one() {
+        let (client, mut peer) = duplex(16);
+        let offered = Arc::new(Mutex::new(Vec::new()));
+        let conn = Recorded { inner: client, offered: offered.clone() };
+        let mut stream = ProbeStream::new(conn, None, "127.0.0.1", 80, 512);
+
+        let ident = identify_and_banner(&mut stream, 80, &test_config()).await;
+        assert_eq!(ident.protocol, Some(Protocol::Unknown));
+        assert_eq!(ident.banner, None);
+
+        // Only the first probe got anywhere: every write offered is what was left of it
+        let offered = offered.lock().unwrap().clone();
+        let first = offered.first().expect("a probe was sent").clone();
+        assert!(offered.iter().all(|buf| first.ends_with(buf)), "a second probe was written");
+        let mut received = vec![0u8; 16];
+        peer.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, first[..16]);
+    }
+
+    // Answer each command line with the next reply, then report the lines received and the first
+    // byte sent after them (a ClientHello starts with 0x16).
+    async fn starttls_exchange(greeting: &[u8], replies: &[&[u8]]) -> (Option<Option<TlsSession>>, Vec<Vec<u8>>, Option<u8>) {
+        let (client, mut peer) = duplex(4096);
+        let greeting = greeting.to_vec();
+        let upgrade = async move {
+            let mut stream = ProbeStream::new(client, None, "127.0.0.1", 25, 512);
+            starttls_upgrade(&mut stream, 25, &test_config(), &greeting).await
+        };
+        let server = async move {
+            let mut lines = Vec::new();
+            for reply in replies {
+                let mut line = Vec::new();
+                while !line.ends_with(b"\n") {
+                    line.push(peer.read_u8().await.unwrap());
+                }
+                lines.push(line);
+                peer.write_all(reply).await.unwrap();
+            }
+            let next = peer.read_u8().await.ok();
+            (lines, next)
+        };
+        let (result, (lines, next)) = tokio::join!(upgrade, server);
+        (result, lines, next)
+    }
+
+    // The accepted upgrade sends exactly `commands`, then a ClientHello
+    async fn assert_starttls(greeting: &[u8], replies: &[&[u8]], commands: &[&[u8]]) {
+        let (result, lines, next) = starttls_exchange(greeting, replies).await;
+        assert_eq!(lines, commands.iter().map(|c| c.to_vec()).collect::<Vec<_>>());
+        assert_eq!(next, Some(0x16), "no ClientHello after {:?}", String::from_utf8_lossy(greeting));
+        // The peer hangs up instead of answering the ClientHello
+        assert_eq!(result, Some(None));
+    }
+
+    #[tokio::test]
+    async fn starttls_commands_follow_the_greeting() {
+        assert_starttls(
+            b"220 mx.test ESMTP\r\n",
+            &[b"250-mx.test\r\n250 STARTTLS\r\n", b"220 go ahead\r\n"],
+            &[b"EHLO ospine\r\n", b"STARTTLS\r\n"],
+        )
+        .await;
+        assert_starttls(b"* OK IMAP4rev1 ready\r\n", &[b"a001 OK begin TLS\r\n"], &[b"a001 STARTTLS\r\n"]).await;
+        assert_starttls(b"+OK POP3 ready\r\n", &[b"+OK begin TLS\r\n"], &[b"STLS\r\n"]).await;
+        assert_starttls(b"220 (vsFTPd 3.0.5)\r\n", &[b"234 Proceed\r\n"], &[b"AUTH TLS\r\n"]).await;
+    }
+
+    #[tokio::test]
+    async fn starttls_refused_sends_no_client_hello() {
+        let cases: [(&[u8], &[u8]); 3] = [
+            (b"* OK IMAP4rev1 ready\r\n", b"a001 BAD unknown command\r\n"),
+            (b"+OK POP3 ready\r\n", b"-ERR not supported\r\n"),
+            (b"220 FTP server ready\r\n", b"500 AUTH not understood\r\n"),
+        ];
+        for (greeting, refusal) in cases {
+            let (result, lines, next) = starttls_exchange(greeting, &[refusal]).await;
+            assert_eq!(lines.len(), 1);
+            assert_eq!(next, None);
+            assert_eq!(result, Some(None));
+        }
+    }
+
+    #[tokio::test]
+    async fn starttls_needs_a_known_greeting() {
+        let (result, lines, next) = starttls_exchange(b"SSH-2.0-OpenSSH_9.6\r\n", &[]).await;
+        assert_eq!(result, None);
+        assert!(lines.is_empty());
+        assert_eq!(next, None);
+    }
+}
@@ -0,0 +1,161 @@
+//! Scanning through an HTTP proxy (`--proxy`, or `HTTPS_PROXY`/`HTTP_PROXY`): each port is reached
+//! with `CONNECT host:port`, and a `200` answer hands the tunnel to the probes as if it were a
+//! direct connection. Any other answer counts as the port being closed.
+
+use anyhow::{anyhow, bail, Result};
+use std::io::{self, ErrorKind};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+// Port assumed when the proxy URL has none (as curl does)
+const DEFAULT_PROXY_PORT: u16 = 1080;
+// Upper bound on the proxy's response head
+const MAX_RESPONSE_HEAD: usize = 8 * 1024;
+
+#[derive(Clone, Debug)]
+pub struct HttpProxy {
+    host: String,
+    port: u16,
+    /// `Proxy-Authorization` value, from credentials in the URL
+    authorization: Option<String>,
+}
+
+impl HttpProxy {
+    /// Parse `[http://][user:password@]host[:port][/]`; credentials may be percent-encoded.
+    pub fn parse(url: &str) -> Result<Self> {
+        let rest = match url.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => rest,
+            Some((scheme, _)) => bail!("unsupported proxy scheme '{}' (only http:// proxies are supported)", scheme),
+            None => url,
+        };
+        let authority = rest.split('/').next().unwrap_or_default();
+        let (userinfo, hostport) = match authority.rsplit_once('@') {
+            Some((userinfo, hostport)) => (Some(userinfo), hostport),
+            None => (None, authority),
+        };
+        let (host, port) = if let Some(bracketed) = hostport.strip_prefix('[') {
+            let (host, after) = bracketed
+                .split_once(']')
+                .ok_or_else(|| anyhow!("invalid proxy address '{}'", hostport))?;
+            (host, after.strip_prefix(':'))
+        } else {
+            match hostport.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (hostport, None),
+            }
+        };
+        if host.is_empty() {
+            bail!("proxy URL '{}' has no host", url);
+        }
+        let port = match port {
+            Some(p) => p.parse::<u16>().map_err(|_| anyhow!("invalid proxy port '{}'", p))?,
+            None => DEFAULT_PROXY_PORT,
+        };
+        let authorization = userinfo.map(|u| format!("Basic {}", base64(&percent_decode(u))));
+        Ok(Self { host: host.to_string(), port, authorization })
+    }
+
+    /// Proxy from `HTTPS_PROXY`/`https_proxy`, else `HTTP_PROXY`/`http_proxy`.
+    pub fn from_env() -> Result<Option<Self>> {
+        match env_var(&["HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy"]) {
+            Some(url) => Self::parse(&url).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether `NO_PROXY`/`no_proxy` (exact names, `.suffix` domains, or `*`) exempts `target`.
+    pub fn bypassed_by_env(target: &str) -> bool {
+        let Some(no_proxy) = env_var(&["NO_PROXY", "no_proxy"]) else {
+            return false;
+        };
+        let target = target.trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase();
+        no_proxy.split(',').map(|e| e.trim().to_ascii_lowercase()).any(|entry| {
+            let domain = entry.trim_start_matches('.');
+            entry == "*" || (!domain.is_empty() && (target == domain || target.ends_with(&format!(".{}", domain))))
+        })
+    }
+
+    /// Host part of the proxy address, as given.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// `host:port` of the proxy, for messages.
+    pub fn address(&self) -> String {
+        if self.host.contains(':') { format!("[{}]:{}", self.host, self.port) } else { format!("{}:{}", self.host, self.port) }
+    }
+
+    /// Open a tunnel to `host:port` through the proxy.
+    pub async fn connect(&self, host: &str, port: u16) -> io::Result<TcpStream> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        let authority = if host.contains(':') { format!("[{}]:{}", host, port) } else { format!("{}:{}", host, port) };
+        let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+        if let Some(auth) = &self.authorization {
+            request.push_str(&format!("Proxy-Authorization: {}\r\n", auth));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        // Byte by byte, so nothing the target sends through the tunnel is consumed with the head
+        let mut head = Vec::with_capacity(256);
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() >= MAX_RESPONSE_HEAD {
+                return Err(io::Error::new(ErrorKind::InvalidData, "proxy response head too large"));
+            }
+            let mut byte = [0u8; 1];
+            if stream.read(&mut byte).await? == 0 {
+                return Err(io::Error::new(ErrorKind::ConnectionAborted, "proxy closed the connection"));
+            }
+            head.push(byte[0]);
+        }
+        let status_line = String::from_utf8_lossy(head.split(|&b| b == b'\r').next().unwrap_or_default()).into_owned();
+        match status_line.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(stream),
+            Some("407") => Err(io::Error::new(ErrorKind::PermissionDenied, "proxy authentication required (407)")),
+            // 403, 502, 503, 504...: the proxy could not (or would not) reach the port
+            _ => Err(io::Error::new(ErrorKind::ConnectionRefused, format!("proxy CONNECT failed: {}", status_line))),
+        }
+    }
+}
+
+// First of `names` that is set and non-empty
+fn env_var(names: &[&str]) -> Option<String> {
+    names.iter().find_map(|n| std::env::var(n).ok().filter(|v| !v.trim().is_empty()))
+}
+
+fn percent_decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = s.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+// Standard base64 with padding (RFC 4648), for Basic credentials
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let n = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+        for (i, shift) in [18, 12, 6, 0].into_iter().enumerate() {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> shift & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
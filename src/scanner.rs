@@ -1,104 +1,669 @@
-use crate::protocols::identify_and_banner;
-use crate::types::{PortSpec, ScanConfig, ScanResult};
+use crate::capture::ProbeStream;
+use crate::protocols::{identify_and_banner, partial_identification, Identification};
+use crate::types::{rfc3339_utc, PortSpec, Protocol, ReadStrategy, ScanConfig, ScanResult, ServiceInfo, SourcePortLease};
 use anyhow::Result;
-use futures::stream::{self, StreamExt};
-use tokio::net::TcpStream;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use futures::stream::{self, Stream, StreamExt};
+use tokio::net::{TcpSocket, TcpStream};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Arc, Once};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{mpsc, Semaphore};
 use tokio::time;
 
+/// Scan every port of `cfg` and collect the results, sorted by port.
 pub async fn scan_ports(cfg: ScanConfig) -> Result<Vec<ScanResult>> {
+    let mut out: Vec<ScanResult> = scan_ports_stream(cfg).collect().await;
+    // sort by port for stable output
+    out.sort_by_key(|r| r.port);
+    Ok(out)
+}
+
+/// Scan every port of `cfg`, yielding each result as soon as its port is done (completion order).
+/// Dropping the stream (or a `scan_ports` future) cancels the scan: connections in progress are
+/// closed and their connection permits returned.
+pub fn scan_ports_stream(cfg: ScanConfig) -> impl Stream<Item = ScanResult> {
+    // Bounded, so a slow consumer holds back the scan instead of buffering results without limit
+    let (tx, rx) = mpsc::channel(cfg.concurrency.max(1));
+    // The stream owns the scan task: dropping it mid-scan aborts the task instead of leaving it
+    // running until the next result fails to send
+    let task = AbortOnDrop(tokio::spawn(feed_results(cfg, tx)));
+    stream::unfold((rx, task), |(mut rx, task)| async move { rx.recv().await.map(|r| (r, (rx, task))) })
+}
+
+// Aborting drops the scan task's in-flight futures where they stand: pending connects are
+// cancelled and their sockets closed, and the host, global and per-attempt permits they hold are
+// released with them. Finished tasks are unaffected.
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+async fn feed_results(mut cfg: ScanConfig, tx: mpsc::Sender<ScanResult>) {
     let ports: Vec<u16> = match &cfg.port_spec {
         PortSpec::List(v) => v.clone(),
     };
 
-    // Shared results vector guarded by a mutex; avoids spawning one task per port up-front
-    let results: Arc<Mutex<Vec<ScanResult>>> = Arc::new(Mutex::new(Vec::new()));
+    // Hostnames are resolved once up front rather than on every connect; a name that will not
+    // resolve fails all of its ports with the same error
+    let needs_lookup = cfg.proxy.is_none() && cfg.scope_id.is_none() && !cfg.no_dns;
+    if needs_lookup && cfg.host.parse::<IpAddr>().is_err() {
+        match resolve_host(&cfg.host).await {
+            Ok(addrs) => cfg.host_addrs = Some(addrs),
+            Err(e) => {
+                for port in ports {
+                    let r = ScanResult { error: Some(e.clone()), ..blank_result(&cfg, port, "error") };
+                    if tx.send(r).await.is_err() {
+                        return;
+                    }
+                }
+                return;
+            }
+        }
+    }
 
-    // Process ports with bounded concurrency, avoiding massive task fan-out
-    let results_cloned = results.clone();
-    stream::iter(ports.into_iter())
-        .for_each_concurrent(cfg.concurrency, move |port| {
-            let cfg_clone = cfg.clone();
-            let results_inner = results_cloned.clone();
+    let host = Arc::new(HostState {
+        // Cap simultaneous connections to this host (gentler on fragile devices); defaults to --concurrency
+        connections: Semaphore::new(cfg.per_host_connections.unwrap_or(cfg.concurrency).max(1)),
+        timeouts: AtomicUsize::new(0),
+    });
+
+    // Two phases sharing the host, global and rate limits: a cheap connect sweep over every port,
+    // then a fresh connection to each open port for banners and probes. Resets are already final.
+    let two_phase = cfg.two_phase && !cfg.connect_only;
+    // Hollow ports are weeded out in the probing phase, so the sweep does not wait on every connection
+    let sweep_cfg = if two_phase {
+        ScanConfig { connect_only: true, verify_open: None, ..cfg.clone() }
+    } else {
+        cfg.clone()
+    };
+    let mut open: Vec<u16> = Vec::new();
+    // --stop-after-open: dropping the sweep cancels the ports still in flight or queued
+    let mut found = 0;
+    let mut sweep = Box::pin(run_phase(sweep_cfg, ports, host.clone()));
+    while let Some(r) = sweep.next().await {
+        found += usize::from(r.open);
+        if two_phase && r.open && !r.reset_after_connect && !cfg.skips_banner(r.port) {
+            open.push(r.port);
+        } else if tx.send(r).await.is_err() {
+            // The consumer went away: stop scanning
+            return;
+        }
+        if cfg.stop_after_open.is_some_and(|n| found >= n) {
+            break;
+        }
+    }
+    drop(sweep);
+    if open.is_empty() {
+        return;
+    }
+    let mut probe = Box::pin(run_phase(cfg, open, host));
+    while let Some(r) = probe.next().await {
+        if tx.send(r).await.is_err() {
+            return;
+        }
+    }
+}
+
+// Shared by every port of one target, across both phases of a two-phase scan
+struct HostState {
+    connections: Semaphore,
+    // Connect timeouts so far, for the --max-host-timeouts circuit breaker
+    timeouts: AtomicUsize,
+}
+
+impl HostState {
+    fn timeout_budget_spent(&self, cfg: &ScanConfig) -> bool {
+        cfg.max_host_timeouts.is_some_and(|max| self.timeouts.load(AtomicOrdering::Relaxed) >= max)
+    }
+}
+
+// Scan `ports` with bounded concurrency, yielding results as they complete; ports are only
+// started as slots free up, avoiding massive task fan-out.
+fn run_phase(cfg: ScanConfig, ports: Vec<u16>, host: Arc<HostState>) -> impl Stream<Item = ScanResult> {
+    let concurrency = cfg.concurrency;
+    stream::iter(ports)
+        .map(move |port| {
+            let cfg = cfg.clone();
+            let host = host.clone();
             async move {
                 // Perform the scan for a single port, handling errors inline
-                let item = match scan_one(&cfg_clone, port).await {
+                match scan_one(&cfg, port, &host).await {
                     Ok(it) => it,
                     Err(e) => ScanResult {
-                        target: cfg_clone.target.clone(),
-                        port,
-                        open: false,
-                        protocol: None,
-                        banner: None,
                         error: Some(format!("task error: {}", e)),
+                        ..blank_result(&cfg, port, "error")
                     },
-                };
-                // Push into results
-                results_inner.lock().await.push(item);
+                }
             }
         })
-        .await;
+        .buffer_unordered(concurrency)
+}
 
-    // sort by port for stable output
-    let mut out = results.lock().await.clone();
-    out.sort_by_key(|r| r.port);
-    Ok(out)
+// Connect attempts retried after EMFILE/ENFILE before the error is recorded (~45s of back-off)
+const MAX_FD_RETRIES: u32 = 30;
+
+// Lookups of a hostname target before giving up on a transient failure, and the pause before the
+// first retry (doubled for each one after)
+const RESOLVE_ATTEMPTS: u32 = 3;
+const RESOLVE_BACKOFF: Duration = Duration::from_millis(100);
+
+// Ceiling on an RTT-derived probe timeout, however slow the handshake was
+const MAX_PROBE_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Pause that ends a banner on `--reprobe-unknown` connections, unless --read-until-idle set one
+const REPROBE_IDLE: Duration = Duration::from_millis(200);
+
+// `reason` of an open port whose peer closed the connection without a word (`--verify-open`)
+const HOLLOW_REASON: &str = "open-but-hollow";
+
+// `error` of an open port whose banner was not read because the run's `--max-total-bytes` budget
+// was spent
+const BANNER_BUDGET_ERROR: &str = "banner budget exhausted";
+
+// `error` of a port that was not tried because its host had already run into
+// `--max-host-timeouts` connect timeouts; its reason is `no-response`, like a timeout's
+const HOST_TIMEOUTS_ERROR: &str = "skipped: host timeout budget exhausted";
+
+impl ScanResult {
+    /// Open, but the peer closed the connection without sending anything (`--verify-open`).
+    pub fn is_hollow(&self) -> bool {
+        self.reason == HOLLOW_REASON
+    }
+
+    /// Open, with the banner left unread because the `--max-total-bytes` budget was spent.
+    pub fn banner_budget_spent(&self) -> bool {
+        self.open && self.error.as_deref() == Some(BANNER_BUDGET_ERROR)
+    }
+
+    /// Not tried, because its host had already run into `--max-host-timeouts` connect timeouts.
+    pub fn skipped_for_host_timeouts(&self) -> bool {
+        self.error.as_deref() == Some(HOST_TIMEOUTS_ERROR)
+    }
 }
 
-async fn scan_one(cfg: &ScanConfig, port: u16) -> Result<ScanResult> {
-    let target = cfg.target.clone();
+// Emitted once per run the first time descriptors run out
+static FD_WARNING: Once = Once::new();
 
-    // Global rate limit: acquire a token before attempting a connection.
-    // Do this before acquiring the global connection permit so we don't hold
-    // scarce connection slots while waiting for the next rate window.
-    cfg.rate_limiter.acquire().await;
+// Whether the peer closes (FIN or RST) a fresh connection within `grace` without sending any data.
+// Peeking leaves early data, a real service's banner, for the probes.
+async fn is_hollow(stream: &TcpStream, grace: Duration) -> bool {
+    let mut byte = [0u8; 1];
+    match time::timeout(grace, stream.peek(&mut byte)).await {
+        Ok(Ok(0)) => true,
+        Ok(Err(e)) => matches!(e.kind(), ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted),
+        // Data waiting, or still established and silent after the grace period
+        _ => false,
+    }
+}
 
-    // Acquire a global permit to enforce process-wide connection cap.
-    // Held for the duration of this scan operation.
-    let _global_permit = cfg
-        .global_limit
-        .clone()
-        .acquire_owned()
-        .await
-        .expect("global semaphore not closed");
+// nmap-style reason for a failed connect: a refusal, an ICMP unreachable, or a local error.
+fn refusal_reason(e: &std::io::Error) -> &'static str {
+    match e.raw_os_error() {
+        _ if e.kind() == ErrorKind::ConnectionRefused => "conn-refused",
+        Some(code) if code == libc::EHOSTUNREACH => "host-unreach",
+        Some(code) if code == libc::ENETUNREACH => "net-unreach",
+        _ => "error",
+    }
+}
+
+// Coarse OS family from the TCP options the peer negotiated in its SYN-ACK (Linux: TCP_INFO). A
+// connected socket never sees the SYN-ACK's TTL, so the hint rests on the options alone: Windows
+// omits timestamps and scales windows by 8, Linux uses timestamps (typically scale 7), BSD and macOS
+// scale by 6, and embedded stacks and network gear often skip window scaling altogether.
+#[cfg(target_os = "linux")]
+fn os_hint(stream: &TcpStream) -> Option<String> {
+    use std::os::fd::AsRawFd;
+    // linux/tcp.h
+    const TCPI_OPT_TIMESTAMPS: u8 = 1;
+    const TCPI_OPT_SACK: u8 = 2;
+    const TCPI_OPT_WSCALE: u8 = 4;
+
+    let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+    // SAFETY: `info` is a writable tcp_info of `len` bytes, and `stream` keeps the descriptor open
+    let rc = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut info as *mut libc::tcp_info as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if rc != 0 {
+        return None;
+    }
+    let timestamps = info.tcpi_options & TCPI_OPT_TIMESTAMPS != 0;
+    let sack = info.tcpi_options & TCPI_OPT_SACK != 0;
+    // The peer's shift is the low nibble (tcpi_snd_wscale)
+    let wscale = (info.tcpi_options & TCPI_OPT_WSCALE != 0).then_some(info.tcpi_snd_rcv_wscale & 0x0f);
+    let family = match (timestamps, wscale) {
+        (_, None) => "embedded/network device",
+        (false, Some(8)) => "windows",
+        (true, Some(6)) => "bsd/macos",
+        (true, Some(_)) => "linux",
+        (false, Some(_)) => "unknown",
+    };
+    let mut traits = Vec::new();
+    if timestamps {
+        traits.push("ts".to_string());
+    }
+    if sack {
+        traits.push("sack".to_string());
+    }
+    if let Some(shift) = wscale {
+        traits.push(format!("wscale {}", shift));
+    }
+    traits.push(format!("mss {}", info.tcpi_snd_mss));
+    Some(format!("{} ({})", family, traits.join(", ")))
+}
+
+// TCP_INFO is Linux-only; elsewhere the hint is simply omitted
+#[cfg(not(target_os = "linux"))]
+fn os_hint(_stream: &TcpStream) -> Option<String> {
+    None
+}
+
+// Addresses of `host`, in resolver order. Transient failures (the resolver timed out or asked to try
+// again) are retried with exponential back-off; a name that does not exist fails at once. The
+// error is the message recorded on every port.
+async fn resolve_host(host: &str) -> Result<Arc<[IpAddr]>, String> {
+    let mut backoff = RESOLVE_BACKOFF;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match tokio::net::lookup_host((host, 0)).await {
+            Ok(resolved) => {
+                let mut addrs: Vec<IpAddr> = Vec::new();
+                for addr in resolved {
+                    if !addrs.contains(&addr.ip()) {
+                        addrs.push(addr.ip());
+                    }
+                }
+                if addrs.is_empty() {
+                    return Err(format!("resolution failed: {} has no addresses", host));
+                }
+                return Ok(addrs.into());
+            }
+            Err(e) if attempts < RESOLVE_ATTEMPTS && is_transient_lookup_error(&e) => {
+                time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            Err(e) => {
+                let plural = if attempts == 1 { "" } else { "s" };
+                return Err(format!("resolution failed after {} attempt{}: {}", attempts, plural, e));
+            }
+        }
+    }
+}
+
+// getaddrinfo's EAI_AGAIN, as opposed to NXDOMAIN and other definite answers. std only keeps the
+// resolver's message, so this compares against the C library's text for that code.
+fn is_transient_lookup_error(e: &std::io::Error) -> bool {
+    if e.kind() == ErrorKind::TimedOut {
+        return true;
+    }
+    // SAFETY: gai_strerror returns a pointer to a static, NUL-terminated string
+    let again = unsafe { std::ffi::CStr::from_ptr(libc::gai_strerror(libc::EAI_AGAIN)) };
+    e.to_string().contains(&*again.to_string_lossy())
+}
+
+// EMFILE/ENFILE: the process or the system ran out of file descriptors
+fn is_fd_exhaustion(e: &std::io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(code) if code == libc::EMFILE || code == libc::ENFILE)
+}
+
+async fn lease_source_port(cfg: &ScanConfig) -> Option<SourcePortLease> {
+    match &cfg.source_ports {
+        Some(pool) => Some(pool.lease().await),
+        None => None,
+    }
+}
+
+fn source_pool_len(cfg: &ScanConfig) -> usize {
+    cfg.source_ports.as_ref().map_or(0, |pool| pool.len())
+}
+
+// Connect to `port` of the target, from local port `source` when given (`--source-ports`).
+pub(crate) async fn connect(cfg: &ScanConfig, port: u16, source: Option<u16>) -> std::io::Result<TcpStream> {
+    if let Some(proxy) = &cfg.proxy {
+        return proxy.connect(&cfg.host, port).await;
+    }
+    let addrs: Vec<SocketAddr> = match cfg.scope_id {
+        // Link-local targets: the (host, port) form cannot carry a scope id
+        Some(scope_id) => {
+            let ip: Ipv6Addr = cfg
+                .host
+                .parse()
+                .map_err(|_| std::io::Error::new(ErrorKind::InvalidInput, "scoped target is not an IPv6 address"))?;
+            vec![SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id))]
+        }
+        // --no-dns: a parsed address, so no resolver call can happen even by accident
+        None if cfg.no_dns => {
+            let ip: IpAddr = cfg
+                .host
+                .parse()
+                .map_err(|_| std::io::Error::new(ErrorKind::InvalidInput, "target is not an IP address (--no-dns)"))?;
+            vec![SocketAddr::new(ip, port)]
+        }
+        // Looked up when the scan started; tried in resolver order like the (host, port) form
+        None => match &cfg.host_addrs {
+            Some(addrs) => addrs.iter().map(|&ip| SocketAddr::new(ip, port)).collect(),
+            // Use (host, port) tuple to let ToSocketAddrs handle IPv6 brackets and DNS resolution
+            None => tokio::net::lookup_host((cfg.host.as_str(), port)).await?.collect(),
+        },
+    };
+    match source {
+        None => TcpStream::connect(&addrs[..]).await,
+        Some(source) => connect_from(&addrs, source).await,
+    }
+}
+
+// Each address in turn, like `TcpStream::connect`, from a socket bound to local port `source`.
+// SO_REUSEADDR lets the port be reused while earlier connections from it are in TIME_WAIT.
+async fn connect_from(addrs: &[SocketAddr], source: u16) -> std::io::Result<TcpStream> {
+    let mut last_err = None;
+    for &addr in addrs {
+        let (socket, local) = match addr {
+            SocketAddr::V4(_) => (TcpSocket::new_v4()?, SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), source)),
+            SocketAddr::V6(_) => (TcpSocket::new_v6()?, SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), source)),
+        };
+        socket.set_reuseaddr(true)?;
+        let res = match socket.bind(local) {
+            Ok(()) => socket.connect(addr).await,
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| std::io::Error::new(ErrorKind::InvalidInput, "could not resolve to any address")))
+}
+
+// The local port is taken (or its 4-tuple still in TIME_WAIT): another port from the pool may work
+fn is_source_port_busy(e: &std::io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::AddrInUse | ErrorKind::AddrNotAvailable)
+}
+
+// Identification of one connection, within what `--total-timeout` leaves of the budget that began
+// at `started`; true when that ran out mid-probe. The connection's capture is written either way.
+async fn identify(stream: &mut ProbeStream, port: u16, cfg: &ScanConfig, started: Instant) -> (Identification, bool) {
+    let res = match cfg.total_timeout {
+        Some(total) => {
+            let deadline = time::Instant::from_std(started + total);
+            match time::timeout_at(deadline, identify_and_banner(stream, port, cfg)).await {
+                Ok(ident) => (ident, false),
+                Err(_) => (partial_identification(stream.received_head(), port, cfg), true),
+            }
+        }
+        None => (identify_and_banner(stream, port, cfg).await, false),
+    };
+    if let Err(e) = stream.save_capture() {
+        eprintln!("warning: failed to write capture for {}:{}: {}", cfg.target, port, e);
+    }
+    res
+}
+
+fn budget_spent(cfg: &ScanConfig) -> bool {
+    cfg.byte_budget.as_ref().is_some_and(|b| b.exhausted())
+}
+
+// Open, but nothing was said and no probe got an answer
+fn unidentified(ident: &Identification) -> bool {
+    ident.protocol == Some(Protocol::Unknown) && ident.banner.is_none() && !ident.reset_after_connect
+}
+
+// A closed result stamped with this run's scan id, the current time and the reason for its state;
+// callers fill in the rest.
+fn blank_result(cfg: &ScanConfig, port: u16, reason: &str) -> ScanResult {
+    ScanResult {
+        scan_id: cfg.scan_id.clone(),
+        timestamp: rfc3339_utc(SystemTime::now()),
+        target: cfg.target.clone(),
+        label: cfg.label.clone(),
+        resolved_addr: cfg.resolved_addr.clone(),
+        port,
+        group: None,
+        open: false,
+        latency_ms: None,
+        protocol: None,
+        banner: None,
+        banner_is_binary: false,
+        service: None,
+        telnet_options: None,
+        http_title: None,
+        reset_after_connect: false,
+        starttls: None,
+        websocket: None,
+        redirect: None,
+        reprobes: None,
+        os_hint: None,
+        change: None,
+        reason: reason.to_string(),
+        error: None,
+    }
+}
+
+async fn scan_one(cfg: &ScanConfig, port: u16, host: &HostState) -> Result<ScanResult> {
+    // A host that keeps timing out is most likely down: report the rest filtered without waiting
+    let skipped = || ScanResult { error: Some(HOST_TIMEOUTS_ERROR.into()), ..blank_result(cfg, port, "no-response") };
+    if host.timeout_budget_spent(cfg) {
+        return Ok(skipped());
+    }
+
+    // Per-attempt pacing (base delay + jitter), before taking any shared resource
+    cfg.scan_delay.wait().await;
+
+    let mut fd_retries = 0u32;
+    let mut source_retries = 0usize;
+    let (connect_res, started, rtt, latency_ms, _source, _host_permit, _global_permit) = loop {
+        // Global rate limit: acquire a token before attempting a connection.
+        // Do this before acquiring the global connection permit so we don't hold
+        // scarce connection slots while waiting for the next rate window.
+        cfg.rate_limiter.acquire().await;
+
+        // Per-host budget first, so waiting on a busy host does not tie up global slots
+        let host_permit = host.connections.acquire().await.expect("host semaphore not closed");
+        // Ports queued behind the host cap see timeouts that happened while they waited
+        if host.timeout_budget_spent(cfg) {
+            return Ok(skipped());
+        }
+
+        // --source-ports: a local port of our own for as long as the connection lives
+        let source = lease_source_port(cfg).await;
+
+        // Acquire a global permit to enforce process-wide connection cap.
+        // Held for the duration of this scan operation.
+        let global_permit = cfg
+            .global_limit
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("global semaphore not closed");
+
+        let started = Instant::now();
+        // --total-timeout also bounds the connect, when it is the tighter limit
+        let connect_timeout = cfg.total_timeout.map_or(cfg.timeout, |total| total.min(cfg.timeout));
+        let connect_res = time::timeout(connect_timeout, connect(cfg, port, source.as_ref().map(|s| s.port()))).await;
+        let rtt = started.elapsed();
+        let latency_ms = Some(rtt.as_millis() as u64);
+
+        // Out of file descriptors says nothing about the port: release the slot, back off and retry
+        match &connect_res {
+            Ok(Err(e)) if is_fd_exhaustion(e) && fd_retries < MAX_FD_RETRIES => {
+                drop(global_permit);
+                drop(host_permit);
+                FD_WARNING.call_once(|| {
+                    eprintln!(
+                        "warning: out of file descriptors ({}); backing off and retrying. \
+                         Lower --concurrency/--max-connections or raise the open-file limit (ulimit -n)",
+                        e
+                    );
+                });
+                fd_retries += 1;
+                time::sleep(Duration::from_millis(50 << fd_retries.min(5))).await;
+            }
+            // That source port cannot be used right now; the next one in the rotation may be free
+            Ok(Err(e)) if is_source_port_busy(e) && source.is_some() && source_retries < source_pool_len(cfg) => {
+                source_retries += 1;
+            }
+            _ => break (connect_res, started, rtt, latency_ms, source, host_permit, global_permit),
+        }
+    };
+
+    // --verify-open: accept-only front ends (no backend behind them) close the idle connection unprompted
+    if let (Ok(Ok(stream)), Some(grace)) = (&connect_res, cfg.verify_open) {
+        if is_hollow(stream, grace).await {
+            return Ok(ScanResult { open: true, latency_ms, ..blank_result(cfg, port, HOLLOW_REASON) });
+        }
+    }
 
-    // Use (host, port) tuple to let ToSocketAddrs handle IPv6 brackets and DNS resolution
-    let connect_res = time::timeout(cfg.timeout, TcpStream::connect((target.as_str(), port))).await;
     match connect_res {
-        Err(_) => Ok(ScanResult {
-            target,
-            port,
-            open: false,
-            protocol: None,
-            banner: None,
-            error: Some("timeout".into()),
+        Err(_) => {
+            host.timeouts.fetch_add(1, AtomicOrdering::Relaxed);
+            Ok(ScanResult { error: Some("timeout".into()), ..blank_result(cfg, port, "no-response") })
+        }
+        // A reset (rather than a refusal) means the handshake completed and the peer then aborted
+        Ok(Err(e)) if e.kind() == ErrorKind::ConnectionReset => Ok(ScanResult {
+            open: true,
+            latency_ms,
+            protocol: Some(Protocol::Unknown),
+            reset_after_connect: true,
+            ..blank_result(cfg, port, "reset")
         }),
         Ok(Err(e)) => Ok(ScanResult {
-            target,
-            port,
-            open: false,
-            protocol: None,
-            banner: None,
             error: Some(e.to_string()),
+            ..blank_result(cfg, port, refusal_reason(&e))
+        }),
+        Ok(Ok(stream)) if cfg.skips_banner(port) || budget_spent(cfg) => Ok(ScanResult {
+            open: true,
+            latency_ms,
+            os_hint: if cfg.os_hint && cfg.proxy.is_none() { os_hint(&stream) } else { None },
+            error: (!cfg.skips_banner(port)).then(|| BANNER_BUDGET_ERROR.to_string()),
+            ..blank_result(cfg, port, "syn-ack")
         }),
-        Ok(Ok(mut stream)) => {
-            let (protocol, banner) = identify_and_banner(
-                &mut stream,
-                port,
-                cfg.banner_read_len,
-                cfg.timeout,
-                cfg.passive,
-            )
-            .await;
+        Ok(Ok(stream)) => {
+            // Read before probing: the options were fixed by the handshake (the proxy's, when tunneling)
+            let os_hint = if cfg.os_hint && cfg.proxy.is_none() { os_hint(&stream) } else { None };
+            let mut stream = ProbeStream::new(stream, cfg.capture_dir.as_deref(), &cfg.target, port, cfg.banner_read_len);
+            // Slow links get probe waits in proportion to their round trip; fast ones keep --timeout-ms
+            let adaptive_cfg = cfg.rtt_multiplier.map(|multiplier| {
+                let adaptive = rtt.mul_f64(multiplier).min(MAX_PROBE_TIMEOUT);
+                ScanConfig { timeout: cfg.timeout.max(adaptive), ..cfg.clone() }
+            });
+            let probe_cfg = adaptive_cfg.as_ref().unwrap_or(cfg);
+            let (mut ident, mut out_of_time) = identify(&mut stream, port, probe_cfg, started).await;
+            // --reprobe-unknown: silent services may only be slow to speak; try fresh connections that
+            // read until idle and wait one more timeout each time
+            let mut reprobes = 0;
+            // Source port of the current reprobe connection, returned once that is done
+            let mut _reprobe_source = None;
+            while reprobes < cfg.reprobe_unknown && !out_of_time && unidentified(&ident) && !budget_spent(cfg) {
+                if cfg.total_timeout.is_some_and(|total| started.elapsed() >= total) {
+                    break;
+                }
+                reprobes += 1;
+                let reprobe_cfg = ScanConfig {
+                    timeout: (probe_cfg.timeout * (reprobes + 1)).min(MAX_PROBE_TIMEOUT),
+                    read_strategy: match probe_cfg.read_strategy {
+                        ReadStrategy::Single => ReadStrategy::UntilIdle(REPROBE_IDLE),
+                        idle => idle,
+                    },
+                    ..probe_cfg.clone()
+                };
+                // A new connection like any other: paced by --scan-delay and the rate limit
+                cfg.scan_delay.wait().await;
+                cfg.rate_limiter.acquire().await;
+                let source = lease_source_port(cfg).await;
+                let res = time::timeout(reprobe_cfg.timeout, connect(cfg, port, source.as_ref().map(|s| s.port()))).await;
+                let Ok(Ok(tcp)) = res else {
+                    break;
+                };
+                _reprobe_source = source;
+                stream = ProbeStream::new(tcp, cfg.capture_dir.as_deref(), &cfg.target, port, cfg.banner_read_len)
+                    .reprobe(reprobes);
+                (ident, out_of_time) = identify(&mut stream, port, &reprobe_cfg, started).await;
+            }
+            let reason = if ident.reset_after_connect { "reset" } else { "syn-ack" };
+            let error = if out_of_time {
+                Some("total timeout reached while probing".to_string())
+            } else if unidentified(&ident) && budget_spent(cfg) {
+                // Cut off (or never read) for want of budget rather than silent
+                Some(BANNER_BUDGET_ERROR.to_string())
+            } else {
+                None
+            };
+            let service = ident.protocol.as_ref().map(|p| ServiceInfo {
+                raw: ident.banner.clone(),
+                ..ident.service.unwrap_or_else(|| ServiceInfo::named(p))
+            });
             Ok(ScanResult {
-                target,
-                port,
                 open: true,
-                protocol,
-                banner,
-                error: None,
+                latency_ms,
+                protocol: ident.protocol,
+                banner: ident.banner,
+                banner_is_binary: ident.banner_is_binary,
+                service,
+                telnet_options: ident.telnet_options,
+                http_title: ident.http_title,
+                reset_after_connect: ident.reset_after_connect,
+                starttls: ident.starttls,
+                websocket: ident.websocket,
+                redirect: ident.redirect,
+                os_hint,
+                reprobes: (reprobes > 0).then_some(reprobes),
+                error,
+                ..blank_result(cfg, port, reason)
             })
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAP: usize = 8;
+
+    #[tokio::test]
+    async fn dropping_the_stream_returns_connection_permits() {
+        // A listener whose accept queue is full drops further SYNs, so connects to it stay pending
+        // just like connects to an unroutable address, without depending on the network
+        let socket = TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener = socket.listen(0).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _queued = TcpStream::connect(addr).await.unwrap();
+
+        let global = Arc::new(Semaphore::new(CAP));
+        let cfg = ScanConfig::builder()
+            .target(addr.ip().to_string())
+            .ports(vec![addr.port()])
+            .timeout(Duration::from_secs(30))
+            .global_limit(global.clone())
+            .build()
+            .unwrap();
+        let mut scan = Box::pin(scan_ports_stream(cfg));
+        tokio::select! {
+            r = scan.next() => panic!("a pending connect finished: {:?}", r),
+            _ = time::sleep(Duration::from_millis(300)) => {}
+        }
+        assert_eq!(global.available_permits(), CAP - 1, "the pending connect should hold a permit");
+
+        drop(scan);
+        // The abort lands when the runtime next polls the scan task
+        time::timeout(Duration::from_secs(5), async {
+            while global.available_permits() != CAP {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("permits not returned after the scan was dropped");
+    }
+}
@@ -0,0 +1,282 @@
+//! Data-driven service probes loaded from an nmap-service-probes-like ("lite") text file.
+//!
+//! Format, one directive per line (`#` starts a comment):
+//!
+//! ```text
+//! Probe <name> q|<payload>|     start a probe; an empty payload (q||) matches passive banners
+//! rarity <1-9>                  lower values are tried first (default 5)
+//! ports <spec>                  ports the probe applies to, e.g. 80,8000-8100 (default: all)
+//! match <service> m|<regex>|[is] response pattern; `i` = case-insensitive, `s` = dot matches newline
+//! ```
+//!
+//! Any character may delimit `q` and `m` values. Payloads understand `\r \n \t \0 \\ \xHH`
+//! and an escaped delimiter.
+
+use anyhow::{anyhow, bail, Context, Result};
+use regex::bytes::{Regex, RegexBuilder};
+use std::path::Path;
+
+/// Probe definitions shipped with the binary, used when no `--probe-file` is given.
+const BUILTIN_PROBES: &str = include_str!("default_probes.txt");
+
+#[derive(Debug)]
+pub struct ServiceProbe {
+    pub name: String,
+    pub payload: Vec<u8>,
+    pub rarity: u8,
+    /// Ports this probe applies to; empty means every port
+    pub ports: Vec<u16>,
+    matches: Vec<ServiceMatch>,
+}
+
+#[derive(Debug)]
+struct ServiceMatch {
+    service: String,
+    pattern: Regex,
+}
+
+impl ServiceProbe {
+    /// Name of the first service whose pattern matches the response.
+    pub fn match_response(&self, buf: &[u8]) -> Option<&str> {
+        self.matches
+            .iter()
+            .find(|m| m.pattern.is_match(buf))
+            .map(|m| m.service.as_str())
+    }
+
+    fn applies_to(&self, port: u16) -> bool {
+        self.ports.is_empty() || self.ports.contains(&port)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ProbeSet {
+    probes: Vec<ServiceProbe>,
+}
+
+impl ProbeSet {
+    pub fn builtin() -> Self {
+        Self::parse(BUILTIN_PROBES).expect("built-in probe definitions must parse")
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let src = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read probe file {}", path.display()))?;
+        Self::parse(&src).with_context(|| format!("invalid probe file {}", path.display()))
+    }
+
+    pub fn parse(src: &str) -> Result<Self> {
+        let mut probes: Vec<ServiceProbe> = Vec::new();
+        for (idx, raw) in src.lines().enumerate() {
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let lineno = idx + 1;
+            let (directive, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim();
+            if directive == "Probe" {
+                let (name, value) = rest
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| anyhow!("line {lineno}: expected `Probe <name> q|payload|`"))?;
+                let value = value.trim();
+                let body = value
+                    .strip_prefix('q')
+                    .ok_or_else(|| anyhow!("line {lineno}: probe payload must start with q"))?;
+                let (payload, _) = split_delimited(body)
+                    .ok_or_else(|| anyhow!("line {lineno}: unterminated probe payload"))?;
+                probes.push(ServiceProbe {
+                    name: name.to_string(),
+                    payload: unescape(payload).map_err(|e| anyhow!("line {lineno}: {e}"))?,
+                    rarity: 5,
+                    ports: Vec::new(),
+                    matches: Vec::new(),
+                });
+                continue;
+            }
+            let probe = probes
+                .last_mut()
+                .ok_or_else(|| anyhow!("line {lineno}: `{directive}` before any Probe"))?;
+            match directive {
+                "rarity" => {
+                    probe.rarity = rest
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|r| (1..=9).contains(r))
+                        .ok_or_else(|| anyhow!("line {lineno}: rarity must be 1-9"))?;
+                }
+                "ports" => {
+                    probe.ports = crate::parse_ports(rest)
+                        .map_err(|e| anyhow!("line {lineno}: invalid ports: {e}"))?;
+                }
+                "match" => {
+                    let (service, value) = rest
+                        .split_once(char::is_whitespace)
+                        .ok_or_else(|| anyhow!("line {lineno}: expected `match <service> m|regex|`"))?;
+                    let body = value
+                        .trim()
+                        .strip_prefix('m')
+                        .ok_or_else(|| anyhow!("line {lineno}: match pattern must start with m"))?;
+                    let (pattern, flags) = split_delimited(body)
+                        .ok_or_else(|| anyhow!("line {lineno}: unterminated match pattern"))?;
+                    let pattern = RegexBuilder::new(pattern)
+                        .case_insensitive(flags.contains('i'))
+                        .dot_matches_new_line(flags.contains('s'))
+                        .unicode(false)
+                        .build()
+                        .map_err(|e| anyhow!("line {lineno}: bad regex: {e}"))?;
+                    probe.matches.push(ServiceMatch { service: service.to_ascii_lowercase(), pattern });
+                }
+                other => bail!("line {lineno}: unknown directive `{other}`"),
+            }
+        }
+        Ok(Self { probes })
+    }
+
+    /// Service matching a passively received banner (probes with an empty payload).
+    pub fn match_banner(&self, buf: &[u8], port: u16) -> Option<&str> {
+        self.for_port(port)
+            .into_iter()
+            .filter(|p| p.payload.is_empty())
+            .find_map(|p| p.match_response(buf))
+    }
+
+    /// Service matching a response to any probe applicable to `port`, in rarity order.
+    pub fn match_any(&self, buf: &[u8], port: u16) -> Option<&str> {
+        self.for_port(port).into_iter().find_map(|p| p.match_response(buf))
+    }
+
+    /// Every service name a probe can report.
+    pub fn services(&self) -> impl Iterator<Item = &str> {
+        self.probes.iter().flat_map(|p| p.matches.iter().map(|m| m.service.as_str()))
+    }
+
+    /// Probes that send data and apply to `port`, in rarity order.
+    pub fn active_for_port(&self, port: u16) -> Vec<&ServiceProbe> {
+        self.for_port(port).into_iter().filter(|p| !p.payload.is_empty()).collect()
+    }
+
+    fn for_port(&self, port: u16) -> Vec<&ServiceProbe> {
+        let mut v: Vec<&ServiceProbe> = self.probes.iter().filter(|p| p.applies_to(port)).collect();
+        v.sort_by_key(|p| p.rarity);
+        v
+    }
+}
+
+// Split `|body|rest` on its leading delimiter, honouring backslash escapes inside the body.
+fn split_delimited(s: &str) -> Option<(&str, &str)> {
+    let delim = s.chars().next()?;
+    let body = &s[delim.len_utf8()..];
+    let mut escaped = false;
+    for (i, c) in body.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == delim {
+            return Some((&body[..i], &body[i + c.len_utf8()..]));
+        }
+    }
+    None
+}
+
+fn unescape(s: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(s.len());
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let esc = *bytes.get(i + 1).ok_or("dangling backslash in payload")?;
+        match esc {
+            b'r' => out.push(b'\r'),
+            b'n' => out.push(b'\n'),
+            b't' => out.push(b'\t'),
+            b'0' => out.push(0),
+            b'x' => {
+                let hex = s.get(i + 2..i + 4).ok_or("truncated \\x escape in payload")?;
+                out.push(u8::from_str_radix(hex, 16).map_err(|_| format!("bad \\x escape `{hex}`"))?);
+                i += 2;
+            }
+            // Escaped backslash or delimiter
+            other => out.push(other),
+        }
+        i += 2;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_escapes() {
+        let set = ProbeSet::parse("Probe esc q|a\\r\\n\\t\\0\\\\\\|\\x7fZ|\nProbe alt q#1\\#2#").unwrap();
+        assert_eq!(set.probes[0].payload, b"a\r\n\t\0\\|\x7fZ");
+        // Any delimiter, escaped the same way
+        assert_eq!(set.probes[1].payload, b"1#2");
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let cases = [
+            ("# comment\n\nrarity 3", "line 3: `rarity` before any Probe"),
+            ("Probe a q|x|\nrarity 0", "line 2: rarity must be 1-9"),
+            ("Probe a q|x|\nports 99999", "line 2: invalid ports"),
+            ("Probe a q|x\\|", "line 1: unterminated probe payload"),
+            ("Probe a q|\\xZZ|", "line 1: bad \\x escape `ZZ`"),
+            ("Probe a q|x|\nmatch svc m|(|", "line 2: bad regex"),
+            ("Probe a q|x|\nsoftmatch svc m|x|", "line 2: unknown directive `softmatch`"),
+        ];
+        for (src, expected) in cases {
+            let err = ProbeSet::parse(src).unwrap_err().to_string();
+            assert!(err.starts_with(expected), "{src:?}: {err}");
+        }
+    }
+
+    #[test]
+    fn active_probes_in_rarity_order() {
+        let src = "\
+Probe late q|3|
+rarity 9
+Probe early q|1|
+rarity 1
+Probe passive q||
+rarity 1
+Probe default q|2|
+Probe elsewhere q|4|
+rarity 1
+ports 8000-8100
+";
+        let set = ProbeSet::parse(src).unwrap();
+        let names = |port| set.active_for_port(port).iter().map(|p| p.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names(80), ["early", "default", "late"]);
+        assert_eq!(names(8080), ["early", "elsewhere", "default", "late"]);
+    }
+
+    #[test]
+    fn banner_match_flags() {
+        let src = "\
+Probe NULL q||
+match ftp m|^220 .*ftp|i
+match smtp m|^220 .*smtp|
+match pop3 m|^\\+OK.ready|s
+match imap m|^\\* OK.IMAP|
+Probe GetRequest q|GET / HTTP/1.0\\r\\n\\r\\n|
+match http m|^HTTP/1\\.[01]|
+";
+        let set = ProbeSet::parse(src).unwrap();
+        assert_eq!(set.match_banner(b"220 ProFTPD Server\r\n", 21), Some("ftp"));
+        assert_eq!(set.match_banner(b"+OK\nready", 110), Some("pop3"));
+        // Without `i`, case matters; without `s`, `.` stops at a newline
+        assert_eq!(set.match_banner(b"220 mx.test ESMTP\r\n", 25), None);
+        assert_eq!(set.match_banner(b"* OK\nIMAP4rev1\r\n", 143), None);
+        // Only passive (empty-payload) probes match a banner
+        assert_eq!(set.match_banner(b"HTTP/1.1 200 OK\r\n", 80), None);
+        assert_eq!(set.match_any(b"HTTP/1.1 200 OK\r\n", 80), Some("http"));
+    }
+}
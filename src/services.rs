@@ -0,0 +1,92 @@
+//! Service-name lookups for port specs (`--ports http,ssh,8000-8100`): TCP entries of
+//! `/etc/services`, or a small embedded table where that file is missing (e.g. Windows, containers).
+
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+const SERVICES_FILE: &str = "/etc/services";
+
+// Used only when SERVICES_FILE cannot be read
+const FALLBACK_SERVICES: &[(&str, u16)] = &[
+    ("ftp-data", 20), ("ftp", 21), ("ssh", 22), ("telnet", 23), ("smtp", 25), ("domain", 53),
+    ("http", 80), ("kerberos", 88), ("pop3", 110), ("sunrpc", 111), ("ntp", 123), ("epmap", 135),
+    ("netbios-ssn", 139), ("imap", 143), ("snmp", 161), ("ldap", 389), ("https", 443),
+    ("microsoft-ds", 445), ("submissions", 465), ("submission", 587), ("ldaps", 636), ("rsync", 873),
+    ("imaps", 993), ("pop3s", 995), ("ms-sql-s", 1433), ("openvpn", 1194), ("mysql", 3306),
+    ("ms-wbt-server", 3389), ("postgresql", 5432), ("vnc", 5900), ("redis", 6379),
+    ("http-alt", 8080), ("mongodb", 27017),
+];
+
+static SERVICES: OnceLock<HashMap<String, u16>> = OnceLock::new();
+
+/// TCP port of a service name (or alias), case-insensitively.
+pub fn port_by_name(name: &str) -> Result<u16> {
+    let table = SERVICES.get_or_init(load);
+    let key = name.trim().to_ascii_lowercase();
+    if let Some(&port) = table.get(&key) {
+        return Ok(port);
+    }
+    match suggest(table, &key) {
+        Some(close) => Err(anyhow!("unknown service name '{}' (did you mean '{}'?)", name, close)),
+        None => Err(anyhow!("unknown service name '{}'", name)),
+    }
+}
+
+fn load() -> HashMap<String, u16> {
+    match std::fs::read_to_string(SERVICES_FILE) {
+        Ok(src) => parse(&src),
+        Err(_) => FALLBACK_SERVICES.iter().map(|&(n, p)| (n.to_string(), p)).collect(),
+    }
+}
+
+// `<name> <port>/<proto> [aliases...] [# comment]`; TCP entries only, first definition wins.
+fn parse(src: &str) -> HashMap<String, u16> {
+    let mut table = HashMap::new();
+    for line in src.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        let (Some(name), Some(port_proto)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let Some(port) = port_proto.strip_suffix("/tcp").and_then(|p| p.parse::<u16>().ok()) else {
+            continue;
+        };
+        for n in std::iter::once(name).chain(fields) {
+            table.entry(n.to_ascii_lowercase()).or_insert(port);
+        }
+    }
+    table
+}
+
+// Closest known name within a small edit distance, for "did you mean" hints.
+fn suggest<'a>(table: &'a HashMap<String, u16>, name: &str) -> Option<&'a str> {
+    table
+        .keys()
+        .map(|k| (edit_distance(k, name), k))
+        .filter(|(d, _)| *d <= 2)
+        .min()
+        .map(|(_, k)| k.as_str())
+}
+
+// Optimal string alignment distance: insertions, deletions, substitutions and adjacent swaps.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut d = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
@@ -0,0 +1,192 @@
+//! `--sink`: results streamed live to a collector as NDJSON over one TCP or Unix socket
+//! connection. A broken connection is re-established a few times; after that, lines are held in
+//! memory and retried every `RETRY_INTERVAL`, and anything still undelivered when the scan ends is
+//! written to a local file. A line cut off by a broken write is sent again whole on the next
+//! connection, which must start on a record boundary.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::time::{timeout, Instant};
+
+// Connection attempts per failure, and the pause before the first retry (doubled for each one after)
+const CONNECT_ATTEMPTS: u32 = 3;
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+// Bounds on one connect and one batch write, so a blackholed collector cannot stall the scan
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+// While the sink is unreachable, results are only buffered between reconnect attempts this far apart
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug)]
+enum SinkAddr {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+pub struct ResultSink {
+    addr: SinkAddr,
+    conn: Option<Box<dyn AsyncWrite + Unpin + Send>>,
+    // Lines not yet delivered, oldest first, each with its newline
+    pending: VecDeque<String>,
+    // Bytes of the oldest pending line already written on this connection (a partial write)
+    offset: usize,
+    // Whether the last delivery failed, so the warning is given once per outage
+    buffering: bool,
+    // While buffering, no reconnect is attempted before this
+    retry_at: Option<Instant>,
+}
+
+impl ResultSink {
+    /// Parse `tcp://host:port` or `unix:/path` and connect.
+    pub async fn connect(url: &str) -> Result<Self> {
+        let addr = if let Some(hostport) = url.strip_prefix("tcp://") {
+            if !hostport.rsplit_once(':').is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok()) {
+                bail!("invalid sink '{}': expected tcp://host:port", url);
+            }
+            SinkAddr::Tcp(hostport.to_string())
+        } else if let Some(path) = url.strip_prefix("unix://").or_else(|| url.strip_prefix("unix:")) {
+            if path.is_empty() {
+                bail!("invalid sink '{}': expected unix:/path", url);
+            }
+            SinkAddr::Unix(PathBuf::from(path))
+        } else {
+            bail!("invalid sink '{}': expected tcp://host:port or unix:/path", url);
+        };
+        let mut sink = Self { addr, conn: None, pending: VecDeque::new(), offset: 0, buffering: false, retry_at: None };
+        sink.reconnect().await.with_context(|| format!("failed to connect to sink {}", url))?;
+        Ok(sink)
+    }
+
+    /// Queue one NDJSON line; `flush` sends it.
+    pub fn push(&mut self, mut line: String) {
+        line.push('\n');
+        self.pending.push_back(line);
+    }
+
+    /// Send everything pending, reconnecting if the connection broke. Lines stay pending when the
+    /// sink cannot be reached.
+    pub async fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        if self.send_pending().await.is_ok() {
+            return;
+        }
+        self.conn = None;
+        if self.retry_at.is_some_and(|at| Instant::now() < at) {
+            return;
+        }
+        let res = match self.reconnect().await {
+            Ok(()) => self.send_pending().await.map_err(anyhow::Error::from),
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(()) if self.buffering => {
+                self.buffering = false;
+                self.retry_at = None;
+                eprintln!("note: result sink reachable again; buffered results delivered");
+            }
+            Ok(()) => {}
+            Err(e) => {
+                self.conn = None;
+                self.retry_at = Some(Instant::now() + RETRY_INTERVAL);
+                if !self.buffering {
+                    self.buffering = true;
+                    eprintln!("warning: result sink unavailable ({:#}); buffering results locally", e);
+                }
+            }
+        }
+    }
+
+    /// Last delivery attempt; what is still pending goes to `fallback` (None when all was sent).
+    pub async fn finish(mut self, fallback: &Path) -> Result<Option<usize>> {
+        // One more attempt, whenever the last one was
+        self.retry_at = None;
+        self.flush().await;
+        if let Some(conn) = self.conn.as_mut() {
+            let _ = conn.shutdown().await;
+        }
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+        // Whole lines, including any the collector only got the start of
+        let body: String = self.pending.iter().map(String::as_str).collect();
+        std::fs::write(fallback, body).with_context(|| format!("failed to write {}", fallback.display()))?;
+        Ok(Some(self.pending.len()))
+    }
+
+    // Write the pending lines in one batch. Only what the connection accepted is taken off the
+    // queue; a line it took part of stays pending and is resent whole after a reconnect.
+    async fn send_pending(&mut self) -> std::io::Result<()> {
+        let conn = self.conn.as_mut().ok_or(std::io::ErrorKind::NotConnected)?;
+        let batch: String = self.pending.iter().map(String::as_str).collect();
+        let batch = &batch.as_bytes()[self.offset..];
+        let mut written = 0;
+        let res = timeout(SEND_TIMEOUT, async {
+            while written < batch.len() {
+                match conn.write(&batch[written..]).await? {
+                    0 => return Err(std::io::ErrorKind::WriteZero.into()),
+                    n => written += n,
+                }
+            }
+            conn.flush().await
+        })
+        .await
+        .unwrap_or_else(|_| Err(std::io::ErrorKind::TimedOut.into()));
+        self.delivered(written);
+        res
+    }
+
+    // Drop `n` delivered bytes from the front of the queue
+    fn delivered(&mut self, mut n: usize) {
+        while let Some(line) = self.pending.front() {
+            let rest = line.len() - self.offset;
+            if n < rest {
+                self.offset += n;
+                return;
+            }
+            n -= rest;
+            self.offset = 0;
+            self.pending.pop_front();
+        }
+    }
+
+    async fn reconnect(&mut self) -> Result<()> {
+        let mut backoff = RETRY_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            let opened = timeout(CONNECT_TIMEOUT, self.open())
+                .await
+                .unwrap_or_else(|_| Err(std::io::ErrorKind::TimedOut.into()));
+            match opened {
+                Ok(conn) => {
+                    // A fresh connection must not open with the tail of a torn line
+                    self.conn = Some(conn);
+                    self.offset = 0;
+                    return Ok(());
+                }
+                Err(e) if attempt >= CONNECT_ATTEMPTS => {
+                    return Err(anyhow!("{} (after {} attempts)", e, attempt));
+                }
+                Err(_) => {
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    async fn open(&self) -> std::io::Result<Box<dyn AsyncWrite + Unpin + Send>> {
+        match &self.addr {
+            SinkAddr::Tcp(hostport) => Ok(Box::new(tokio::net::TcpStream::connect(hostport.as_str()).await?)),
+            #[cfg(unix)]
+            SinkAddr::Unix(path) => Ok(Box::new(tokio::net::UnixStream::connect(path).await?)),
+            #[cfg(not(unix))]
+            SinkAddr::Unix(_) => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "unix sockets are not available")),
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! Run totals reported by `--summary` and `--summary-json`.
+
+use ospine::ScanResult;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Totals accumulated while results stream in, reported by `--summary` and `--summary-json`.
+/// Field names are a stable interface for scripts; add fields rather than renaming them.
+#[derive(Debug, Default, Serialize)]
+pub struct ScanSummary {
+    pub scan_id: String,
+    pub targets: usize,
+    pub ports_scanned: usize,
+    pub open: usize,
+    /// Ports that refused the connection
+    pub closed: usize,
+    /// Ports that did not answer, or were unreachable
+    pub filtered: usize,
+    /// Wall-clock time from start to the last result
+    pub duration_ms: u64,
+    /// Ports scanned per second over `duration_ms`
+    pub ports_per_sec: f64,
+    /// Open ports per detected protocol; ports without one count as "unknown"
+    pub protocol_counts: BTreeMap<String, usize>,
+    /// Hosts with the highest median connect latency, slowest first (only with `--report-slowest`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub slowest_hosts: Vec<HostLatency>,
+}
+
+/// Connect latency of one target, over the ports whose handshake completed.
+#[derive(Debug, Clone, Serialize)]
+pub struct HostLatency {
+    pub target: String,
+    /// Ports the statistics are based on
+    pub samples: usize,
+    pub avg_ms: f64,
+    pub median_ms: u64,
+}
+
+impl ScanSummary {
+    pub fn new(scan_id: &str) -> Self {
+        Self { scan_id: scan_id.to_string(), ..Self::default() }
+    }
+
+    /// Record the elapsed time once the last result is in.
+    pub fn finish(&mut self, elapsed: Duration) {
+        self.duration_ms = elapsed.as_millis() as u64;
+        let secs = elapsed.as_secs_f64();
+        self.ports_per_sec = if secs > 0.0 { (self.ports_scanned as f64 / secs * 10.0).round() / 10.0 } else { 0.0 };
+    }
+
+    pub fn record(&mut self, r: &ScanResult) {
+        self.ports_scanned += 1;
+        if !r.open {
+            if r.reason == "conn-refused" {
+                self.closed += 1;
+            } else {
+                self.filtered += 1;
+            }
+            return;
+        }
+        self.open += 1;
+        let proto = r.protocol.as_ref().map_or_else(|| "unknown".to_string(), |p| p.to_string());
+        *self.protocol_counts.entry(proto).or_insert(0) += 1;
+    }
+
+    /// Fold one target's results into the `keep` slowest hosts (by median, then average, latency).
+    /// Targets without a completed handshake have no latency and are skipped.
+    pub fn record_host_latency(&mut self, results: &[ScanResult], keep: usize) {
+        let Some(first) = results.first() else {
+            return;
+        };
+        let mut samples: Vec<u64> = results.iter().filter_map(|r| r.latency_ms).collect();
+        if samples.is_empty() || keep == 0 {
+            return;
+        }
+        samples.sort_unstable();
+        // Both indices coincide for an odd count
+        let median_ms = (samples[(samples.len() - 1) / 2] + samples[samples.len() / 2]) / 2;
+        let avg = samples.iter().sum::<u64>() as f64 / samples.len() as f64;
+        self.slowest_hosts.push(HostLatency {
+            target: first.target.clone(),
+            samples: samples.len(),
+            avg_ms: (avg * 10.0).round() / 10.0,
+            median_ms,
+        });
+        self.slowest_hosts.sort_by(|a, b| {
+            b.median_ms
+                .cmp(&a.median_ms)
+                .then(b.avg_ms.total_cmp(&a.avg_ms))
+                .then(a.target.cmp(&b.target))
+        });
+        self.slowest_hosts.truncate(keep);
+    }
+
+    /// "http: 42, ssh: 17, unknown: 3", most common first.
+    pub fn protocol_census(&self) -> String {
+        let mut counts: Vec<(&String, &usize)> = self.protocol_counts.iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        counts
+            .iter()
+            .map(|(p, n)| format!("{}: {}", p, n))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
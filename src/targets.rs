@@ -0,0 +1,217 @@
+//! Scan targets as the binary expands them from the command line and endpoint files.
+
+use anyhow::Result;
+use ipnet::IpNet;
+use ospine::parse_ports;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A scan target: the form the user entered and the form used to connect.
+#[derive(Clone, Debug)]
+pub struct Target {
+    /// As entered by the user; reported in `ScanResult.target`
+    pub name: String,
+    /// Connection form (e.g. ASCII punycode for internationalized hostnames)
+    pub host: String,
+    /// IPv6 scope id from a `%zone` suffix (link-local targets)
+    pub scope_id: Option<u32>,
+    /// The one address of a hostname this entry covers (`--all-addresses`); `host` is then that address
+    pub resolved_addr: Option<String>,
+}
+
+/// RNG seeded from `--seed` when given (reproducible runs), otherwise from OS entropy.
+pub fn seeded_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(s) => StdRng::seed_from_u64(s),
+        None => StdRng::from_entropy(),
+    }
+}
+
+// Bounds on how far a CIDR target may expand.
+pub struct CidrLimits {
+    pub max_hosts: usize,
+    pub ipv6_expand_prefix: u8,
+    pub allow_large_ipv6: bool,
+}
+
+pub fn parse_targets(input: &str, limits: &CidrLimits) -> Result<Vec<Target>> {
+    // Bracketed IPv6 ("[2001:db8::1]", as in URLs) is the same target as the bare literal
+    if let Some(rest) = input.strip_prefix('[') {
+        let inner = rest.strip_suffix(']').ok_or_else(|| {
+            if rest.contains("]:") {
+                anyhow::anyhow!("invalid target '{}': give ports with --ports, not in the target", input)
+            } else {
+                anyhow::anyhow!("invalid target '{}': expected [IPv6 address]", input)
+            }
+        })?;
+        if !inner.contains(':') {
+            anyhow::bail!("invalid target '{}': brackets are only for IPv6 addresses", input);
+        }
+        return parse_targets(inner, limits);
+    }
+    // IPv6 zone ("fe80::1%eth0", also after a CIDR): every address is scoped to that interface
+    if let Some((addr, zone)) = input.split_once('%') {
+        let mut targets = parse_targets(addr, limits)?;
+        let scope_id = resolve_zone(zone)?;
+        for t in &mut targets {
+            if !IpAddr::from_str(&t.host).is_ok_and(|ip| ip.is_ipv6()) {
+                anyhow::bail!("zone '%{}' is only valid on IPv6 addresses, not '{}'", zone, t.name);
+            }
+            t.name = format!("{}%{}", t.name, zone);
+            t.scope_id = Some(scope_id);
+        }
+        return Ok(targets);
+    }
+    // Try CIDR first
+    if let Ok(net) = IpNet::from_str(input) {
+        // Large IPv6 prefixes would expand to astronomically many hosts; require an explicit opt-in
+        let large_v6 = matches!(net, IpNet::V6(_)) && net.prefix_len() < limits.ipv6_expand_prefix;
+        if large_v6 && !limits.allow_large_ipv6 {
+            anyhow::bail!(
+                "IPv6 CIDR {} is wider than /{}; narrow the prefix or pass --allow-large-ipv6 to scan the first --max-hosts ({}) addresses",
+                net, limits.ipv6_expand_prefix, limits.max_hosts
+            );
+        }
+        // Never materialise more than the cap (+1 to detect overflow)
+        let mut hosts: Vec<Target> = net
+            .hosts()
+            .take(limits.max_hosts.saturating_add(1))
+            .map(|ip| ip_target(ip.to_string()))
+            .collect();
+        if hosts.len() > limits.max_hosts {
+            if !large_v6 {
+                anyhow::bail!("CIDR {} expands to more than {} hosts (raise --max-hosts to allow it)", net, limits.max_hosts);
+            }
+            hosts.truncate(limits.max_hosts);
+            eprintln!("note: scanning only the first {} addresses of {}", limits.max_hosts, net);
+        }
+        return Ok(hosts);
+    }
+    // Canonical form, so "2001:0db8:0::1" and "2001:db8::1" report the same target
+    if let Ok(ip) = IpAddr::from_str(input) {
+        return Ok(vec![ip_target(ip.to_string())]);
+    }
+    // No hostname contains ':', so this was meant as an IPv6 address (or CIDR) and is malformed
+    if input.contains(':') {
+        anyhow::bail!("invalid IPv6 address '{}'", input);
+    }
+    // Otherwise, treat as a hostname; connect via its ASCII (punycode) form so
+    // internationalized names resolve consistently, but report it as entered.
+    let host = idna::domain_to_ascii(input)
+        .map_err(|_| anyhow::anyhow!("invalid hostname '{}': not a valid (internationalized) domain name", input))?;
+    Ok(vec![Target { name: input.to_string(), host, scope_id: None, resolved_addr: None }])
+}
+
+fn ip_target(ip: String) -> Target {
+    Target { name: ip.clone(), host: ip, scope_id: None, resolved_addr: None }
+}
+
+// --endpoints-file: `host port` or `host:port` per line (`[v6]:port` for IPv6; the port may also be a
+// service name or range).
+pub fn load_endpoints(path: &Path) -> Result<Vec<(String, Vec<u16>)>> {
+    let src = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read endpoints file {}: {}", path.display(), e))?;
+    let mut pairs = Vec::new();
+    for (idx, raw) in src.lines().enumerate() {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let lineno = idx + 1;
+        let invalid = || anyhow::anyhow!("{} line {}: expected `host port` or `host:port`, got '{}'", path.display(), lineno, line);
+        let (host, port) = match line.split_once(char::is_whitespace) {
+            Some((h, p)) => (h, p.trim()),
+            None => match line.strip_prefix('[') {
+                Some(rest) => rest.split_once("]:").ok_or_else(invalid)?,
+                // A bare IPv6 address is ambiguous without brackets
+                None if line.matches(':').count() == 1 => line.split_once(':').ok_or_else(invalid)?,
+                None => return Err(invalid()),
+            },
+        };
+        let ports = parse_ports(port).map_err(|e| anyhow::anyhow!("{} line {}: {}", path.display(), lineno, e))?;
+        pairs.push((host.to_string(), ports));
+    }
+    Ok(pairs)
+}
+
+// Merge (host, ports) pairs per host, in input order, so each host is scanned once.
+pub fn group_endpoints(pairs: Vec<(String, Vec<u16>)>, limits: &CidrLimits) -> Result<Vec<(Target, Vec<u16>)>> {
+    let mut hosts: Vec<(String, Vec<u16>)> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for (host, ports) in pairs {
+        let slot = *index.entry(host.clone()).or_insert_with(|| {
+            hosts.push((host, Vec::new()));
+            hosts.len() - 1
+        });
+        hosts[slot].1.extend(ports);
+    }
+    let mut out = Vec::new();
+    for (host, mut ports) in hosts {
+        ports.sort_unstable();
+        ports.dedup();
+        for t in parse_targets(&host, limits)? {
+            out.push((t, ports.clone()));
+        }
+    }
+    Ok(out)
+}
+
+// --all-addresses: replace each hostname target with one entry per resolved address, in resolver
+// order. Names that do not resolve are kept, so their results report the lookup error.
+pub async fn expand_addresses(
+    targets: Vec<(Target, Option<Vec<u16>>)>,
+    max_hosts: usize,
+) -> Result<Vec<(Target, Option<Vec<u16>>)>> {
+    let mut out = Vec::with_capacity(targets.len());
+    for (t, ports) in targets {
+        if IpAddr::from_str(&t.host).is_ok() {
+            out.push((t, ports));
+            continue;
+        }
+        let mut addrs: Vec<IpAddr> = Vec::new();
+        match tokio::net::lookup_host((t.host.as_str(), 0)).await {
+            Ok(resolved) => {
+                for addr in resolved {
+                    if !addrs.contains(&addr.ip()) {
+                        addrs.push(addr.ip());
+                    }
+                }
+            }
+            Err(e) => eprintln!("warning: could not resolve {}: {}", t.name, e),
+        }
+        if addrs.is_empty() {
+            out.push((t, ports));
+            continue;
+        }
+        for ip in addrs {
+            let addr = ip.to_string();
+            out.push((Target { name: t.name.clone(), host: addr.clone(), scope_id: None, resolved_addr: Some(addr) }, ports.clone()));
+        }
+    }
+    if out.len() > max_hosts {
+        anyhow::bail!("--all-addresses expands to {} hosts, more than {} (raise --max-hosts to allow it)", out.len(), max_hosts);
+    }
+    Ok(out)
+}
+
+// Scope id for an IPv6 zone: a numeric index, or an interface name resolved to its index.
+fn resolve_zone(zone: &str) -> Result<u32> {
+    if let Ok(index) = zone.parse::<u32>() {
+        return Ok(index);
+    }
+    #[cfg(unix)]
+    {
+        let name = std::ffi::CString::new(zone)
+            .map_err(|_| anyhow::anyhow!("invalid IPv6 zone '{}'", zone))?;
+        // SAFETY: `name` is a valid NUL-terminated string that outlives the call
+        let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if index != 0 {
+            return Ok(index);
+        }
+    }
+    anyhow::bail!("cannot resolve IPv6 zone '{}': no such network interface (use its numeric index)", zone)
+}
@@ -0,0 +1,99 @@
+//! Full TLS client sessions for probes that need to talk *inside* TLS (e.g. DNS-over-TLS).
+//!
+//! A scanner wants to reach whatever is listening, so certificates are never validated; handshake
+//! signatures still are, which keeps the session well-formed without trusting anyone.
+
+use crate::fingerprint::cert_common_name;
+use crate::types::TlsSession;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, ClientConnection, DigitallySignedStruct, ProtocolVersion, SignatureScheme};
+use ::ring::digest::{digest, SHA256};
+use std::net::IpAddr;
+use std::sync::{Arc, OnceLock};
+use tokio_rustls::TlsConnector;
+
+static CONNECTOR: OnceLock<TlsConnector> = OnceLock::new();
+
+/// Shared connector (TLS 1.2 and 1.3, ring provider, no certificate validation).
+pub fn connector() -> TlsConnector {
+    CONNECTOR
+        .get_or_init(|| {
+            let provider = Arc::new(ring::default_provider());
+            let config = ClientConfig::builder_with_provider(provider.clone())
+                .with_safe_default_protocol_versions()
+                .expect("ring provider supports the default protocol versions")
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(AcceptAnyCert(provider)))
+                .with_no_client_auth();
+            TlsConnector::from(Arc::new(config))
+        })
+        .clone()
+}
+
+/// SNI / verification name for `host`; IP literals are sent as IP addresses (no SNI).
+pub fn server_name(host: &str) -> Option<ServerName<'static>> {
+    match host.parse::<IpAddr>() {
+        Ok(ip) => Some(ServerName::IpAddress(ip.into())),
+        Err(_) => ServerName::try_from(host.to_string()).ok(),
+    }
+}
+
+/// Version, cipher suite and server certificate of an established session.
+pub fn session_details(conn: &ClientConnection) -> TlsSession {
+    let cert = conn.peer_certificates().and_then(|chain| chain.first());
+    TlsSession {
+        version: conn.protocol_version().map(|v| match v {
+            ProtocolVersion::TLSv1_2 => "1.2".to_string(),
+            ProtocolVersion::TLSv1_3 => "1.3".to_string(),
+            other => format!("{:?}", other),
+        }),
+        cipher: conn.negotiated_cipher_suite().map(|s| {
+            let suite = s.suite();
+            suite.as_str().map_or_else(|| format!("{:?}", suite), str::to_string)
+        }),
+        cert_subject: cert.and_then(|c| cert_common_name(c)),
+        cert_sha256: cert.map(|c| {
+            digest(&SHA256, c).as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+        }),
+    }
+}
+
+#[derive(Debug)]
+struct AcceptAnyCert(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
@@ -1,6 +1,15 @@
+use crate::proxy::HttpProxy;
+use crate::service_probes::ProbeSet;
+use encoding_rs::Encoding;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::fmt::{Display, Formatter};
-use std::time::{Duration, Instant};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
 use std::sync::Arc;
 use tokio::sync::{Semaphore, Mutex};
 
@@ -9,22 +18,121 @@ pub enum PortSpec {
     List(Vec<u16>),
 }
 
+/// Settings for scanning one target. Made with `ScanConfig::builder()`; the fields can be read and
+/// adjusted afterwards.
 #[derive(Clone, Debug)]
+#[non_exhaustive]
 pub struct ScanConfig {
+    /// Identifier of the invocation this scan belongs to, copied into every result
+    pub scan_id: String,
     pub target: String,
+    /// Host to connect to; may differ from `target` (e.g. ASCII punycode for an internationalized hostname)
+    pub host: String,
+    /// IPv6 scope id to connect with, for link-local targets given with a `%zone` suffix
+    pub scope_id: Option<u32>,
+    /// Connect to `host` only as a parsed IP address, never resolving it (`--no-dns`)
+    pub no_dns: bool,
+    /// Addresses a hostname `host` resolved to, looked up once when the scan starts and shared by
+    /// every port; None = resolve on each connect
+    pub host_addrs: Option<Arc<[IpAddr]>>,
+    /// Label for the target from `--annotate-file`, if any range matched
+    pub label: Option<String>,
+    /// Which of the hostname's addresses this scan covers, copied into results (`--all-addresses`)
+    pub resolved_addr: Option<String>,
     pub port_spec: PortSpec,
     pub concurrency: usize,
+    /// Cap on simultaneous connections to this target; None = `concurrency`
+    pub per_host_connections: Option<usize>,
     pub timeout: Duration,
+    /// Connect timeouts a target may run into before its remaining ports are reported filtered
+    /// without being tried (`--max-host-timeouts`); None = no limit
+    pub max_host_timeouts: Option<usize>,
+    /// Open ports after which the rest of a target is not scanned, nor reported
+    /// (`--stop-after-open`); None = scan every port
+    pub stop_after_open: Option<usize>,
+    /// Budget for a port's whole connect-and-probe sequence (`--total-timeout`); when it runs out
+    /// mid-probe, what was received so far is reported. None = no overall cap
+    pub total_timeout: Option<Duration>,
+    /// Scales the measured connect RTT into the banner/probe timeout (`--rtt-multiplier`)
+    pub rtt_multiplier: Option<f64>,
     pub banner_read_len: usize,
+    /// Single read or read-until-idle for banners and probe replies
+    pub read_strategy: ReadStrategy,
+    /// Display cap for stored banners (characters, or bytes of a hexdump); None = no cap
+    pub banner_max_chars: Option<usize>,
+    /// Store banners as the hex encoding of the raw bytes (forensic captures)
+    pub banner_hex: bool,
+    /// Character encoding banners are decoded with (`--banner-encoding`); None = lossy UTF-8
+    pub banner_encoding: Option<&'static Encoding>,
     /// When true, perform only passive reads (no active protocol probes)
     pub passive: bool,
+    /// When true, skip banner grabbing entirely and only report connect state
+    pub connect_only: bool,
+    /// Reconnect and identify again, up to this many times, when an open port stays silent and
+    /// unidentified (`--reprobe-unknown`); 0 = accept the first result
+    pub reprobe_unknown: u32,
+    /// Ports only connect-checked, never read from or probed (`--no-banner-ports`); sorted
+    pub no_banner_ports: Arc<[u16]>,
+    /// Guess the peer's OS family from the negotiated TCP options (`--os-hint`)
+    pub os_hint: bool,
+    /// Cap on active probes tried per open port (`--max-probes`); None = all that apply
+    pub max_probes: Option<usize>,
+    /// Sweep every port connect-only first, then reconnect to the open ones to probe them
+    pub two_phase: bool,
+    /// Grace period a new connection must survive before probing (`--verify-open`); a peer that
+    /// closes it silently is reported as open-but-hollow
+    pub verify_open: Option<Duration>,
+    /// After plaintext SMTP/IMAP/POP3/FTP detection, try upgrading the connection with STARTTLS
+    pub starttls: bool,
+    /// Log probe send/reply failures to stderr
+    pub verbose: bool,
     // Global semaphore to enforce a process-wide connection cap
     pub global_limit: Arc<Semaphore>,
     // Global rate limiter to cap connection attempts per second
     pub rate_limiter: Arc<RateLimiter>,
+    // Per-attempt delay plus jitter applied before each connection
+    pub scan_delay: Arc<ScanDelay>,
+    /// Data-driven probes tried after the built-in detectors come up empty
+    pub probes: Arc<ProbeSet>,
+    /// Verb and path used by the HTTP probe
+    pub http_method: HttpMethod,
+    pub http_path: String,
+    /// User-Agent sent by the HTTP probe
+    pub http_user_agent: String,
+    /// Host header override; derived from `host` and the port when unset
+    pub http_host: Option<String>,
+    /// Make the HTTP probe a WebSocket upgrade request and report whether it was accepted
+    pub ws_probe: bool,
+    /// Follow one HTTP redirect on the same host and report the page it leads to (`--follow-redirect`)
+    pub follow_redirect: bool,
+    /// Directory receiving raw per-port traffic captures (`--capture-dir`)
+    pub capture_dir: Option<PathBuf>,
+    /// HTTP proxy every port is reached through (`--proxy`); None = direct connections
+    pub proxy: Option<Arc<HttpProxy>>,
+    /// Local ports to connect from in rotation (`--source-ports`); None = ephemeral ports
+    pub source_ports: Option<Arc<SourcePorts>>,
+    /// Banner bytes the run may read in total (`--max-total-bytes`); None = no limit
+    pub byte_budget: Option<Arc<ByteBudget>>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// How banners and probe replies are read (see `--read-until-idle`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadStrategy {
+    /// One read: whatever the first segment(s) delivered. Fast; the default
+    Single,
+    /// Keep reading until the buffer is full or no data arrives for this long
+    UntilIdle(Duration),
+}
+
+/// Request method used by the HTTP probe
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpMethod {
+    Head,
+    Get,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Protocol {
     Http,
     Https,
@@ -33,7 +141,54 @@ pub enum Protocol {
     Tls,
     Telnet,
     Dns,
+    /// DNS over TLS (RFC 7858)
+    DnsOverTls,
+    Msrpc,
+    Smb,
+    /// AMQP message broker (e.g. RabbitMQ)
+    Amqp,
+    /// Internet Relay Chat server
+    Irc,
+    /// Finger user information (RFC 1288)
+    Finger,
+    /// Gopher menu server (RFC 1436)
+    Gopher,
+    /// Microsoft SQL Server (TDS)
+    Mssql,
+    /// Docker Engine API
+    DockerApi,
+    /// Kubernetes API server or kubelet API
+    KubeApi,
     Unknown,
+    /// A service named by a data-driven probe definition (see `--probe-file`)
+    Other(String),
+}
+
+impl Protocol {
+    /// Map a service name (as used in probe files) to a protocol, falling back to `Other`.
+    pub fn from_service_name(name: &str) -> Self {
+        match name.to_ascii_lowercase().as_str() {
+            "http" => Protocol::Http,
+            "https" => Protocol::Https,
+            "ssh" => Protocol::Ssh,
+            "smtp" => Protocol::Smtp,
+            "tls" | "ssl" => Protocol::Tls,
+            "telnet" => Protocol::Telnet,
+            "dns" | "domain" => Protocol::Dns,
+            "dns-over-tls" | "dot" | "domain-s" => Protocol::DnsOverTls,
+            "msrpc" | "epmap" => Protocol::Msrpc,
+            "smb" | "microsoft-ds" | "netbios-ssn" => Protocol::Smb,
+            "amqp" => Protocol::Amqp,
+            "irc" | "ircs" | "ircs-u" => Protocol::Irc,
+            "finger" => Protocol::Finger,
+            "gopher" => Protocol::Gopher,
+            "mssql" | "ms-sql-s" | "tds" => Protocol::Mssql,
+            "docker" | "docker-api" => Protocol::DockerApi,
+            "kubernetes" | "kube-api" | "kubelet" => Protocol::KubeApi,
+            "unknown" => Protocol::Unknown,
+            other => Protocol::Other(other.to_string()),
+        }
+    }
 }
 
 impl Display for Protocol {
@@ -46,19 +201,119 @@ impl Display for Protocol {
             Protocol::Tls => "tls",
             Protocol::Telnet => "telnet",
             Protocol::Dns => "dns",
+            Protocol::DnsOverTls => "dns-over-tls",
+            Protocol::Msrpc => "msrpc",
+            Protocol::Smb => "smb",
+            Protocol::Amqp => "amqp",
+            Protocol::Irc => "irc",
+            Protocol::Finger => "finger",
+            Protocol::Gopher => "gopher",
+            Protocol::Mssql => "mssql",
+            Protocol::DockerApi => "docker-api",
+            Protocol::KubeApi => "kube-api",
             Protocol::Unknown => "unknown",
+            Protocol::Other(name) => name,
         };
         write!(f, "{}", s)
     }
 }
 
+/// What is known about the service on an open port, beyond the coarse `protocol`. Parsed from SSH
+/// identification strings, HTTP `Server` headers and TLS ServerHellos; other services only get
+/// `name` and `raw`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceInfo {
+    /// Same as `ScanResult.protocol`
+    pub name: String,
+    /// e.g. "OpenSSH", "nginx"
+    pub product: Option<String>,
+    /// e.g. "8.2p1"; the negotiated protocol version for TLS
+    pub version: Option<String>,
+    /// Further detail: OS or distribution ("Ubuntu-4ubuntu0.5"), or the TLS cipher suite
+    pub extra: Option<String>,
+    /// TLS: the application protocol the server selected from the ClientHello's ALPN offer
+    /// (`h2` or `http/1.1`); None when it selected none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alpn: Option<String>,
+    /// The TLS session a STARTTLS upgrade led to (only with `--starttls`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsSession>,
+    /// The banner, as in `ScanResult.banner`
+    pub raw: Option<String>,
+}
+
+/// What a completed TLS handshake negotiated, and who the server said it was.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsSession {
+    /// Negotiated protocol version, e.g. "1.3"
+    pub version: Option<String>,
+    /// Negotiated cipher suite, e.g. "TLS13_AES_256_GCM_SHA384"
+    pub cipher: Option<String>,
+    /// Subject common name of the server's certificate
+    pub cert_subject: Option<String>,
+    /// SHA-256 of the server's certificate (DER), lowercase hex
+    pub cert_sha256: Option<String>,
+}
+
+impl ServiceInfo {
+    pub fn named(protocol: &Protocol) -> Self {
+        Self { name: protocol.to_string(), ..Self::default() }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ScanResult {
+    /// Identifier shared by all results of one invocation
+    pub scan_id: String,
+    /// When this port finished scanning (RFC 3339, UTC)
+    pub timestamp: String,
     pub target: String,
+    /// Inventory label of the target (see `--annotate-file`)
+    pub label: Option<String>,
+    /// Address of a hostname target that was scanned, when each address is scanned (`--all-addresses`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved_addr: Option<String>,
     pub port: u16,
+    /// `--port-group` name(s) the port belongs to, comma-separated when several
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
     pub open: bool,
+    /// Time taken by the TCP handshake, when the connect completed
+    pub latency_ms: Option<u64>,
     pub protocol: Option<Protocol>,
+    /// Deprecated: the same text is in `service.raw`; kept for existing consumers
     pub banner: Option<String>,
+    /// True when `banner` is a hexdump of binary data rather than text
+    pub banner_is_binary: bool,
+    /// Parsed product/version details of an identified service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<ServiceInfo>,
+    /// Telnet options the server negotiated, when it spoke Telnet
+    pub telnet_options: Option<Vec<String>>,
+    /// HTML page title, when the HTTP probe used GET
+    pub http_title: Option<String>,
+    /// The port accepted the connection and then immediately reset it
+    pub reset_after_connect: bool,
+    /// Whether a STARTTLS upgrade led to a TLS handshake (only set with `--starttls`)
+    pub starttls: Option<bool>,
+    /// Whether the HTTP service accepted a WebSocket upgrade (only set with `--ws-probe`)
+    pub websocket: Option<bool>,
+    /// URL of the HTTP redirect that was followed (only with `--follow-redirect`); status, server and
+    /// title then describe that page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect: Option<String>,
+    /// Coarse OS family guessed from the handshake's TCP options (only set with `--os-hint`, on Linux)
+    pub os_hint: Option<String>,
+    /// Fresh connections made to identify a silent port (`--reprobe-unknown`); absent when none were
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reprobes: Option<u32>,
+    /// Why the port is in its state, nmap `--reason` style: "syn-ack", "reset", "conn-refused",
+    /// "no-response", "host-unreach", "net-unreach", "error", or "open-but-hollow" (see `--verify-open`)
+    pub reason: String,
+    /// How this result differs from the `--baseline` scan: "new-open", "now-closed", "changed-banner"
+    /// or "unchanged"; absent without a baseline
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub change: Option<String>,
     pub error: Option<String>,
 }
 
@@ -113,3 +368,165 @@ impl RateLimiter {
         }
     }
 }
+
+// RNG seeded from `--seed` when given (reproducible runs), otherwise from OS entropy
+fn seeded_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(s) => StdRng::seed_from_u64(s),
+        None => StdRng::from_entropy(),
+    }
+}
+
+/// Per-attempt pacing: a base delay plus uniform random jitter before each connection attempt.
+/// Independent of the global `RateLimiter`; seeding the RNG makes the jitter reproducible.
+#[derive(Debug)]
+pub struct ScanDelay {
+    base: Duration,
+    max_jitter: Duration,
+    rng: std::sync::Mutex<StdRng>,
+}
+
+impl ScanDelay {
+    pub fn new(base: Duration, max_jitter: Duration, seed: Option<u64>) -> Self {
+        Self { base, max_jitter, rng: std::sync::Mutex::new(seeded_rng(seed)) }
+    }
+
+    /// Sleep for the base delay plus a random jitter in `0..=max_jitter`.
+    pub async fn wait(&self) {
+        if self.base.is_zero() && self.max_jitter.is_zero() {
+            return;
+        }
+        let jitter = if self.max_jitter.is_zero() {
+            Duration::ZERO
+        } else {
+            // Never held across an await point
+            let mut rng = self.rng.lock().unwrap_or_else(|e| e.into_inner());
+            rng.gen_range(Duration::ZERO..=self.max_jitter)
+        };
+        tokio::time::sleep(self.base + jitter).await;
+    }
+}
+
+/// Cap on the banner and probe-reply bytes read by a whole run (`--max-total-bytes`), shared by
+/// every port of every target. Reads in progress finish, so the total can overshoot by a little.
+#[derive(Debug)]
+pub struct ByteBudget {
+    limit: u64,
+    used: AtomicU64,
+}
+
+impl ByteBudget {
+    pub fn new(limit: u64) -> Self {
+        Self { limit, used: AtomicU64::new(0) }
+    }
+
+    pub fn record(&self, bytes: usize) {
+        self.used.fetch_add(bytes as u64, AtomicOrdering::Relaxed);
+    }
+
+    /// Bytes read so far.
+    pub fn used(&self) -> u64 {
+        self.used.load(AtomicOrdering::Relaxed)
+    }
+
+    pub fn exhausted(&self) -> bool {
+        self.used() >= self.limit
+    }
+}
+
+/// Local ports connections are bound to (`--source-ports`), handed out least recently used first so
+/// consecutive connections leave from different ports. A port is in use by one connection at a
+/// time; when all are taken, the next connection waits for one to be returned.
+#[derive(Debug)]
+pub struct SourcePorts {
+    free: std::sync::Mutex<VecDeque<u16>>,
+    available: Semaphore,
+    size: usize,
+}
+
+/// A port taken from `SourcePorts`; it goes back to the end of the rotation when dropped.
+#[derive(Debug)]
+pub(crate) struct SourcePortLease {
+    pool: Arc<SourcePorts>,
+    port: u16,
+}
+
+impl SourcePorts {
+    /// Pool of `ports`, rotated in the given order; duplicates are dropped.
+    pub fn new(ports: impl IntoIterator<Item = u16>) -> Self {
+        let mut free = VecDeque::new();
+        for port in ports {
+            if !free.contains(&port) {
+                free.push_back(port);
+            }
+        }
+        Self { available: Semaphore::new(free.len()), size: free.len(), free: std::sync::Mutex::new(free) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The least recently used free port, waiting until one is returned if all are in use.
+    pub(crate) async fn lease(self: &Arc<Self>) -> SourcePortLease {
+        self.available.acquire().await.expect("source port semaphore not closed").forget();
+        let port = self
+            .free
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop_front()
+            .expect("a permit stands for a free port");
+        SourcePortLease { pool: self.clone(), port }
+    }
+}
+
+impl SourcePortLease {
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl Drop for SourcePortLease {
+    fn drop(&mut self) {
+        self.pool.free.lock().unwrap_or_else(|e| e.into_inner()).push_back(self.port);
+        self.pool.available.add_permits(1);
+    }
+}
+
+/// Identifier shared by every result of one invocation (nanosecond start time + pid, hex).
+pub(crate) fn new_scan_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    format!("{:x}-{:x}", nanos, std::process::id())
+}
+
+/// Format a timestamp as RFC 3339 UTC with millisecond precision (e.g. 2024-05-01T12:00:00.000Z).
+pub fn rfc3339_utc(t: SystemTime) -> String {
+    let d = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = d.as_secs();
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    // Civil-from-days conversion (proleptic Gregorian calendar)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60,
+        d.subsec_millis()
+    )
+}
@@ -0,0 +1,125 @@
+//! Minimal nmap-style XML report (`--xml`), written host by host as targets complete.
+//!
+//! Not byte-identical to nmap, but uses the same element layout (`nmaprun` / `host` / `ports` /
+//! `port` / `state` / `service`) so tools that ingest nmap XML can read it.
+
+use ospine::ScanResult;
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Longest banner excerpt carried in a `product` attribute
+const MAX_PRODUCT_CHARS: usize = 80;
+
+pub struct XmlReport {
+    out: BufWriter<File>,
+}
+
+impl XmlReport {
+    pub fn create(path: &Path, args: &str, version: &str) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("failed to create XML report {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(
+            out,
+            "<nmaprun scanner=\"ospine\" args=\"{}\" start=\"{}\" version=\"{}\" xmloutputversion=\"1.05\">",
+            escape(args),
+            unix_now(),
+            escape(version)
+        )?;
+        Ok(Self { out })
+    }
+
+    /// One `<host>` element for the results of a single target.
+    pub fn write_host(&mut self, results: &[ScanResult]) -> Result<()> {
+        let Some(first) = results.first() else {
+            return Ok(());
+        };
+        let out = &mut self.out;
+        writeln!(out, "<host>")?;
+        writeln!(out, "<status state=\"up\" reason=\"user-set\"/>")?;
+        // A hostname scanned per address (--all-addresses) gets both elements
+        if let Ok(ip) = IpAddr::from_str(first.resolved_addr.as_deref().unwrap_or(&first.target)) {
+            let kind = if ip.is_ipv4() { "ipv4" } else { "ipv6" };
+            writeln!(out, "<address addr=\"{}\" addrtype=\"{}\"/>", ip, kind)?;
+        }
+        if IpAddr::from_str(&first.target).is_err() {
+            writeln!(out, "<hostnames><hostname name=\"{}\" type=\"user\"/></hostnames>", escape(&first.target))?;
+        }
+        writeln!(out, "<ports>")?;
+        for r in results {
+            // Only a refused connection is known closed; timeouts and unreachable hosts are filtered
+            let state = match (r.open, r.reason.as_str()) {
+                (true, _) => "open",
+                (false, "conn-refused") => "closed",
+                (false, _) => "filtered",
+            };
+            write!(
+                out,
+                "<port protocol=\"tcp\" portid=\"{}\"><state state=\"{}\" reason=\"{}\"/>",
+                r.port,
+                state,
+                escape(&r.reason)
+            )?;
+            if let Some(proto) = &r.protocol {
+                write!(out, "<service name=\"{}\"", escape(&proto.to_string()))?;
+                let service = r.service.as_ref();
+                let parsed_product = service.and_then(|s| s.product.clone());
+                let product = parsed_product
+                    .or_else(|| r.banner.as_deref().filter(|_| !r.banner_is_binary).and_then(product_line));
+                if let Some(product) = product {
+                    write!(out, " product=\"{}\"", escape(&product))?;
+                }
+                if let Some(version) = service.and_then(|s| s.version.as_deref()) {
+                    write!(out, " version=\"{}\"", escape(version))?;
+                }
+                if let Some(extra) = service.and_then(|s| s.extra.as_deref()) {
+                    write!(out, " extrainfo=\"{}\"", escape(extra))?;
+                }
+                write!(out, " method=\"probed\" conf=\"10\"/>")?;
+            }
+            writeln!(out, "</port>")?;
+        }
+        writeln!(out, "</ports>")?;
+        writeln!(out, "</host>")?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        writeln!(self.out, "<runstats><finished time=\"{}\"/></runstats>", unix_now())?;
+        writeln!(self.out, "</nmaprun>")?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+// First non-empty banner line, shortened for use as a product string.
+fn product_line(banner: &str) -> Option<String> {
+    let line = banner.lines().map(str::trim).find(|l| !l.is_empty())?;
+    Some(line.chars().take(MAX_PRODUCT_CHARS).collect())
+}
+
+// Escape for attribute values; control characters XML 1.0 cannot carry become spaces.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c if c.is_control() => out.push(' '),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}